@@ -1,21 +1,112 @@
 from __future__ import annotations
 
-from typing import Any, Iterable, Mapping, Optional
+import asyncio
+import os
+from typing import Any, Callable, Iterable, Iterator, Mapping, Optional
 
 try:
     from ._pnl_rs import (  # type: ignore[attr-defined]
+        aggregate_portfolio as _aggregate_portfolio,
+        bootstrap_forward_pnl as _bootstrap_forward_pnl,
+        Cancelled,
+        CancelToken,
+        compute_positions as _compute_positions,
+        estimate_payload as _estimate_payload,
+        implementation_shortfall as _implementation_shortfall,
+        inventory_skew as _inventory_skew,
+        iter_orders_and_fills as _iter_orders_and_fills,
+        join_post_only as _join_post_only,
+        load_config as _load_config,
+        mark_to_market as _mark_to_market,
+        memory_usage as _memory_usage,
+        order_timeline as _order_timeline,
         process_orders_and_fills as _process_orders_and_fills,
+        quote_uptime as _quote_uptime,
+        SeenIdStore,
+        SpreadAdvisor,
+        register_field_aliases as _register_field_aliases,
+        register_quote_aliases as _register_quote_aliases,
+        set_input_limits as _set_input_limits,
+        set_memory_debug as _set_memory_debug,
+        simulate_stops as _simulate_stops,
+        summarise_accounts as _summarise_accounts,
+        summarise_fills as _summarise_fills,
+        summarise_from_orders_and_fills as _summarise_from_orders_and_fills,
         summarise_trades as _summarise_trades,
+        validate_payload as _validate_payload,
     )
 except ModuleNotFoundError:  # pragma: no cover - native module optional
     _summarise_trades = None
     _process_orders_and_fills = None
+    _mark_to_market = None
+    _order_timeline = None
+    _compute_positions = None
+    _aggregate_portfolio = None
+    _summarise_accounts = None
+    _join_post_only = None
+    _summarise_from_orders_and_fills = None
+    _summarise_fills = None
+    _register_field_aliases = None
+    _register_quote_aliases = None
+    _validate_payload = None
+    SeenIdStore = None  # type: ignore[assignment,misc]
+    SpreadAdvisor = None  # type: ignore[assignment,misc]
+    CancelToken = None  # type: ignore[assignment,misc]
+    Cancelled = None  # type: ignore[assignment,misc]
+    _iter_orders_and_fills = None
+    _simulate_stops = None
+    _implementation_shortfall = None
+    _quote_uptime = None
+    _inventory_skew = None
+    _load_config = None
+    _set_input_limits = None
+    _estimate_payload = None
+    _set_memory_debug = None
+    _memory_usage = None
+    _bootstrap_forward_pnl = None
 
 
 def native_available() -> bool:
     return _summarise_trades is not None
 
 
+def _optional_list(values: Optional[Iterable[Any]]) -> Optional[list[Any]]:
+    return list(values) if values is not None else None
+
+
+def _payload(records: Any) -> Any:
+    return dict(records) if isinstance(records, Mapping) else list(records)
+
+
+_memory_debug = False
+
+
+def set_memory_debug(enabled: bool) -> bool:
+    global _memory_debug
+    if _set_memory_debug is None or not _memory_usage()["available"]:
+        return False
+    _set_memory_debug(enabled)
+    _memory_debug = enabled
+    return True
+
+
+# With memory debug on, adds `native_memory` to a result: peak and retained
+# bytes of the native structures relative to the start of the call.
+def _measured(call: Callable[[], Any]) -> Any:
+    if not _memory_debug:
+        return call()
+    before = _memory_usage(reset_peak=True)
+    result = call()
+    after = _memory_usage()
+    if isinstance(result, dict):
+        result["native_memory"] = {
+            "peak_bytes": after["peak_bytes"] - before["current_bytes"],
+            "retained_bytes": after["current_bytes"] - before["current_bytes"],
+            "allocations": after["allocations"] - before["allocations"],
+        }
+    return result
+
+
 def summarise_trades(
     trades: Iterable[Mapping[str, Any]],
     intervals: Iterable[Mapping[str, Any]],
@@ -24,25 +115,640 @@ def summarise_trades(
     cutoff_timestamp_us: int,
     maker_fee_rate: str,
     taker_fee_rate: str,
+    fixed_point_decimals: Optional[tuple[int, int] | Mapping[str, tuple[int, int]]] = None,
+    include_position_series: bool = False,
+    include_base_pnl: bool = False,
+    as_of_timestamps_us: Optional[Iterable[int]] = None,
+    funding_rates: Optional[Iterable[Mapping[str, Any]]] = None,
+    borrow_costs: Optional[Iterable[Mapping[str, Any]]] = None,
+    borrow_rates: Optional[Iterable[Mapping[str, Any]]] = None,
+    comparisons: Optional[Iterable[Mapping[str, Any]]] = None,
+    include_match_ledger: bool = False,
+    splits: Optional[Iterable[Mapping[str, Any]]] = None,
+    include_rolling_volume: bool = False,
+    float_output: bool = False,
+    include_products: Optional[Iterable[str]] = None,
+    exclude_products: Optional[Iterable[str]] = None,
+    mid_prices: Optional[Iterable[Mapping[str, Any]]] = None,
+    mixed_products: str = "error",
+    inverse_products: Optional[Iterable[str]] = None,
+    oversell: str = "allow_short",
+    fee_schedule: Optional[Iterable[Mapping[str, Any]]] = None,
+    min_fee: Optional[str] = None,
+    max_fee: Optional[str] = None,
+    lot_method: str = "fifo",
+    scenarios: Optional[Iterable[Mapping[str, Any]]] = None,
+    quote_currency: Optional[str] = None,
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
 ) -> Optional[dict[str, Any]]:
     if _summarise_trades is None:
         return None
-    return _summarise_trades(
+    return _measured(lambda: _summarise_trades(
         list(trades),
         list(intervals),
         now_timestamp_us,
         cutoff_timestamp_us,
         maker_fee_rate,
         taker_fee_rate,
-    )
+        fixed_point_decimals=dict(fixed_point_decimals) if isinstance(fixed_point_decimals, Mapping) else fixed_point_decimals,
+        include_position_series=include_position_series,
+        include_base_pnl=include_base_pnl,
+        as_of_timestamps_us=_optional_list(as_of_timestamps_us),
+        funding_rates=_optional_list(funding_rates),
+        borrow_costs=_optional_list(borrow_costs),
+        borrow_rates=_optional_list(borrow_rates),
+        comparisons=_optional_list(comparisons),
+        include_match_ledger=include_match_ledger,
+        splits=_optional_list(splits),
+        include_rolling_volume=include_rolling_volume,
+        float_output=float_output,
+        include_products=_optional_list(include_products),
+        exclude_products=_optional_list(exclude_products),
+        mid_prices=_optional_list(mid_prices),
+        mixed_products=mixed_products,
+        inverse_products=_optional_list(inverse_products),
+        oversell=oversell,
+        fee_schedule=_optional_list(fee_schedule),
+        min_fee=min_fee,
+        max_fee=max_fee,
+        lot_method=lot_method,
+        scenarios=_optional_list(scenarios),
+        quote_currency=quote_currency,
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
+
+
+# The native calls release the GIL for their heavy parts, so running them on
+# a worker thread keeps the event loop responsive.
+async def summarise_trades_async(
+    trades: Iterable[Mapping[str, Any]],
+    intervals: Iterable[Mapping[str, Any]],
+    **kwargs: Any,
+) -> Optional[dict[str, Any]]:
+    if _summarise_trades is None:
+        return None
+    return await asyncio.to_thread(summarise_trades, list(trades), list(intervals), **kwargs)
+
+
+def summarise_accounts(
+    accounts: Mapping[str, Iterable[Mapping[str, Any]]],
+    intervals: Iterable[Mapping[str, Any]],
+    *,
+    now_timestamp_us: int,
+    cutoff_timestamp_us: int,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    float_output: bool = False,
+    mixed_products: str = "error",
+    oversell: str = "allow_short",
+    fee_schedule: Optional[Iterable[Mapping[str, Any]]] = None,
+    min_fee: Optional[str] = None,
+    max_fee: Optional[str] = None,
+    quote_currency: Optional[str] = None,
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
+) -> Optional[dict[str, dict[str, Any]]]:
+    if _summarise_accounts is None:
+        return None
+    return _measured(lambda: _summarise_accounts(
+        {account_id: list(trades) for account_id, trades in accounts.items()},
+        list(intervals),
+        now_timestamp_us,
+        cutoff_timestamp_us,
+        maker_fee_rate,
+        taker_fee_rate,
+        float_output=float_output,
+        mixed_products=mixed_products,
+        oversell=oversell,
+        fee_schedule=_optional_list(fee_schedule),
+        min_fee=min_fee,
+        max_fee=max_fee,
+        quote_currency=quote_currency,
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
 
 
 def process_orders_and_fills(
-    orders: Iterable[Mapping[str, Any]],
-    fills: Iterable[Mapping[str, Any]],
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
     *,
     product_id: str,
+    working_statuses: Optional[Iterable[str]] = None,
+    submitted_time_sources: Optional[Iterable[str]] = None,
+    completed_time_sources: Optional[Iterable[str]] = None,
+    sort_records: bool = False,
+    now_timestamp_us: Optional[int] = None,
+    fields: Optional[Iterable[str]] = None,
+    dust_size: Optional[str] = None,
+    duplicate_orders: str = "keep_all",
+    seen_ids: Any = None,
+    arrival_prices: Optional[Mapping[str, str]] = None,
+    arrival_price_series: Optional[Iterable[Mapping[str, Any]]] = None,
+    timestamp_precision: str = "millis",
+    include_raw_timestamps: bool = False,
+    columnar: bool = False,
+    quote_currency: Optional[str] = None,
+    inverse_products: Optional[Iterable[str]] = None,
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
 ) -> Optional[dict[str, Any]]:
     if _process_orders_and_fills is None:
         return None
-    return _process_orders_and_fills(list(orders), list(fills), product_id)
+    return _measured(lambda: _process_orders_and_fills(
+        _payload(orders),
+        _payload(fills),
+        product_id,
+        working_statuses=_optional_list(working_statuses),
+        submitted_time_sources=_optional_list(submitted_time_sources),
+        completed_time_sources=_optional_list(completed_time_sources),
+        sort_records=sort_records,
+        now_timestamp_us=now_timestamp_us,
+        fields=_optional_list(fields),
+        dust_size=dust_size,
+        duplicate_orders=duplicate_orders,
+        seen_ids=seen_ids,
+        arrival_prices=dict(arrival_prices) if arrival_prices is not None else None,
+        arrival_price_series=_optional_list(arrival_price_series),
+        timestamp_precision=timestamp_precision,
+        include_raw_timestamps=include_raw_timestamps,
+        columnar=columnar,
+        quote_currency=quote_currency,
+        inverse_products=_optional_list(inverse_products),
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
+
+
+async def process_orders_and_fills_async(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    **kwargs: Any,
+) -> Optional[dict[str, Any]]:
+    if _process_orders_and_fills is None:
+        return None
+    return await asyncio.to_thread(process_orders_and_fills, _payload(orders), _payload(fills), **kwargs)
+
+
+def iter_orders_and_fills(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    *,
+    product_id: str,
+    chunk_size: int = 5000,
+    working_statuses: Optional[Iterable[str]] = None,
+    submitted_time_sources: Optional[Iterable[str]] = None,
+    completed_time_sources: Optional[Iterable[str]] = None,
+    now_timestamp_us: Optional[int] = None,
+    fields: Optional[Iterable[str]] = None,
+    dust_size: Optional[str] = None,
+    duplicate_orders: str = "keep_all",
+    seen_ids: Any = None,
+    timestamp_precision: str = "millis",
+    include_raw_timestamps: bool = False,
+    columnar: bool = False,
+    quote_currency: Optional[str] = None,
+    inverse_products: Optional[Iterable[str]] = None,
+) -> Optional[Iterator[dict[str, Any]]]:
+    if _iter_orders_and_fills is None:
+        return None
+    return _iter_orders_and_fills(
+        _payload(orders),
+        _payload(fills),
+        product_id,
+        chunk_size=chunk_size,
+        working_statuses=_optional_list(working_statuses),
+        submitted_time_sources=_optional_list(submitted_time_sources),
+        completed_time_sources=_optional_list(completed_time_sources),
+        now_timestamp_us=now_timestamp_us,
+        fields=_optional_list(fields),
+        dust_size=dust_size,
+        duplicate_orders=duplicate_orders,
+        seen_ids=seen_ids,
+        timestamp_precision=timestamp_precision,
+        include_raw_timestamps=include_raw_timestamps,
+        columnar=columnar,
+        quote_currency=quote_currency,
+        inverse_products=_optional_list(inverse_products),
+    )
+
+
+def mark_to_market(
+    trades: Iterable[Mapping[str, Any]],
+    snapshots: Iterable[Mapping[str, Any]],
+    *,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    cutoff_timestamp_us: Optional[int] = None,
+    splits: Optional[Iterable[Mapping[str, Any]]] = None,
+    inverse_products: Optional[Iterable[str]] = None,
+    oversell: str = "allow_short",
+    mixed_products: str = "error",
+    fee_schedule: Optional[Iterable[Mapping[str, Any]]] = None,
+    min_fee: Optional[str] = None,
+    max_fee: Optional[str] = None,
+) -> Optional[list[dict[str, Any]]]:
+    if _mark_to_market is None:
+        return None
+    return _mark_to_market(
+        list(trades),
+        list(snapshots),
+        maker_fee_rate,
+        taker_fee_rate,
+        cutoff_timestamp_us=cutoff_timestamp_us,
+        splits=_optional_list(splits),
+        inverse_products=_optional_list(inverse_products),
+        oversell=oversell,
+        mixed_products=mixed_products,
+        fee_schedule=_optional_list(fee_schedule),
+        min_fee=min_fee,
+        max_fee=max_fee,
+    )
+
+
+def bootstrap_forward_pnl(
+    trades: Iterable[Mapping[str, Any]],
+    *,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    horizon_days: int,
+    paths: int = 10_000,
+    percentiles: Optional[Iterable[float]] = None,
+    seed: int = 0,
+    ruin_loss: Optional[str] = None,
+    cutoff_timestamp_us: Optional[int] = None,
+    inverse_products: Optional[Iterable[str]] = None,
+    mixed_products: str = "error",
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
+) -> Optional[dict[str, Any]]:
+    if _bootstrap_forward_pnl is None:
+        return None
+    return _measured(lambda: _bootstrap_forward_pnl(
+        list(trades),
+        maker_fee_rate,
+        taker_fee_rate,
+        horizon_days,
+        paths=paths,
+        percentiles=_optional_list(percentiles),
+        seed=seed,
+        ruin_loss=ruin_loss,
+        cutoff_timestamp_us=cutoff_timestamp_us,
+        inverse_products=_optional_list(inverse_products),
+        mixed_products=mixed_products,
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
+
+
+def order_timeline(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    *,
+    working_statuses: Optional[Iterable[str]] = None,
+    timestamp_precision: str = "millis",
+) -> Optional[list[dict[str, Any]]]:
+    if _order_timeline is None:
+        return None
+    return _order_timeline(
+        _payload(orders),
+        _payload(fills),
+        working_statuses=_optional_list(working_statuses),
+        timestamp_precision=timestamp_precision,
+    )
+
+
+def compute_positions(
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    *,
+    product_id: str,
+) -> Optional[list[dict[str, Any]]]:
+    if _compute_positions is None:
+        return None
+    return _compute_positions(_payload(fills), product_id)
+
+
+def aggregate_portfolio(
+    products: Iterable[Mapping[str, Any]],
+    intervals: Iterable[Mapping[str, Any]],
+    *,
+    now_timestamp_us: int,
+    cutoff_timestamp_us: int,
+    conversion_prices: Mapping[str, Any],
+    reporting_currency: str = "USD",
+    float_output: bool = False,
+) -> Optional[dict[str, Any]]:
+    if _aggregate_portfolio is None:
+        return None
+    return _aggregate_portfolio(
+        [
+            {**product, "trades": list(product["trades"])}
+            for product in products
+        ],
+        list(intervals),
+        now_timestamp_us,
+        cutoff_timestamp_us,
+        {
+            currency: price if isinstance(price, str) else list(price)
+            for currency, price in conversion_prices.items()
+        },
+        reporting_currency=reporting_currency,
+        float_output=float_output,
+    )
+
+
+def join_post_only(
+    trades: Iterable[Mapping[str, Any]],
+    executed_records: Iterable[Mapping[str, Any]],
+) -> Optional[list[dict[str, Any]]]:
+    if _join_post_only is None:
+        return None
+    return _join_post_only(
+        [dict(trade) for trade in trades],
+        [dict(record) for record in executed_records],
+    )
+
+
+def summarise_from_orders_and_fills(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    intervals: Iterable[Mapping[str, Any]],
+    *,
+    product_id: str,
+    now_timestamp_us: int,
+    cutoff_timestamp_us: int,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    float_output: bool = False,
+    include_bracket_groups: bool = False,
+    arrival_prices: Optional[Mapping[str, str]] = None,
+    arrival_price_series: Optional[Iterable[Mapping[str, Any]]] = None,
+    include_order_pnl: bool = False,
+    mixed_products: str = "warn",
+    inverse_products: Optional[Iterable[str]] = None,
+    oversell: str = "allow_short",
+    fee_schedule: Optional[Iterable[Mapping[str, Any]]] = None,
+    min_fee: Optional[str] = None,
+    max_fee: Optional[str] = None,
+    quote_currency: Optional[str] = None,
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
+) -> Optional[dict[str, Any]]:
+    if _summarise_from_orders_and_fills is None:
+        return None
+    return _measured(lambda: _summarise_from_orders_and_fills(
+        _payload(orders),
+        _payload(fills),
+        list(intervals),
+        now_timestamp_us,
+        cutoff_timestamp_us,
+        maker_fee_rate,
+        taker_fee_rate,
+        product_id,
+        float_output=float_output,
+        include_bracket_groups=include_bracket_groups,
+        arrival_prices=dict(arrival_prices) if arrival_prices is not None else None,
+        arrival_price_series=_optional_list(arrival_price_series),
+        include_order_pnl=include_order_pnl,
+        mixed_products=mixed_products,
+        inverse_products=_optional_list(inverse_products),
+        oversell=oversell,
+        fee_schedule=_optional_list(fee_schedule),
+        min_fee=min_fee,
+        max_fee=max_fee,
+        quote_currency=quote_currency,
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
+
+
+def summarise_fills(
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    intervals: Iterable[Mapping[str, Any]],
+    *,
+    product_id: str,
+    now_timestamp_us: int,
+    cutoff_timestamp_us: int,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    orders: Optional[Iterable[Mapping[str, Any]]] = None,
+    float_output: bool = False,
+    maker_rules: Optional[Iterable[Mapping[str, Any]]] = None,
+    classify_maker: Optional[Callable[[dict[str, Any]], Optional[bool]]] = None,
+    seen_ids: Any = None,
+    mixed_products: str = "warn",
+    inverse_products: Optional[Iterable[str]] = None,
+    oversell: str = "allow_short",
+    fee_schedule: Optional[Iterable[Mapping[str, Any]]] = None,
+    min_fee: Optional[str] = None,
+    max_fee: Optional[str] = None,
+    quote_currency: Optional[str] = None,
+    cancel_token: Any = None,
+    progress: Optional[Callable[[str, int, int], Any]] = None,
+    progress_every: int = 10_000,
+) -> Optional[dict[str, Any]]:
+    if _summarise_fills is None:
+        return None
+    return _measured(lambda: _summarise_fills(
+        _payload(fills),
+        list(intervals),
+        now_timestamp_us,
+        cutoff_timestamp_us,
+        maker_fee_rate,
+        taker_fee_rate,
+        product_id,
+        orders=_payload(orders) if orders is not None else None,
+        float_output=float_output,
+        maker_rules=_optional_list(maker_rules),
+        classify_maker=classify_maker,
+        seen_ids=seen_ids,
+        mixed_products=mixed_products,
+        inverse_products=_optional_list(inverse_products),
+        oversell=oversell,
+        fee_schedule=_optional_list(fee_schedule),
+        min_fee=min_fee,
+        max_fee=max_fee,
+        quote_currency=quote_currency,
+        cancel_token=cancel_token,
+        progress=progress,
+        progress_every=progress_every,
+    ))
+
+
+def register_field_aliases(
+    record_type: str,
+    aliases: Mapping[str, str],
+    *,
+    replace: bool = False,
+) -> bool:
+    if _register_field_aliases is None:
+        return False
+    _register_field_aliases(record_type, dict(aliases), replace=replace)
+    return True
+
+
+def register_quote_aliases(aliases: Mapping[str, str], *, replace: bool = False) -> bool:
+    if _register_quote_aliases is None:
+        return False
+    _register_quote_aliases(dict(aliases), replace=replace)
+    return True
+
+
+def set_input_limits(
+    *,
+    max_trades: Optional[int] = None,
+    max_orders: Optional[int] = None,
+    max_fills: Optional[int] = None,
+) -> bool:
+    if _set_input_limits is None:
+        return False
+    _set_input_limits(max_trades=max_trades, max_orders=max_orders, max_fills=max_fills)
+    return True
+
+
+def estimate_payload(
+    *,
+    orders: Optional[Iterable[Mapping[str, Any]] | Mapping[str, Any]] = None,
+    fills: Optional[Iterable[Mapping[str, Any]] | Mapping[str, Any]] = None,
+    trades: Optional[Iterable[Mapping[str, Any]]] = None,
+) -> Optional[dict[str, Any]]:
+    if _estimate_payload is None:
+        return None
+    return _estimate_payload(
+        orders=_payload(orders) if orders is not None else None,
+        fills=_payload(fills) if fills is not None else None,
+        trades=_optional_list(trades),
+    )
+
+
+def validate_payload(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+) -> Optional[dict[str, Any]]:
+    if _validate_payload is None:
+        return None
+    return _validate_payload(_payload(orders), _payload(fills))
+
+
+def simulate_stops(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    prices: Iterable[Mapping[str, Any]],
+    *,
+    product_id: str,
+    working_statuses: Optional[Iterable[str]] = None,
+    timestamp_precision: str = "millis",
+) -> Optional[list[dict[str, Any]]]:
+    if _simulate_stops is None:
+        return None
+    return _simulate_stops(
+        _payload(orders),
+        _payload(fills),
+        list(prices),
+        product_id,
+        working_statuses=_optional_list(working_statuses),
+        timestamp_precision=timestamp_precision,
+    )
+
+
+def implementation_shortfall(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    *,
+    product_id: str,
+    maker_fee_rate: str,
+    taker_fee_rate: str,
+    arrival_prices: Optional[Mapping[str, str]] = None,
+    arrival_price_series: Optional[Iterable[Mapping[str, Any]]] = None,
+    now_timestamp_us: Optional[int] = None,
+    float_output: bool = False,
+) -> Optional[list[dict[str, Any]]]:
+    if _implementation_shortfall is None:
+        return None
+    return _implementation_shortfall(
+        _payload(orders),
+        _payload(fills),
+        product_id,
+        maker_fee_rate,
+        taker_fee_rate,
+        arrival_prices=dict(arrival_prices) if arrival_prices is not None else None,
+        arrival_price_series=_optional_list(arrival_price_series),
+        now_timestamp_us=now_timestamp_us,
+        float_output=float_output,
+    )
+
+
+def quote_uptime(
+    orders: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    fills: Iterable[Mapping[str, Any]] | Mapping[str, Any],
+    mid_prices: Iterable[Mapping[str, Any]],
+    intervals: Iterable[Mapping[str, Any]],
+    *,
+    product_id: str,
+    now_timestamp_us: int,
+    cutoff_timestamp_us: int,
+    max_distance_bps: str = "50",
+    working_statuses: Optional[Iterable[str]] = None,
+) -> Optional[list[dict[str, Any]]]:
+    if _quote_uptime is None:
+        return None
+    return _quote_uptime(
+        _payload(orders),
+        _payload(fills),
+        list(mid_prices),
+        list(intervals),
+        now_timestamp_us,
+        cutoff_timestamp_us,
+        product_id,
+        max_distance_bps=max_distance_bps,
+        working_statuses=_optional_list(working_statuses),
+    )
+
+
+def inventory_skew(
+    trades: Iterable[Mapping[str, Any]],
+    *,
+    max_position: str,
+    max_skew_bps: str = "10",
+    base_quote_size: Optional[str] = None,
+    mid_price: Optional[str] = None,
+    product_id: Optional[str] = None,
+    mixed_products: str = "error",
+    inverse_products: Optional[Iterable[str]] = None,
+    oversell: str = "allow_short",
+) -> Optional[dict[str, Any]]:
+    if _inventory_skew is None:
+        return None
+    return _inventory_skew(
+        list(trades),
+        max_position,
+        max_skew_bps=max_skew_bps,
+        base_quote_size=base_quote_size,
+        mid_price=mid_price,
+        product_id=product_id,
+        mixed_products=mixed_products,
+        inverse_products=_optional_list(inverse_products),
+        oversell=oversell,
+    )
+
+
+def load_config(
+    path: str | os.PathLike[str],
+    *,
+    format: Optional[str] = None,
+) -> Optional[dict[str, Any]]:
+    if _load_config is None:
+        return None
+    return _load_config(os.fspath(path), format=format)
@@ -1,67 +1,408 @@
-use std::collections::{HashMap, VecDeque};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
 use std::str::FromStr;
+#[cfg(feature = "memory-debug")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
 
-use chrono::{DateTime, Duration, NaiveDateTime, SecondsFormat, Utc};
-use pyo3::exceptions::PyValueError;
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, SecondsFormat, Utc};
+use pyo3::exceptions::{PyKeyError, PyOSError, PyTypeError, PyUserWarning, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyAny, PyDict, PyList};
+use pyo3::types::{PyAny, PyBytes, PyDict, PyList, PyString, PyTuple};
+use rayon::prelude::*;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use rustc_hash::{FxHashMap, FxHashSet};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::Value;
+use smallvec::SmallVec;
 
-#[derive(FromPyObject)]
+/// Side, price, size and commission are parsed straight from the borrowed
+/// Python string (or bytes) rather than copied into a `String` first. Side
+/// and price errors are held until `parse_trades` keeps the trade, so rows
+/// it would skip anyway (filtered product, zero size) cannot fail the call.
 struct TradeInput {
     timestamp_us: i64,
-    side: String,
-    price: String,
-    size: String,
+    side: PyResult<Side>,
+    price: PyResult<Decimal>,
+    size: Decimal,
     post_only: bool,
+    product_id: Option<String>,
+    leg_group: Option<String>,
+    trade_id: Option<String>,
+    commission: Option<Decimal>,
+    commission_currency: Option<String>,
+}
+
+impl<'py> FromPyObject<'py> for TradeInput {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let commission = match obj.get_item("commission") {
+            Ok(value) if value.is_none() => None,
+            Ok(value) => with_text(&value, "commission", |text| {
+                (!is_null_sentinel(text)).then(|| parse_decimal(text, "commission")).transpose()
+            })?,
+            Err(err) if err.is_instance_of::<PyKeyError>(obj.py()) => None,
+            Err(err) => return Err(err),
+        };
+        Ok(TradeInput {
+            timestamp_us: obj.get_item("timestamp_us")?.extract()?,
+            side: obj.get_item("side").and_then(|side| with_text(&side, "side", |text| Side::try_from(text))),
+            price: obj.get_item("price").and_then(|price| with_text(&price, "price", |text| parse_decimal(text, "price"))),
+            size: with_text(&obj.get_item("size")?, "size", |text| parse_decimal(text, "size"))?,
+            post_only: obj.get_item("post_only")?.extract()?,
+            product_id: optional_item(obj, "product_id")?,
+            leg_group: optional_item(obj, "leg_group")?,
+            trade_id: optional_item(obj, "trade_id")?,
+            commission,
+            commission_currency: optional_item(obj, "commission_currency")?,
+        })
+    }
+}
+
+/// A `trades` argument. The sequence's `len()` is checked against
+/// `max_trades` before any trade is extracted.
+struct TradeInputs(Vec<TradeInput>);
+
+impl<'py> FromPyObject<'py> for TradeInputs {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        check_input_size("trades", obj.len()?)?;
+        Ok(TradeInputs(obj.extract()?))
+    }
+}
+
+/// Runs `parse` on a `str` or UTF-8 `bytes` value without allocating.
+fn with_text<T>(value: &Bound<'_, PyAny>, label: &str, parse: impl FnOnce(&str) -> PyResult<T>) -> PyResult<T> {
+    if let Ok(text) = value.downcast::<PyString>() {
+        return parse(&text.to_cow()?);
+    }
+    if let Ok(bytes) = value.downcast::<PyBytes>() {
+        let text = std::str::from_utf8(bytes.as_bytes())
+            .map_err(|_| PyValueError::new_err(format!("{} is not valid UTF-8", label)))?;
+        return parse(text);
+    }
+    Err(PyTypeError::new_err(format!("{} must be str or bytes", label)))
 }
 
-#[derive(FromPyObject, Clone)]
+/// Reads an optional mapping key, treating a missing key like `None`.
+fn optional_item<'py, T: FromPyObject<'py>>(obj: &Bound<'py, PyAny>, key: &str) -> PyResult<Option<T>> {
+    match obj.get_item(key) {
+        Ok(value) if value.is_none() => Ok(None),
+        Ok(value) if value.extract::<&str>().is_ok_and(is_null_sentinel) => Ok(None),
+        Ok(value) => value.extract().map(Some),
+        Err(err) if err.is_instance_of::<PyKeyError>(obj.py()) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// One summary window, as passed to the summarise calls or read from a
+/// config file; `label` defaults to `key`.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 struct IntervalSpec {
     key: String,
-    label: String,
+    #[serde(default)]
+    label: Option<String>,
+    #[serde(default)]
     delta_seconds: Option<i64>,
+    /// Anchored intervals start at `anchor_timestamp_us`, or at the latest
+    /// `anchor + k * period_seconds` not after `now` when a period is given.
+    #[serde(default)]
+    anchor_timestamp_us: Option<i64>,
+    #[serde(default)]
+    period_seconds: Option<i64>,
+    /// `kind: "since_last_flat"` intervals start with the trade that opened
+    /// the current position and are empty while flat. The boundary comes out
+    /// of the replay (see `anchor_since_last_flat`); outputs built without
+    /// one span from the cutoff.
+    #[serde(default, rename = "kind", deserialize_with = "deserialize_interval_kind")]
+    since_last_flat: bool,
+}
+
+impl IntervalSpec {
+    fn label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.key)
+    }
+}
+
+impl<'py> FromPyObject<'py> for IntervalSpec {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let kind: Option<String> = optional_item(obj, "kind")?;
+        Ok(IntervalSpec {
+            key: obj.get_item("key")?.extract()?,
+            label: optional_item(obj, "label")?,
+            delta_seconds: optional_item(obj, "delta_seconds")?,
+            anchor_timestamp_us: optional_item(obj, "anchor_timestamp_us")?,
+            period_seconds: optional_item(obj, "period_seconds")?,
+            since_last_flat: interval_kind(kind.as_deref())?,
+        })
+    }
+}
+
+fn interval_kind(kind: Option<&str>) -> PyResult<bool> {
+    match kind {
+        None => Ok(false),
+        Some("since_last_flat") => Ok(true),
+        Some(other) => Err(PyValueError::new_err(format!("unknown interval kind: {}", other))),
+    }
+}
+
+fn deserialize_interval_kind<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    let kind = Option::<String>::deserialize(deserializer)?;
+    interval_kind(kind.as_deref()).map_err(|err| serde::de::Error::custom(err.to_string()))
+}
+
+/// A split or redenomination: from `timestamp_us` on, one old unit is
+/// `ratio` new units, given as a decimal or as `new/old` (e.g. "1/3").
+/// `product_id` limits it to one product's trades; `new_product_id` also
+/// renames them, for redenominations that list under a new ticker.
+struct SplitSpec {
+    timestamp_us: i64,
+    ratio: String,
+    product_id: Option<String>,
+    new_product_id: Option<String>,
+}
+
+impl<'py> FromPyObject<'py> for SplitSpec {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        Ok(SplitSpec {
+            timestamp_us: obj.get_item("timestamp_us")?.extract()?,
+            ratio: obj.get_item("ratio")?.extract()?,
+            product_id: optional_item(obj, "product_id")?,
+            new_product_id: optional_item(obj, "new_product_id")?,
+        })
+    }
+}
+
+/// `fixed_point_decimals`: one (price, size) pair of decimal places for every
+/// trade, or a pair per product id.
+#[derive(FromPyObject)]
+enum FixedPointDecimals {
+    All((u32, u32)),
+    ByProduct(BTreeMap<String, (u32, u32)>),
+}
+
+impl FixedPointDecimals {
+    /// Places the replay runs at: the traded product's pair, or the widest
+    /// price and size places among the products sharing the book.
+    fn resolve(&self, trades: &[Trade]) -> PyResult<(u32, u32)> {
+        let by_product = match self {
+            FixedPointDecimals::All(decimals) => return Ok(*decimals),
+            FixedPointDecimals::ByProduct(by_product) => by_product,
+        };
+        let mut resolved = (0, 0);
+        let mut seen: Option<&str> = None;
+        for trade in trades {
+            let product_id = trade.product_id.as_deref().ok_or_else(|| {
+                PyValueError::new_err("per-product fixed_point_decimals need a product_id on every trade")
+            })?;
+            if seen == Some(product_id) {
+                continue;
+            }
+            seen = Some(product_id);
+            let (price, size) = by_product.get(product_id).ok_or_else(|| {
+                PyValueError::new_err(format!("no fixed_point_decimals for product {}", product_id))
+            })?;
+            resolved = (resolved.0.max(*price), resolved.1.max(*size));
+        }
+        Ok(resolved)
+    }
+}
+
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct SnapshotSpec {
+    timestamp_us: i64,
+    mark_price: String,
+}
+
+/// One observation of a price series used by `simulate_stops`.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct PricePointSpec {
+    timestamp_us: i64,
+    price: String,
+}
+
+/// Compares the interval `interval_key` with the same window moved back by
+/// `offset_seconds`, e.g. this week against last week.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct ComparisonSpec {
+    key: String,
+    interval_key: String,
+    offset_seconds: i64,
+}
+
+/// One perpetual funding settlement: `rate` is charged on the position's
+/// value at `mark_price`, longs paying when it is positive.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct FundingSpec {
+    timestamp_us: i64,
+    rate: String,
+    mark_price: String,
+}
+
+/// A borrow or interest charge already computed by the venue.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct BorrowCostSpec {
+    timestamp_us: i64,
+    amount: String,
+}
+
+/// Annualised borrow rate on short inventory, effective from `timestamp_us`
+/// until the next entry.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct BorrowRateSpec {
+    timestamp_us: i64,
+    annual_rate: String,
+}
+
+/// Maker and taker rates in force from `effective_from_timestamp_us` until
+/// the next entry, e.g. after a volume tier change.
+#[derive(Clone, FromPyObject)]
+#[pyo3(from_item_all)]
+struct FeeRateSpec {
+    effective_from_timestamp_us: i64,
+    maker_fee_rate: String,
+    taker_fee_rate: String,
+}
+
+/// A what-if run for `scenarios`. Fee settings left out fall back to the
+/// call's own; the lot method and maker classification to the defaults. A
+/// scenario that sets any fee field prices every trade at its rates, ignoring
+/// commissions recorded on the trades; one that only changes the lot method
+/// or classification keeps them.
+struct ScenarioSpec {
+    key: String,
+    maker_fee_rate: Option<String>,
+    taker_fee_rate: Option<String>,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<String>,
+    max_fee: Option<String>,
+    lot_method: Option<String>,
+    maker_classification: Option<String>,
+}
+
+impl<'py> FromPyObject<'py> for ScenarioSpec {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        Ok(ScenarioSpec {
+            key: obj.get_item("key")?.extract()?,
+            maker_fee_rate: optional_item(obj, "maker_fee_rate")?,
+            taker_fee_rate: optional_item(obj, "taker_fee_rate")?,
+            fee_schedule: optional_item(obj, "fee_schedule")?,
+            min_fee: optional_item(obj, "min_fee")?,
+            max_fee: optional_item(obj, "max_fee")?,
+            lot_method: optional_item(obj, "lot_method")?,
+            maker_classification: optional_item(obj, "maker_classification")?,
+        })
+    }
+}
+
+impl ScenarioSpec {
+    fn overrides_fees(&self) -> bool {
+        self.maker_fee_rate.is_some()
+            || self.taker_fee_rate.is_some()
+            || self.fee_schedule.is_some()
+            || self.min_fee.is_some()
+            || self.max_fee.is_some()
+    }
+}
+
+/// One product's raw inputs for `aggregate_portfolio`.
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct PortfolioProduct {
+    product_id: String,
+    trades: TradeInputs,
+    maker_fee_rate: String,
+    taker_fee_rate: String,
+}
+
+#[derive(FromPyObject)]
+#[pyo3(from_item_all)]
+struct RatePoint {
+    timestamp_us: i64,
+    price: String,
+}
+
+/// A conversion price given either as one spot rate or as a timestamped table.
+#[derive(FromPyObject)]
+enum ConversionInput {
+    Spot(String),
+    Table(Vec<RatePoint>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct RawOrder {
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     order_id: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     status: Option<String>,
-    #[serde(default, rename = "order_status")]
+    #[serde(default, rename = "order_status", deserialize_with = "nullable_string")]
     legacy_status: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     client_order_id: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     side: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     completed_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     expire_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     submitted_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     created_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     order_placed_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_time")]
     last_fill_time: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     average_filled_price: Option<String>,
-    #[serde(default)]
+    #[serde(default, deserialize_with = "nullable_string")]
     product_id: Option<String>,
     #[serde(default)]
     order_configuration: Option<Value>,
+    #[serde(default)]
+    edit_history: Option<Vec<RawEdit>>,
+    /// Perpetual futures only; absent on spot orders.
+    #[serde(default)]
+    leverage: Option<Value>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    margin_type: Option<String>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    product_type: Option<String>,
+    /// Explicit one-cancels-other group shared by sibling orders.
+    #[serde(default, deserialize_with = "nullable_string")]
+    oco_group_id: Option<String>,
+    /// Entry order a bracket leg was attached to; legs of the same parent
+    /// cancel each other like an OCO group.
+    #[serde(default, alias = "parent_order_id", deserialize_with = "nullable_string")]
+    originating_order_id: Option<String>,
 }
 
+/// One accepted amendment from an order's `edit_history`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct RawFill {
+struct RawEdit {
     #[serde(default)]
-    order_id: Option<String>,
+    price: Option<Value>,
     #[serde(default)]
+    size: Option<Value>,
+    #[serde(default, deserialize_with = "nullable_time")]
+    replace_accept_timestamp: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RawFill {
+    #[serde(default, deserialize_with = "nullable_string")]
+    order_id: Option<String>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    trade_id: Option<String>,
+    #[serde(default, deserialize_with = "nullable_time")]
     trade_time: Option<String>,
     #[serde(default)]
     size: Option<Value>,
@@ -73,6 +414,14 @@ struct RawFill {
     unit_price: Option<Value>,
     #[serde(default)]
     average_price: Option<Value>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    side: Option<String>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    product_id: Option<String>,
+    #[serde(default)]
+    commission: Option<Value>,
+    #[serde(default, deserialize_with = "nullable_string")]
+    liquidity_indicator: Option<String>,
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -81,700 +430,7610 @@ enum Side {
     Sell,
 }
 
+impl Side {
+    fn as_str(self) -> &'static str {
+        match self {
+            Side::Buy => "BUY",
+            Side::Sell => "SELL",
+        }
+    }
+
+    fn opposite(self) -> Self {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
 impl TryFrom<&str> for Side {
     type Error = PyErr;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        match value.to_ascii_uppercase().as_str() {
-            "BUY" => Ok(Side::Buy),
-            "SELL" => Ok(Side::Sell),
-            other => Err(PyValueError::new_err(format!("unknown side: {}", other))),
+        if value.eq_ignore_ascii_case("BUY") {
+            Ok(Side::Buy)
+        } else if value.eq_ignore_ascii_case("SELL") {
+            Ok(Side::Sell)
+        } else {
+            Err(PyValueError::new_err(format!("unknown side: {}", value.to_ascii_uppercase())))
         }
     }
 }
 
-struct Trade {
-    timestamp: DateTime<Utc>,
-    side: Side,
-    price: Decimal,
-    size: Decimal,
-    post_only: bool,
-}
+/// Arithmetic used by the lot matcher and interval sums. `Decimal` is the
+/// default; `i128` backs the fixed-point mode, where every value is a mantissa
+/// at a caller-chosen scale and a product's scale is the sum of its operands'
+/// scales, so the loop never rescales. Operations are checked: an overflow is
+/// an error rather than a panic or a silently wrapped PnL.
+trait Amount: Copy + Default + PartialOrd {
+    const ZERO: Self;
+    /// Whether values are fixed-point mantissas.
+    const FIXED: bool;
 
-#[derive(Clone, Copy)]
-struct Lot {
-    price: Decimal,
-    size: Decimal,
-}
+    fn try_add(self, other: Self) -> PyResult<Self>;
+    fn try_sub(self, other: Self) -> PyResult<Self>;
+    fn try_mul(self, other: Self) -> PyResult<Self>;
 
-struct Entry {
-    timestamp: DateTime<Utc>,
-    realized_profit: Decimal,
-    maker_volume: Decimal,
-    taker_volume: Decimal,
-    fee: Decimal,
-}
+    /// A whole number (e.g. elapsed microseconds) at scale 0.
+    fn from_i64(value: i64) -> Self;
 
-struct RawMetrics {
-    profit_before_fees: Decimal,
-    maker_volume: Decimal,
-    taker_volume: Decimal,
-    fee_total: Decimal,
-    profit_after_fees: Decimal,
-}
+    fn to_decimal(self, scale: u32) -> PyResult<Decimal>;
 
-fn timestamp_us_to_datetime(ts: i64) -> PyResult<DateTime<Utc>> {
-    let secs = ts.div_euclid(1_000_000);
-    let micros = ts.rem_euclid(1_000_000) as u32;
-    let nanos = micros * 1_000;
-    let naive = NaiveDateTime::from_timestamp_opt(secs, nanos)
-        .ok_or_else(|| PyValueError::new_err("timestamp out of range"))?;
-    Ok(DateTime::<Utc>::from_utc(naive, Utc))
+    /// `1 / self`, used to price inverse contracts.
+    fn recip(self) -> PyResult<Self>;
 }
 
-fn parse_decimal(value: &str, label: &str) -> PyResult<Decimal> {
-    Decimal::from_str(value)
-        .map_err(|_| PyValueError::new_err(format!("invalid decimal for {}: {}", label, value)))
+fn amount_overflow() -> PyErr {
+    PyValueError::new_err("arithmetic overflow in PnL computation; lower fixed_point_decimals or check input magnitudes")
 }
 
-fn build_entries(trades: &[Trade], maker_fee: Decimal, taker_fee: Decimal) -> Vec<Entry> {
-    let mut long_lots: VecDeque<Lot> = VecDeque::new();
-    let mut short_lots: VecDeque<Lot> = VecDeque::new();
-    let mut entries: Vec<Entry> = Vec::with_capacity(trades.len());
-    let zero = Decimal::ZERO;
-
-    for trade in trades {
-        let mut remaining = trade.size;
-        let mut realized = Decimal::ZERO;
-
-        match trade.side {
-            Side::Buy => {
-                while remaining > zero {
-                    if let Some(front) = short_lots.front_mut() {
-                        let matched = if remaining <= front.size { remaining } else { front.size };
-                        realized += (front.price - trade.price) * matched;
-                        front.size -= matched;
-                        remaining -= matched;
-                        if front.size <= zero {
-                            short_lots.pop_front();
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                if remaining > zero {
-                    long_lots.push_back(Lot {
-                        price: trade.price,
-                        size: remaining,
-                    });
-                }
-            }
-            Side::Sell => {
-                while remaining > zero {
-                    if let Some(front) = long_lots.front_mut() {
-                        let matched = if remaining <= front.size { remaining } else { front.size };
-                        realized += (trade.price - front.price) * matched;
-                        front.size -= matched;
-                        remaining -= matched;
-                        if front.size <= zero {
-                            long_lots.pop_front();
-                        }
-                    } else {
-                        break;
-                    }
-                }
-                if remaining > zero {
-                    short_lots.push_back(Lot {
-                        price: trade.price,
-                        size: remaining,
-                    });
-                }
-            }
-        }
-
-        let notional = trade.price * trade.size;
-        let maker_volume = if trade.post_only { notional } else { zero };
-        let taker_volume = if trade.post_only { zero } else { notional };
-        let fee_rate = if trade.post_only { maker_fee } else { taker_fee };
-        let fee = notional * fee_rate;
+impl Amount for Decimal {
+    const ZERO: Self = Decimal::ZERO;
+    const FIXED: bool = false;
 
-        entries.push(Entry {
-            timestamp: trade.timestamp,
-            realized_profit: realized,
-            maker_volume,
-            taker_volume,
-            fee,
-        });
+    fn try_add(self, other: Self) -> PyResult<Self> {
+        self.checked_add(other).ok_or_else(amount_overflow)
     }
 
-    entries
-}
+    fn try_sub(self, other: Self) -> PyResult<Self> {
+        self.checked_sub(other).ok_or_else(amount_overflow)
+    }
 
-fn summarise_interval(entries: &[Entry], start: DateTime<Utc>) -> RawMetrics {
-    let zero = Decimal::ZERO;
-    let mut profit_before = zero;
-    let mut maker_volume = zero;
-    let mut taker_volume = zero;
-    let mut fee_total = zero;
+    fn try_mul(self, other: Self) -> PyResult<Self> {
+        self.checked_mul(other).ok_or_else(amount_overflow)
+    }
 
-    for entry in entries {
-        if entry.timestamp < start {
-            continue;
-        }
-        profit_before += entry.realized_profit;
-        maker_volume += entry.maker_volume;
-        taker_volume += entry.taker_volume;
-        fee_total += entry.fee;
+    fn from_i64(value: i64) -> Self {
+        Decimal::from(value)
     }
 
-    let profit_after = profit_before - fee_total;
-    RawMetrics {
-        profit_before_fees: profit_before,
-        maker_volume,
-        taker_volume,
-        fee_total,
-        profit_after_fees: profit_after,
+    fn to_decimal(self, _scale: u32) -> PyResult<Decimal> {
+        Ok(self)
     }
-}
 
-fn interval_start(now: DateTime<Utc>, delta: Option<i64>, cutoff: DateTime<Utc>) -> DateTime<Utc> {
-    match delta {
-        None => cutoff,
-        Some(seconds) => {
-            let start = now - Duration::seconds(seconds.max(0));
-            if start < cutoff { cutoff } else { start }
-        }
+    fn recip(self) -> PyResult<Self> {
+        Decimal::ONE
+            .checked_div(self)
+            .ok_or_else(|| PyValueError::new_err(format!("cannot invert price {}", self)))
     }
 }
 
-#[pyfunction]
-fn summarise_trades(
-    py: Python<'_>,
-    trades: Vec<TradeInput>,
-    intervals: Vec<IntervalSpec>,
-    now_timestamp_us: i64,
-    cutoff_timestamp_us: i64,
-    maker_fee_rate: &str,
-    taker_fee_rate: &str,
-) -> PyResult<PyObject> {
-    let maker_fee = parse_decimal(maker_fee_rate, "maker_fee_rate")?;
-    let taker_fee = parse_decimal(taker_fee_rate, "taker_fee_rate")?;
-    let now = timestamp_us_to_datetime(now_timestamp_us)?;
-    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+impl Amount for i128 {
+    const ZERO: Self = 0;
+    const FIXED: bool = true;
 
-    let mut parsed_trades: Vec<Trade> = Vec::with_capacity(trades.len());
-    for trade in trades {
-        let price = parse_decimal(&trade.price, "price")?;
-        let size = parse_decimal(&trade.size, "size")?;
-        if size <= Decimal::ZERO || price <= Decimal::ZERO {
-            continue;
-        }
-        let timestamp = timestamp_us_to_datetime(trade.timestamp_us)?;
-        if timestamp < cutoff {
-            continue;
-        }
-        let side = Side::try_from(trade.side.as_str())?;
-        parsed_trades.push(Trade {
-            timestamp,
-            side,
-            price,
-            size,
-            post_only: trade.post_only,
-        });
+    fn try_add(self, other: Self) -> PyResult<Self> {
+        self.checked_add(other).ok_or_else(amount_overflow)
     }
 
-    parsed_trades.sort_by_key(|trade| trade.timestamp);
-    let entries = build_entries(&parsed_trades, maker_fee, taker_fee);
+    fn try_sub(self, other: Self) -> PyResult<Self> {
+        self.checked_sub(other).ok_or_else(amount_overflow)
+    }
 
-    let mut intervals_py = Vec::with_capacity(intervals.len());
-    let mut total_before = Decimal::ZERO;
-    let mut total_after = Decimal::ZERO;
+    fn try_mul(self, other: Self) -> PyResult<Self> {
+        self.checked_mul(other).ok_or_else(amount_overflow)
+    }
 
-    for spec in &intervals {
-        let start = interval_start(now, spec.delta_seconds, cutoff);
-        let metrics = summarise_interval(&entries, start);
-        let interval_dict = PyDict::new(py);
-        interval_dict.set_item("key", &spec.key)?;
-        interval_dict.set_item("label", &spec.label)?;
-        interval_dict.set_item("profit_before_fees", metrics.profit_before_fees.to_string())?;
-        interval_dict.set_item("maker_volume", metrics.maker_volume.to_string())?;
-        interval_dict.set_item("taker_volume", metrics.taker_volume.to_string())?;
-        interval_dict.set_item("fee_total", metrics.fee_total.to_string())?;
-        interval_dict.set_item("profit_after_fees", metrics.profit_after_fees.to_string())?;
+    fn from_i64(value: i64) -> Self {
+        i128::from(value)
+    }
 
-        if spec.key == "all" {
-            total_before = metrics.profit_before_fees;
-            total_after = metrics.profit_after_fees;
-        }
+    fn to_decimal(self, scale: u32) -> PyResult<Decimal> {
+        Decimal::try_from_i128_with_scale(self, scale)
+            .map(|value| value.normalize())
+            .map_err(|_| PyValueError::new_err("fixed-point value out of Decimal range"))
+    }
 
-        intervals_py.push(interval_dict);
+    fn recip(self) -> PyResult<Self> {
+        Err(PyValueError::new_err("inverse products are not supported with fixed_point_decimals"))
     }
+}
 
-    let result = PyDict::new(py);
-    result.set_item("intervals", PyList::new(py, intervals_py))?;
-    result.set_item("total_profit_before_fees", total_before.to_string())?;
-    result.set_item("total_profit_after_fees", total_after.to_string())?;
+struct Trade<A = Decimal> {
+    timestamp: DateTime<Utc>,
+    side: Side,
+    price: A,
+    size: A,
+    post_only: bool,
+    product_id: Option<Arc<str>>,
+    leg_group: Option<Arc<str>>,
+    /// Caller's id for the trade and its position in the input list, kept so
+    /// match ledgers can point back at the original rows.
+    trade_id: Option<Arc<str>>,
+    input_index: usize,
+    /// Actual commission charged, replacing the rate-based fee when present.
+    commission: Option<Decimal>,
+    commission_currency: Option<Arc<str>>,
+    /// Coin-margined contract: `size` is contracts worth one quote unit each,
+    /// so PnL is contracts x (1/entry - 1/exit) and accrues in the base currency.
+    inverse: bool,
+}
 
-    Ok(result.into())
+/// Deduplicates repeated labels (product ids, group ids) so every trade
+/// shares one allocation per distinct value.
+#[derive(Default)]
+struct Interner {
+    values: FxHashSet<Arc<str>>,
 }
 
-fn value_to_string(value: &Value) -> Option<String> {
-    match value {
-        Value::String(s) => Some(s.clone()),
-        Value::Number(n) => Some(n.to_string()),
-        Value::Bool(b) => Some(b.to_string()),
-        _ => None,
+impl Interner {
+    fn intern(&mut self, value: &str) -> Arc<str> {
+        if let Some(existing) = self.values.get(value) {
+            return existing.clone();
+        }
+        let interned: Arc<str> = Arc::from(value);
+        self.values.insert(interned.clone());
+        interned
     }
 }
 
-fn option_to_string(value: Option<&Value>) -> Option<String> {
-    value.and_then(value_to_string)
+#[derive(Clone, Copy, Default)]
+struct Lot<A> {
+    price: A,
+    size: A,
+    /// Fee charged on the opening trade (after any minimum or cap) and the
+    /// size it booked; the lot carries `fee * size / trade_size` of it.
+    fee: Decimal,
+    trade_size: A,
+    /// Replay index of the trade that opened the lot.
+    trade: usize,
+    /// When that trade happened, for holding times.
+    opened_us: i64,
 }
 
-fn decimal_from_value(value: Option<&Value>) -> Option<Decimal> {
-    option_to_string(value).and_then(|text| Decimal::from_str(text.trim()).ok())
+/// One lot closed (fully or partly) by a later trade; trades are replay indices.
+struct LotMatch<A = Decimal> {
+    open_trade: usize,
+    close_trade: usize,
+    size: A,
+    open_price: A,
+    close_price: A,
+    realized: A,
 }
 
-fn parse_datetime_text(text: &str) -> Option<DateTime<Utc>> {
-    let trimmed = text.trim();
-    if trimmed.is_empty() {
-        return None;
+/// FIFO queue of open lots. A book rarely holds more than a handful of lots, so
+/// they live inline and consumed lots are skipped via `head` rather than shifted.
+/// Total size and cost are kept current so position reads don't walk the lots.
+#[derive(Default)]
+struct LotQueue<A: Amount> {
+    lots: SmallVec<[Lot<A>; 8]>,
+    head: usize,
+    size: A,
+    cost: A,
+}
+
+impl<A: Amount> LotQueue<A> {
+    fn front(&self) -> Option<&Lot<A>> {
+        self.lots.get(self.head)
     }
-    if let Ok(dt) = DateTime::parse_from_rfc3339(trimmed) {
-        return Some(dt.with_timezone(&Utc));
+
+    /// Removes `matched` from the front lot, dropping it once exhausted.
+    fn consume_front(&mut self, matched: A) -> PyResult<()> {
+        let Some(front) = self.lots.get_mut(self.head) else {
+            return Ok(());
+        };
+        front.size = front.size.try_sub(matched)?;
+        self.size = self.size.try_sub(matched)?;
+        self.cost = self.cost.try_sub(front.price.try_mul(matched)?)?;
+        if front.size <= A::ZERO {
+            self.pop_front();
+        }
+        Ok(())
     }
-    if let Ok(dt) = NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S%.f") {
-        return Some(DateTime::<Utc>::from_utc(dt, Utc));
+
+    fn pop_front(&mut self) {
+        self.head += 1;
+        if self.head == self.lots.len() {
+            self.lots.clear();
+            self.head = 0;
+            self.size = A::ZERO;
+            self.cost = A::ZERO;
+        } else if self.head >= 32 && self.head * 2 >= self.lots.len() {
+            self.lots.drain(..self.head);
+            self.head = 0;
+        }
     }
-    if let Ok(dt) = NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M:%S%.f") {
-        return Some(DateTime::<Utc>::from_utc(dt, Utc));
+
+    fn back(&self) -> Option<&Lot<A>> {
+        if self.is_empty() {
+            None
+        } else {
+            self.lots.last()
+        }
     }
-    None
-}
 
-fn parse_datetime_value(value: Option<&Value>) -> Option<DateTime<Utc>> {
-    option_to_string(value)
-        .as_deref()
-        .and_then(|s| if s.is_empty() { None } else { parse_datetime_text(s) })
-}
+    /// Removes `matched` from the newest lot, dropping it once exhausted.
+    fn consume_back(&mut self, matched: A) -> PyResult<()> {
+        let Some(back) = self.lots.last_mut() else {
+            return Ok(());
+        };
+        back.size = back.size.try_sub(matched)?;
+        self.size = self.size.try_sub(matched)?;
+        self.cost = self.cost.try_sub(back.price.try_mul(matched)?)?;
+        if back.size <= A::ZERO {
+            self.lots.pop();
+            if self.head == self.lots.len() {
+                self.lots.clear();
+                self.head = 0;
+                self.size = A::ZERO;
+                self.cost = A::ZERO;
+            }
+        }
+        Ok(())
+    }
 
-fn format_datetime(dt: DateTime<Utc>) -> String {
-    dt.to_rfc3339_opts(SecondsFormat::Millis, true)
-}
+    fn push_back(&mut self, lot: Lot<A>) -> PyResult<()> {
+        self.size = self.size.try_add(lot.size)?;
+        self.cost = self.cost.try_add(lot.price.try_mul(lot.size)?)?;
+        self.lots.push(lot);
+        Ok(())
+    }
 
-fn parse_boolish(value: Option<&Value>) -> Option<bool> {
-    match value {
-        Some(Value::Bool(b)) => Some(*b),
-        Some(other) => option_to_string(Some(other)).as_deref().and_then(|s| {
-            let lower = s.trim().to_ascii_lowercase();
-            match lower.as_str() {
-                "true" => Some(true),
-                "false" => Some(false),
-                _ => None,
-            }
-        }),
-        None => None,
+    fn iter(&self) -> impl Iterator<Item = &Lot<A>> {
+        self.lots[self.head..].iter()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.head == self.lots.len()
     }
 }
 
-#[derive(Clone, Copy)]
-struct FillData {
-    size: Decimal,
-    price: Decimal,
-    trade_time: Option<DateTime<Utc>>,
+/// Long and short lot queues. Only one side is ever non-empty: a trade
+/// closes opposite lots first (oldest first, or newest first when `lifo`)
+/// and books any remainder on its own side.
+///
+/// Inverse contracts are booked as the opposite side of a linear position
+/// priced at 1/price, which yields contracts x (1/entry - 1/exit) in base
+/// units; `inverse` flips that back when the position is read.
+#[derive(Default)]
+struct Book<A: Amount> {
+    long_lots: LotQueue<A>,
+    short_lots: LotQueue<A>,
+    inverse: bool,
+    lifo: bool,
 }
 
-fn collect_fills(fills: &[RawFill]) -> HashMap<String, Vec<FillData>> {
-    let mut map: HashMap<String, Vec<FillData>> = HashMap::new();
-    for fill in fills {
-        let Some(order_id) = fill
-            .order_id
-            .as_ref()
-            .and_then(|s| if s.is_empty() { None } else { Some(s.clone()) })
-        else {
-            continue;
-        };
-        let size = decimal_from_value(fill.size.as_ref())
-            .or_else(|| decimal_from_value(fill.base_size.as_ref()));
-        let price = decimal_from_value(fill.price.as_ref())
-            .or_else(|| decimal_from_value(fill.unit_price.as_ref()))
-            .or_else(|| decimal_from_value(fill.average_price.as_ref()));
-        let (Some(size), Some(price)) = (size, price) else { continue; };
-        if size <= Decimal::ZERO || price <= Decimal::ZERO {
-            continue;
+impl<A: Amount> Book<A> {
+    /// Side and price `trade` is booked at: its own for linear products, the
+    /// opposite side at 1/price for inverse ones.
+    fn booked(&mut self, trade: &Trade<A>) -> PyResult<(Side, A)> {
+        if !trade.inverse {
+            return Ok((trade.side, trade.price));
         }
-        let trade_time = fill
-            .trade_time
-            .as_deref()
-            .and_then(parse_datetime_text);
-        map.entry(order_id)
-            .or_default()
-            .push(FillData { size, price, trade_time });
+        self.inverse = true;
+        Ok((trade.side.opposite(), trade.price.recip()?))
     }
-    map
-}
 
-enum OrderConfigType {
-    Limit,
-    StopLimit,
-    TriggerBracket,
-    Market,
-    Unknown,
-}
+    /// Applies a trade and returns the profit realized by the lots it closed.
+    fn execute(&mut self, side: Side, price: A, size: A, fee: Decimal) -> PyResult<A> {
+        self.execute_traced(side, price, size, fee, 0, 0, None, None)
+    }
 
-fn extract_order_config<'a>(value: Option<&'a Value>) -> (OrderConfigType, Option<&'a serde_json::Map<String, Value>>) {
-    let Some(container) = value.and_then(|v| v.as_object()) else {
-        return (OrderConfigType::Unknown, None);
-    };
+    /// `execute` that also tags opened lots with `trade` and its timestamp
+    /// and, for every lot it closes, records the match in `ledger` and how
+    /// long the lot was held (with the size closed) in `holding`.
+    #[allow(clippy::too_many_arguments)]
+    fn execute_traced(
+        &mut self,
+        side: Side,
+        price: A,
+        size: A,
+        fee: Decimal,
+        trade: usize,
+        timestamp_us: i64,
+        mut ledger: Option<&mut Vec<LotMatch<A>>>,
+        mut holding: Option<&mut SmallVec<[(i64, A); 1]>>,
+    ) -> PyResult<A> {
+        let lifo = self.lifo;
+        let (closing, opening) = match side {
+            Side::Buy => (&mut self.short_lots, &mut self.long_lots),
+            Side::Sell => (&mut self.long_lots, &mut self.short_lots),
+        };
+        let mut remaining = size;
+        let mut realized = A::ZERO;
 
-    for key in ["limit_limit_gtd", "limit_limit_gtc"] {
-        if let Some(entry) = container.get(key).and_then(|v| v.as_object()) {
-            return (OrderConfigType::Limit, Some(entry));
+        while remaining > A::ZERO {
+            let next = if lifo { closing.back() } else { closing.front() };
+            let Some(front) = next else {
+                break;
+            };
+            let matched = if remaining <= front.size { remaining } else { front.size };
+            let profit = match side {
+                Side::Buy => front.price.try_sub(price)?.try_mul(matched)?,
+                Side::Sell => price.try_sub(front.price)?.try_mul(matched)?,
+            };
+            if let Some(ledger) = ledger.as_deref_mut() {
+                ledger.push(LotMatch {
+                    open_trade: front.trade,
+                    close_trade: trade,
+                    size: matched,
+                    open_price: front.price,
+                    close_price: price,
+                    realized: profit,
+                });
+            }
+            if let Some(holding) = holding.as_deref_mut() {
+                holding.push((timestamp_us.saturating_sub(front.opened_us), matched));
+            }
+            realized = realized.try_add(profit)?;
+            if lifo {
+                closing.consume_back(matched)?;
+            } else {
+                closing.consume_front(matched)?;
+            }
+            remaining = remaining.try_sub(matched)?;
         }
-    }
 
-    for key in ["stop_limit_stop_limit_gtd", "stop_limit_stop_limit_gtc"] {
-        if let Some(entry) = container.get(key).and_then(|v| v.as_object()) {
-            return (OrderConfigType::StopLimit, Some(entry));
+        if remaining > A::ZERO {
+            opening.push_back(Lot {
+                price,
+                size: remaining,
+                fee,
+                trade_size: size,
+                trade,
+                opened_us: timestamp_us,
+            })?;
         }
+
+        Ok(realized)
     }
 
-    for key in ["trigger_bracket_gtd", "trigger_bracket_gtc"] {
-        if let Some(entry) = container.get(key).and_then(|v| v.as_object()) {
-            return (OrderConfigType::TriggerBracket, Some(entry));
+    /// The side currently holding inventory and its lots.
+    fn active(&self) -> (Option<Side>, &LotQueue<A>) {
+        if !self.long_lots.is_empty() {
+            (Some(Side::Buy), &self.long_lots)
+        } else if !self.short_lots.is_empty() {
+            (Some(Side::Sell), &self.short_lots)
+        } else {
+            (None, &self.long_lots)
         }
     }
 
-    for key in ["market_market_ioc", "market_market_gtc"] {
-        if let Some(entry) = container.get(key).and_then(|v| v.as_object()) {
-            return (OrderConfigType::Market, Some(entry));
+    fn open_position(&self, scales: EntryScales) -> PyResult<OpenPosition> {
+        let (side, lots) = self.active();
+        let mut entry_fees = Decimal::ZERO;
+        for lot in lots.iter() {
+            let (size, trade_size) = (lot.size.to_decimal(scales.size)?, lot.trade_size.to_decimal(scales.size)?);
+            let fee = if size == trade_size {
+                lot.fee
+            } else {
+                lot.fee.try_mul(size)?.checked_div(trade_size).ok_or_else(amount_overflow)?
+            };
+            entry_fees = entry_fees.try_add(fee)?;
         }
+
+        Ok(OpenPosition {
+            side: if self.inverse { side.map(Side::opposite) } else { side },
+            size: lots.size.to_decimal(scales.size)?,
+            cost: lots.cost.to_decimal(scales.notional)?,
+            entry_fees,
+            inverse: self.inverse,
+        })
     }
 
-    (OrderConfigType::Unknown, None)
+    fn position_point(&self, timestamp: DateTime<Utc>, scales: EntryScales) -> PyResult<PositionPoint> {
+        Ok(PositionPoint {
+            timestamp,
+            position: self.open_position(scales)?,
+        })
+    }
 }
 
-fn min_datetime(values: &[Option<DateTime<Utc>>]) -> Option<DateTime<Utc>> {
-    values.iter().filter_map(|opt| *opt).min()
+struct PositionPoint {
+    timestamp: DateTime<Utc>,
+    position: OpenPosition,
 }
 
-fn resolve_submitted_time(
-    order: &RawOrder,
-    fills: Option<&Vec<FillData>>,
-    completed: Option<DateTime<Utc>>,
-) -> (DateTime<Utc>, bool) {
-    let candidates = [
-        order.submitted_time.as_deref(),
-        order.created_time.as_deref(),
-        order.order_placed_time.as_deref(),
-        order.last_fill_time.as_deref(),
-    ];
+/// Net inventory left after replaying every trade. For inverse contracts
+/// `cost` and `entry_fees` are in base units.
+#[derive(Default)]
+struct OpenPosition {
+    side: Option<Side>,
+    size: Decimal,
+    cost: Decimal,
+    entry_fees: Decimal,
+    inverse: bool,
+}
 
-    for candidate in candidates.iter().flatten() {
-        if let Some(dt) = parse_datetime_text(candidate) {
-            return (dt, false);
+impl OpenPosition {
+    fn direction(&self) -> &'static str {
+        match self.side {
+            Some(Side::Buy) => "long",
+            Some(Side::Sell) => "short",
+            None => "flat",
         }
     }
 
-    if let Some(fills_vec) = fills {
-        let fill_times: Vec<Option<DateTime<Utc>>> = fills_vec.iter().map(|fill| fill.trade_time).collect();
-        if let Some(min_dt) = min_datetime(&fill_times) {
-            return (min_dt, false);
+    fn signed_size(&self) -> Decimal {
+        match self.side {
+            Some(Side::Sell) => -self.size,
+            _ => self.size,
         }
     }
 
-    if let Some(dt) = completed {
-        return (dt, false);
+    fn unrealized_profit(&self, mark_price: Decimal) -> Decimal {
+        if self.inverse {
+            let mark_value = if mark_price > Decimal::ZERO { self.size / mark_price } else { Decimal::ZERO };
+            return match self.side {
+                Some(Side::Buy) => self.cost - mark_value,
+                Some(Side::Sell) => mark_value - self.cost,
+                None => Decimal::ZERO,
+            };
+        }
+        match self.side {
+            Some(Side::Buy) => mark_price * self.size - self.cost,
+            Some(Side::Sell) => self.cost - mark_price * self.size,
+            None => Decimal::ZERO,
+        }
     }
 
-    (Utc::now(), true)
-}
+    /// Size-weighted entry price; the harmonic mean for inverse contracts.
+    fn average_entry_price(&self) -> Option<Decimal> {
+        if self.inverse {
+            return (self.cost > Decimal::ZERO).then(|| self.size / self.cost);
+        }
+        (self.size > Decimal::ZERO).then(|| self.cost / self.size)
+    }
 
-fn average_fill_price(fills: Option<&Vec<FillData>>) -> Option<Decimal> {
-    let fills_vec = fills?;
-    let mut total_size = Decimal::ZERO;
-    let mut total_value = Decimal::ZERO;
-    for fill in fills_vec {
-        if fill.size <= Decimal::ZERO || fill.price <= Decimal::ZERO {
-            continue;
+    /// Exit price at which closing the position recovers its entry fees.
+    fn break_even_price(&self) -> Option<Decimal> {
+        if self.size <= Decimal::ZERO {
+            return None;
+        }
+        if self.inverse {
+            let exit_value = match self.side {
+                Some(Side::Sell) => self.cost + self.entry_fees,
+                _ => self.cost - self.entry_fees,
+            };
+            return (exit_value > Decimal::ZERO).then(|| self.size / exit_value);
+        }
+        match self.side {
+            Some(Side::Sell) => Some((self.cost - self.entry_fees) / self.size),
+            _ => Some((self.cost + self.entry_fees) / self.size),
         }
-        total_size += fill.size;
-        total_value += fill.size * fill.price;
     }
-    if total_size > Decimal::ZERO && total_value > Decimal::ZERO {
-        Some(total_value / total_size)
-    } else {
-        None
+
+    fn to_py<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("direction", self.direction())?;
+        dict.set_item("size", self.size.to_string())?;
+        dict.set_item(
+            "average_entry_price",
+            self.average_entry_price().map(|d| d.to_string()),
+        )?;
+        dict.set_item(
+            "break_even_price",
+            self.break_even_price().map(|d| d.to_string()),
+        )?;
+        dict.set_item("accrued_fees", self.entry_fees.to_string())?;
+        Ok(dict)
     }
 }
 
-struct ProcessedOpenRecord {
-    order_id: String,
-    side: String,
-    limit_price: Decimal,
-    base_size: Decimal,
-    status: String,
-    client_order_id: String,
-    end_time: Option<DateTime<Utc>>,
-    product_id: String,
-    stop_price: Option<Decimal>,
+struct Entry {
+    timestamp: DateTime<Utc>,
+    side: Side,
+    size: Decimal,
+    realized_profit: Decimal,
+    /// Liquidity side the fee was charged at; a zero-notional trade still
+    /// has one, so volumes cannot stand in for it.
+    is_maker: bool,
+    maker_volume: Decimal,
+    taker_volume: Decimal,
+    fee: Decimal,
+    /// Currency the fee was charged in, if known. Fees in any
+    /// currency other than the product's quote are kept out of `fee` and
+    /// carried in `foreign_fee`, as they can't be netted against PnL; with no
+    /// known quote the fee stays in `fee`.
+    fee_currency: Option<Arc<str>>,
+    foreign_fee: Decimal,
+    /// Realized profit and fee converted to base units at the trade's price;
+    /// only populated when base-denominated PnL is requested.
+    realized_profit_base: Decimal,
+    fee_base: Decimal,
+    /// Absolute position size and its cost basis once this trade is applied.
+    exposure_size: Decimal,
+    exposure_cost: Decimal,
+    /// Signed size the lot book holds once this trade is applied, as booked:
+    /// inverse contracts count as the opposite side at 1/price, and a sell
+    /// clamped by the oversell policy only moves it by what was booked.
+    position: Decimal,
+    /// Inverse contract: volumes, fees and PnL are in base units and `size`
+    /// is contracts, so the exposure is stored as base value / contracts.
+    inverse: bool,
+    /// A sell beyond the long inventory, whatever the oversell policy did with it.
+    oversold: bool,
+    /// This trade took the position back to flat or flipped it through zero.
+    closed_position: bool,
+    /// Direction held before the trade (long = bought first, also for
+    /// inverse contracts), i.e. the lots its realized profit came from.
+    held_side: Option<Side>,
+    /// How long each lot (or part of one) this trade closed had been open,
+    /// with the size closed.
+    holding_us: SmallVec<[(i64, Decimal); 1]>,
 }
 
-struct ProcessedExecutedRecord {
-    order_id: String,
-    ts_submitted: DateTime<Utc>,
-    ts_submitted_inferred: bool,
-    ts_filled: Option<DateTime<Utc>>,
-    side: String,
-    limit_price: Decimal,
-    base_size: Decimal,
-    status: String,
-    filled_size: Option<Decimal>,
-    client_order_id: String,
-    end_time: Option<DateTime<Utc>>,
-    product_id: String,
-    stop_price: Option<Decimal>,
-    post_only: bool,
+struct RawMetrics {
+    profit_before_fees: Decimal,
+    maker_volume: Decimal,
+    taker_volume: Decimal,
+    fee_total: Decimal,
+    /// `fee_total` split into what was charged and what was rebated (negative
+    /// fee rates), both as positive amounts.
+    fees_paid: Decimal,
+    rebates_received: Decimal,
+    /// `fee_total` split by the liquidity side of the trade.
+    maker_fees: Decimal,
+    taker_fees: Decimal,
+    /// The window holds both inverse trades (volume in base) and linear ones
+    /// (volume in quote), so volume-based ratios have no single unit.
+    mixed_units: bool,
+    /// Fees per charged currency; rate-based fees on trades without a
+    /// product id fall under "quote".
+    fees_by_currency: BTreeMap<String, Decimal>,
+    profit_after_fees: Decimal,
+    profit_before_fees_base: Decimal,
+    profit_after_fees_base: Decimal,
+    avg_exposure_base: Decimal,
+    avg_exposure_notional: Decimal,
+    /// Volume over average exposure; None without exposure in the window or
+    /// when inverse and linear units meet in it.
+    turnover: Option<Decimal>,
+    /// Times the position was closed out or flipped in the window.
+    inventory_cycles: usize,
+    /// `profit_before_fees` split by the direction of the lots closed.
+    long_profit: Decimal,
+    short_profit: Decimal,
+    /// Mean and median time lots closed in the window had been open,
+    /// weighted by the size closed.
+    avg_holding_us: Option<i64>,
+    median_holding_us: Option<i64>,
+    buy_count: usize,
+    sell_count: usize,
+    avg_buy_price: Option<Decimal>,
+    avg_sell_price: Option<Decimal>,
+    /// Largest peak-to-trough drop of cumulative after-fee PnL in the window.
+    max_drawdown: Decimal,
+    /// Funding received (positive) or paid in the window; only set when a
+    /// funding series was supplied.
+    funding_pnl: Option<Decimal>,
+    /// Carrying cost of borrowed inventory in the window, as a positive cost.
+    borrow_cost: Option<Decimal>,
 }
 
-fn process_orders_internal(
-    orders: &[RawOrder],
-    fills: &[RawFill],
-    default_product_id: &str,
-) -> Result<(Vec<ProcessedOpenRecord>, Vec<ProcessedExecutedRecord>), String> {
-    let fills_by_order = collect_fills(fills);
-    let mut open_records: Vec<ProcessedOpenRecord> = Vec::new();
-    let mut executed_records: Vec<ProcessedExecutedRecord> = Vec::new();
+fn timestamp_us_to_datetime(ts: i64) -> PyResult<DateTime<Utc>> {
+    let secs = ts.div_euclid(1_000_000);
+    let micros = ts.rem_euclid(1_000_000) as u32;
+    let nanos = micros * 1_000;
+    DateTime::<Utc>::from_timestamp(secs, nanos)
+        .ok_or_else(|| PyValueError::new_err("timestamp out of range"))
+}
 
-    for order in orders {
-        let Some(order_id) = order
-            .order_id
-            .as_ref()
-            .and_then(|s| if s.is_empty() { None } else { Some(s.clone()) })
-        else {
-            continue;
-        };
+fn parse_decimal(value: &str, label: &str) -> PyResult<Decimal> {
+    Decimal::from_str(value)
+        .map_err(|_| PyValueError::new_err(format!("invalid decimal for {}: {}", label, value)))
+}
 
-        let status = order
-            .status
-            .as_ref()
-            .or(order.legacy_status.as_ref())
-            .map(|s| s.to_ascii_uppercase())
-            .unwrap_or_else(|| "NEW".to_string());
+/// Scales of the matcher values when running in fixed-point mode: realized
+/// profit and volumes are price x size, fees additionally carry the rate's scale.
+#[derive(Clone, Copy, Default)]
+struct EntryScales {
+    price: u32,
+    size: u32,
+    notional: u32,
+    fee: u32,
+}
 
-        let (config_type, config_opt) = extract_order_config(order.order_configuration.as_ref());
-        let Some(config) = config_opt else {
-            continue;
-        };
+/// Fee rates the matcher charges: the flat maker/taker pair, replaced from
+/// each dated change on. Trades before the first change use the flat pair.
+/// Venues with a per-order minimum or cap set `min_fee` / `max_fee`, in the
+/// currency fees settle in. They apply to each trade (fill) on its own; fills
+/// of one order are not added up first.
+struct FeeSchedule<A = Decimal> {
+    maker: A,
+    taker: A,
+    changes: Vec<(DateTime<Utc>, A, A)>,
+    min_fee: Option<A>,
+    max_fee: Option<A>,
+}
 
-        let client_order_id = order.client_order_id.clone().unwrap_or_default();
-        let side_text = order.side.clone().unwrap_or_else(|| "BUY".to_string());
-        let side_enum = Side::try_from(side_text.as_str()).unwrap_or(Side::Buy);
-        let side_str = match side_enum {
-            Side::Buy => "BUY".to_string(),
-            Side::Sell => "SELL".to_string(),
-        };
+impl<A: Amount> FeeSchedule<A> {
+    fn flat(maker: A, taker: A) -> Self {
+        FeeSchedule {
+            maker,
+            taker,
+            changes: Vec::new(),
+            min_fee: None,
+            max_fee: None,
+        }
+    }
 
-        let fills_vec = fills_by_order.get(&order_id);
-        let filled_size = fills_vec.map(|vec| {
-            vec.iter().fold(Decimal::ZERO, |acc, fill| acc + fill.size)
-        }).filter(|total| *total > Decimal::ZERO);
+    /// Fee on `notional` at `rate`, raised to the minimum and held to the
+    /// cap. Zero fees and rebates are left alone.
+    fn charge(&self, notional: A, rate: A) -> PyResult<A> {
+        let mut fee = notional.try_mul(rate)?;
+        if fee > A::ZERO {
+            if let Some(min_fee) = self.min_fee.filter(|min_fee| fee < *min_fee) {
+                fee = min_fee;
+            }
+            if let Some(max_fee) = self.max_fee.filter(|max_fee| fee > *max_fee) {
+                fee = max_fee;
+            }
+        }
+        Ok(fee)
+    }
 
-        let mut completed_time = if status != "OPEN" {
-            order
-                .completed_time
-                .as_deref()
-                .and_then(parse_datetime_text)
-        } else {
-            None
+    /// Rate in force at `at` for the given liquidity side.
+    fn rate(&self, at: DateTime<Utc>, post_only: bool) -> A {
+        let in_force = self.changes.partition_point(|(from, _, _)| *from <= at);
+        let (maker, taker) = match in_force.checked_sub(1) {
+            Some(index) => (self.changes[index].1, self.changes[index].2),
+            None => (self.maker, self.taker),
         };
-        if completed_time.is_none() {
-            if let Some(fills_vec) = fills_vec {
-                completed_time = fills_vec
-                    .iter()
-                    .filter_map(|fill| fill.trade_time)
-                    .last();
+        if post_only {
+            maker
+        } else {
+            taker
+        }
+    }
+}
+
+impl FeeSchedule {
+    fn parse(
+        maker_fee_rate: &str,
+        taker_fee_rate: &str,
+        changes: Option<Vec<FeeRateSpec>>,
+        min_fee: Option<&str>,
+        max_fee: Option<&str>,
+    ) -> PyResult<Self> {
+        let mut schedule = FeeSchedule::flat(
+            parse_decimal(maker_fee_rate, "maker_fee_rate")?,
+            parse_decimal(taker_fee_rate, "taker_fee_rate")?,
+        );
+        schedule.min_fee = min_fee.map(|fee| parse_decimal(fee, "min_fee")).transpose()?;
+        schedule.max_fee = max_fee.map(|fee| parse_decimal(fee, "max_fee")).transpose()?;
+        if let (Some(min_fee), Some(max_fee)) = (schedule.min_fee, schedule.max_fee) {
+            if min_fee > max_fee {
+                return Err(PyValueError::new_err(format!("min_fee {} exceeds max_fee {}", min_fee, max_fee)));
             }
         }
+        for spec in changes.unwrap_or_default() {
+            schedule.changes.push((
+                timestamp_us_to_datetime(spec.effective_from_timestamp_us)?,
+                parse_decimal(&spec.maker_fee_rate, "maker_fee_rate")?,
+                parse_decimal(&spec.taker_fee_rate, "taker_fee_rate")?,
+            ));
+        }
+        schedule.changes.sort_by_key(|(from, _, _)| *from);
+        Ok(schedule)
+    }
 
-        let (submitted_time, submitted_inferred) =
-            resolve_submitted_time(order, fills_vec, completed_time);
+    /// Decimal places needed to hold every rate exactly.
+    fn scale(&self) -> u32 {
+        self.changes
+            .iter()
+            .flat_map(|(_, maker, taker)| [maker.scale(), taker.scale()])
+            .fold(self.maker.scale().max(self.taker.scale()), u32::max)
+    }
 
-        let mut base_size = decimal_from_value(config.get("base_size"))
-            .or_else(|| decimal_from_value(config.get("base_order_size")))
-            .unwrap_or_else(|| Decimal::ZERO);
-        if (base_size == Decimal::ZERO || base_size.is_zero())
-            && filled_size.is_some()
-        {
-            base_size = filled_size.unwrap();
+    /// Rates with `decimals` places; the fee bounds are amounts, so they take
+    /// the fee scale of the replay instead.
+    fn to_fixed(&self, decimals: u32, fee_scale: u32) -> PyResult<FeeSchedule<i128>> {
+        let mut changes = Vec::with_capacity(self.changes.len());
+        for (from, maker, taker) in &self.changes {
+            changes.push((
+                *from,
+                to_fixed(*maker, decimals, "maker_fee_rate")?,
+                to_fixed(*taker, decimals, "taker_fee_rate")?,
+            ));
         }
+        Ok(FeeSchedule {
+            maker: to_fixed(self.maker, decimals, "maker_fee_rate")?,
+            taker: to_fixed(self.taker, decimals, "taker_fee_rate")?,
+            changes,
+            min_fee: self.min_fee.map(|fee| to_fixed(fee, fee_scale, "min_fee")).transpose()?,
+            max_fee: self.max_fee.map(|fee| to_fixed(fee, fee_scale, "max_fee")).transpose()?,
+        })
+    }
+}
 
-        let order_avg_price = order
-            .average_filled_price
-            .as_ref()
-            .and_then(|text| Decimal::from_str(text.trim()).ok());
+/// Which open lots a closing trade matches first.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum LotMethod {
+    #[default]
+    Fifo,
+    Lifo,
+}
 
-        let product_id = order
-            .product_id
-            .as_ref()
-            .and_then(|s| if s.is_empty() { None } else { Some(s.clone()) })
-            .unwrap_or_else(|| default_product_id.to_string());
+impl TryFrom<&str> for LotMethod {
+    type Error = PyErr;
 
-        let expire_time = order
-            .expire_time
-            .as_deref()
-            .and_then(parse_datetime_text);
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "fifo" => Ok(LotMethod::Fifo),
+            "lifo" => Ok(LotMethod::Lifo),
+            other => Err(PyValueError::new_err(format!("unknown lot method: {}", other))),
+        }
+    }
+}
 
-        let (limit_price, stop_price, end_time, post_only_flag) = match config_type {
-            OrderConfigType::Market => {
-                let limit_price = average_fill_price(fills_vec)
-                    .or(order_avg_price)
-                    .unwrap_or_else(|| Decimal::ZERO);
-                let end_time = completed_time.or(Some(submitted_time));
-                (limit_price, None, end_time, false)
-            }
-            OrderConfigType::TriggerBracket => {
-                let limit_price = decimal_from_value(config.get("limit_price"))
-                    .unwrap_or_else(|| Decimal::ZERO);
-                let stop_price = decimal_from_value(config.get("stop_trigger_price"))
-                    .or_else(|| decimal_from_value(config.get("stop_price")));
-                let end_time = parse_datetime_value(config.get("end_time"))
-                    .or(expire_time)
-                    .or(Some(submitted_time));
-                (limit_price, stop_price, end_time, false)
-            }
-            OrderConfigType::StopLimit => {
-                let limit_price = decimal_from_value(config.get("limit_price"))
-                    .unwrap_or_else(|| Decimal::ZERO);
-                let stop_price = decimal_from_value(config.get("stop_price"));
-                let end_time = parse_datetime_value(config.get("end_time"))
-                    .or(expire_time)
-                    .or(Some(submitted_time));
-                (limit_price, stop_price, end_time, false)
-            }
-            OrderConfigType::Limit => {
-                let limit_price = decimal_from_value(config.get("limit_price"))
-                    .unwrap_or_else(|| Decimal::ZERO);
-                let raw_post_only = parse_boolish(config.get("post_only"));
-                let post_only = raw_post_only.unwrap_or(false);
-                let end_time = parse_datetime_value(config.get("end_time"))
-                    .or(expire_time)
-                    .or(Some(submitted_time));
-                (limit_price, None, end_time, post_only)
-            }
-            OrderConfigType::Unknown => continue,
-        };
+/// How the replay decides whether a trade provided liquidity: from its
+/// `post_only` flag, or the same way for every trade.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum MakerClassification {
+    #[default]
+    PostOnly,
+    AllMaker,
+    AllTaker,
+}
 
-        if status == "OPEN" {
-            open_records.push(ProcessedOpenRecord {
-                order_id: order_id.clone(),
-                side: side_str.clone(),
-                limit_price,
-                base_size,
-                status: status.clone(),
-                client_order_id: client_order_id.clone(),
-                end_time,
-                product_id: product_id.clone(),
-                stop_price,
-            });
+impl MakerClassification {
+    fn is_maker(self, post_only: bool) -> bool {
+        match self {
+            MakerClassification::PostOnly => post_only,
+            MakerClassification::AllMaker => true,
+            MakerClassification::AllTaker => false,
         }
+    }
+}
 
-        executed_records.push(ProcessedExecutedRecord {
-            order_id,
-            ts_submitted: submitted_time,
-            ts_submitted_inferred: submitted_inferred,
-            ts_filled: completed_time,
-            side: side_str,
-            limit_price,
-            base_size,
-            status,
-            filled_size,
-            client_order_id,
-            end_time,
-            product_id,
-            stop_price,
-            post_only: matches!(config_type, OrderConfigType::Limit) && post_only_flag,
-        });
+impl TryFrom<&str> for MakerClassification {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "post_only" => Ok(MakerClassification::PostOnly),
+            "all_maker" => Ok(MakerClassification::AllMaker),
+            "all_taker" => Ok(MakerClassification::AllTaker),
+            other => Err(PyValueError::new_err(format!("unknown maker classification: {}", other))),
+        }
     }
+}
 
-    Ok((open_records, executed_records))
+/// What the matcher does with a sell larger than the long inventory.
+/// Inverse contracts are exempt, as shorting them is routine.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum OversellPolicy {
+    /// Open a short lot with the excess.
+    #[default]
+    AllowShort,
+    /// Close what is held and drop the excess; fees still apply to the
+    /// whole trade.
+    ClampToInventory,
+    Error,
 }
 
-#[pyfunction]
-fn process_orders_and_fills(
+impl TryFrom<&str> for OversellPolicy {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "allow_short" => Ok(OversellPolicy::AllowShort),
+            "clamp_to_inventory" => Ok(OversellPolicy::ClampToInventory),
+            "error" => Ok(OversellPolicy::Error),
+            other => Err(PyValueError::new_err(format!("unknown oversell policy: {}", other))),
+        }
+    }
+}
+
+impl OversellPolicy {
+    /// How much of a sell of `size` to book when it exceeds the `held` long
+    /// inventory: all of it, or only what is held; `Error` fails with the
+    /// size, time and holding. Callers count the oversell.
+    fn sell_size<A: Amount>(self, size: A, held: A, timestamp: DateTime<Utc>, scale: u32) -> PyResult<A> {
+        match self {
+            OversellPolicy::AllowShort => Ok(size),
+            OversellPolicy::ClampToInventory => Ok(held),
+            OversellPolicy::Error => Err(PyValueError::new_err(format!(
+                "sell of {} at {} exceeds held inventory of {}",
+                size.to_decimal(scale)?,
+                timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+                held.to_decimal(scale)?
+            ))),
+        }
+    }
+}
+
+pyo3::create_exception!(_pnl_rs, Cancelled, pyo3::exceptions::PyException, "A computation was stopped through its CancelToken.");
+
+/// Flag a caller trips from another thread (or a signal handler) to abort a
+/// running summarisation; the replay and order loops poll it. Extracting the
+/// inputs and building the Python output are not covered: a cancel that
+/// lands there takes effect only if a watched stage is still to come.
+#[pyclass(module = "_pnl_rs")]
+#[derive(Default)]
+struct CancelToken {
+    flag: Arc<AtomicBool>,
+}
+
+#[pymethods]
+impl CancelToken {
+    #[new]
+    fn new() -> Self {
+        CancelToken::default()
+    }
+
+    fn cancel(&self) {
+        self.flag.store(true, Ordering::Relaxed);
+    }
+
+    /// Re-arms the token for the next call.
+    fn reset(&self) {
+        self.flag.store(false, Ordering::Relaxed);
+    }
+
+    #[getter]
+    fn cancelled(&self) -> bool {
+        self.flag.load(Ordering::Relaxed)
+    }
+}
+
+/// State shared between a GIL-free stage and the thread watching it: the
+/// cancel flag (the token's, or a private one the stages still poll), how far
+/// the stage got, and the caller's progress callback.
+struct Watch {
+    cancel: Arc<AtomicBool>,
+    /// Whether the caller passed a `CancelToken`.
+    token: bool,
+    processed: AtomicUsize,
+    /// Items in the running stage; `processed` is clamped to it, so stages
+    /// that replay several books on a shared counter never overshoot.
+    total: AtomicUsize,
+    /// Items between polls: `WATCH_POLL_EVERY`, or `progress_every` if smaller.
+    poll_every: usize,
+    progress: Option<(PyObject, usize)>,
+}
+
+impl Watch {
+    fn new(token: Option<PyRef<'_, CancelToken>>, progress: Option<PyObject>, progress_every: usize) -> PyResult<Self> {
+        if progress_every == 0 {
+            return Err(PyValueError::new_err("progress_every must be positive"));
+        }
+        Ok(Watch {
+            token: token.is_some(),
+            cancel: token.map(|token| token.flag.clone()).unwrap_or_default(),
+            processed: AtomicUsize::new(0),
+            total: AtomicUsize::new(0),
+            poll_every: match progress {
+                Some(_) => progress_every.min(WATCH_POLL_EVERY),
+                None => WATCH_POLL_EVERY,
+            },
+            progress: progress.map(|callback| (callback, progress_every)),
+        })
+    }
+
+    fn cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Counts `items` more as processed, up to the stage total.
+    fn advance(&self, items: usize) {
+        let total = self.total.load(Ordering::Relaxed);
+        let _ = self
+            .processed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |processed| Some((processed + items).min(total)));
+    }
+
+    /// Whether anything needs the watching thread: a token or a callback.
+    fn watched(&self) -> bool {
+        self.token || self.progress.is_some()
+    }
+
+    /// Calls `progress(stage, processed, total)` when `processed` has moved
+    /// into a new multiple of `progress_every` since `reported`.
+    fn report(&self, py: Python<'_>, stage: &str, processed: usize, total: usize, reported: &mut usize) -> PyResult<()> {
+        let Some((callback, every)) = &self.progress else {
+            return Ok(());
+        };
+        if processed / every > *reported / every || (processed == total && *reported != total) {
+            *reported = processed;
+            callback.call1(py, (stage, processed, total))?;
+        }
+        Ok(())
+    }
+}
+
+/// Most trades or orders the loops process between looks at the watch.
+const WATCH_POLL_EVERY: usize = 1024;
+
+/// A loop's view of its `Watch`: `stop` is called with each item's index and
+/// `done` with the item count once the loop ends.
+#[derive(Clone, Copy, Default)]
+struct Tracker<'a>(Option<&'a Watch>);
+
+impl Tracker<'_> {
+    fn stop(self, index: usize) -> bool {
+        let Some(watch) = self.0.filter(|watch| index.is_multiple_of(watch.poll_every)) else {
+            return false;
+        };
+        if index > 0 {
+            watch.advance(watch.poll_every);
+        }
+        watch.cancelled()
+    }
+
+    /// Counts the items after the last poll.
+    fn done(self, count: usize) {
+        if let Some(watch) = self.0.filter(|_| count > 0) {
+            watch.advance(count - (count - 1) / watch.poll_every * watch.poll_every);
+        }
+    }
+}
+
+fn cancelled_error() -> PyErr {
+    Cancelled::new_err("computation cancelled")
+}
+
+/// Runs GIL-free `work`, one stage of `total` items, on a scoped thread while
+/// this one takes the GIL every 50ms to run Python signal handlers and report
+/// progress. A KeyboardInterrupt (or an error from a handler or the progress
+/// callback) trips the cancel flag and is raised once the work has wound
+/// down; otherwise an error caused by the flag surfaces as `Cancelled`.
+///
+/// Without a cancel token or progress callback the work runs inline with the
+/// GIL released, and Ctrl-C is only seen once it returns. Only `work` is
+/// watched: extraction before it and output building after it cannot be
+/// cancelled.
+fn run_watched<T: Send>(
+    py: Python<'_>,
+    watch: &Watch,
+    stage: &str,
+    total: usize,
+    work: impl FnOnce() -> PyResult<T> + Send,
+) -> PyResult<T> {
+    if !watch.watched() {
+        return py.allow_threads(work);
+    }
+    watch.total.store(total, Ordering::Relaxed);
+    watch.processed.store(0, Ordering::Relaxed);
+    let mut reported = 0;
+    let (outcome, interrupted) = std::thread::scope(|scope| {
+        let caller = std::thread::current();
+        let worker = scope.spawn(move || {
+            let outcome = work();
+            caller.unpark();
+            outcome
+        });
+        let mut interrupted = None;
+        while !worker.is_finished() {
+            py.allow_threads(|| std::thread::park_timeout(std::time::Duration::from_millis(50)));
+            if interrupted.is_none() {
+                let processed = watch.processed.load(Ordering::Relaxed);
+                let checked = py.check_signals().and_then(|()| watch.report(py, stage, processed, total, &mut reported));
+                if let Err(err) = checked {
+                    watch.cancel.store(true, Ordering::Relaxed);
+                    interrupted = Some(err);
+                }
+            }
+        }
+        let outcome = worker.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (outcome, interrupted)
+    });
+    match interrupted {
+        Some(err) => Err(err),
+        None if outcome.is_err() && watch.cancelled() => Err(cancelled_error()),
+        None => {
+            let value = outcome?;
+            watch.report(py, stage, total, total, &mut reported)?;
+            Ok(value)
+        }
+    }
+}
+
+/// Optional outputs the matcher should collect while replaying.
+#[derive(Clone, Copy, Default)]
+struct ReplayOptions<'a> {
+    position_series: bool,
+    base_pnl: bool,
+    match_ledger: bool,
+    oversell: OversellPolicy,
+    lot_method: LotMethod,
+    maker_classification: MakerClassification,
+    /// Charge the schedule's rates even on trades that carry the commission
+    /// actually paid, so what-if replays price every trade the same way.
+    ignore_commissions: bool,
+    watch: Tracker<'a>,
+}
+
+/// Everything the matcher produces from one replay of the trades.
+struct Replay {
+    entries: Vec<Entry>,
+    open_position: OpenPosition,
+    position_series: Vec<PositionPoint>,
+    matches: Vec<LotMatch>,
+    /// Sells that exceeded the long inventory, whatever the policy did with them.
+    oversold_count: usize,
+    /// When the trade that opened the current position executed: the last
+    /// move away from flat or flip through zero. None when flat.
+    position_opened_at: Option<DateTime<Utc>>,
+    /// Fixed-point replays only: every entry's amounts as mantissas, unless
+    /// one of them doesn't fit its scale.
+    fixed: Option<(Vec<EntryAmounts<i128>>, EntryScales)>,
+}
+
+impl Replay {
+    fn ledger(&self) -> Ledger<'_> {
+        Ledger {
+            entries: &self.entries,
+            fixed: self.fixed.as_ref().map(|(amounts, scales)| (amounts.as_slice(), *scales)),
+        }
+    }
+}
+
+fn build_entries<A: Amount>(
+    trades: &[Trade<A>],
+    fees: &FeeSchedule<A>,
+    scales: EntryScales,
+    options: ReplayOptions<'_>,
+) -> PyResult<Replay> {
+    let mut book: Book<A> = Book {
+        lifo: options.lot_method == LotMethod::Lifo,
+        ..Book::default()
+    };
+    let mut entries: Vec<Entry> = Vec::with_capacity(trades.len());
+    let mut position_series: Vec<PositionPoint> = Vec::new();
+    if options.position_series {
+        position_series.reserve(trades.len());
+    }
+    let mut ledger: Vec<LotMatch<A>> = Vec::new();
+    let mut oversold_count = 0;
+    let mut position_opened_at = None;
+    let mut fixed = A::FIXED.then(|| Vec::with_capacity(trades.len()));
+    let zero = A::ZERO;
+
+    for (index, trade) in trades.iter().enumerate() {
+        if options.watch.stop(index) {
+            return Err(cancelled_error());
+        }
+        let is_maker = options.maker_classification.is_maker(trade.post_only);
+        let fee_rate = fees.rate(trade.timestamp, is_maker);
+        let (booked_side, booked_price) = book.booked(trade)?;
+        let mut booked_size = trade.size;
+        let held = book.long_lots.size;
+        let oversold = !trade.inverse && trade.side == Side::Sell && booked_size > held;
+        if oversold {
+            oversold_count += 1;
+            booked_size = options.oversell.sell_size(trade.size, held, trade.timestamp, scales.size)?;
+        }
+        let notional = booked_price.try_mul(trade.size)?;
+        let maker_volume = if is_maker { notional } else { zero };
+        let taker_volume = if is_maker { zero } else { notional };
+        let fee = fees.charge(notional, fee_rate)?;
+        let commission = trade.commission.filter(|_| !options.ignore_commissions);
+        let commission_currency = trade.commission_currency.as_ref().filter(|_| commission.is_some());
+        let fee = match commission {
+            Some(commission) => commission,
+            None => fee.to_decimal(scales.fee)?,
+        };
+        // Inverse contracts settle in the base currency.
+        let quote = trade
+            .product_id
+            .as_deref()
+            .and_then(product_currencies)
+            .map(|(base, quote)| if trade.inverse { Cow::Borrowed(base) } else { quote });
+        // Without a product the quote is unknown, so the fee is taken to be
+        // in it rather than set aside as foreign.
+        let (fee, foreign_fee) = match (commission_currency, &quote) {
+            (Some(currency), Some(quote)) if !currency.eq_ignore_ascii_case(quote) => (Decimal::ZERO, fee),
+            _ => (fee, Decimal::ZERO),
+        };
+        let side_before = book.active().0;
+        let mut holding = SmallVec::new();
+        let realized = book.execute_traced(
+            booked_side,
+            booked_price,
+            booked_size,
+            fee,
+            index,
+            trade.timestamp.timestamp_micros(),
+            options.match_ledger.then_some(&mut ledger),
+            Some(&mut holding),
+        )?;
+        let holding_us = holding
+            .into_iter()
+            .map(|(held_us, size)| Ok((held_us, size.to_decimal(scales.size)?)))
+            .collect::<PyResult<_>>()?;
+        let side_after = book.active().0;
+        if side_after != side_before {
+            position_opened_at = side_after.map(|_| trade.timestamp);
+        }
+        let closed_position = side_before.is_some() && side_after != side_before;
+        let held_side = side_before.map(|side| if trade.inverse { side.opposite() } else { side });
+        if options.position_series {
+            position_series.push(book.position_point(trade.timestamp, scales)?);
+        }
+
+        let (_, exposure) = book.active();
+        let position = match side_after {
+            Some(Side::Buy) => exposure.size.to_decimal(scales.size)?,
+            Some(Side::Sell) => -exposure.size.to_decimal(scales.size)?,
+            None => Decimal::ZERO,
+        };
+        let realized_profit = realized.to_decimal(scales.notional)?;
+        let (realized_profit_base, fee_base) = if !options.base_pnl {
+            (Decimal::ZERO, Decimal::ZERO)
+        } else if trade.inverse {
+            (realized_profit, fee)
+        } else {
+            let price = trade.price.to_decimal(scales.price)?;
+            (realized_profit / price, fee / price)
+        };
+
+        entries.push(Entry {
+            timestamp: trade.timestamp,
+            side: trade.side,
+            size: trade.size.to_decimal(scales.size)?,
+            realized_profit,
+            is_maker,
+            maker_volume: maker_volume.to_decimal(scales.notional)?,
+            taker_volume: taker_volume.to_decimal(scales.notional)?,
+            fee,
+            fee_currency: commission_currency.cloned().or_else(|| quote.map(Arc::from)),
+            foreign_fee,
+            realized_profit_base,
+            fee_base,
+            exposure_size: if trade.inverse {
+                exposure.cost.to_decimal(scales.notional)?
+            } else {
+                exposure.size.to_decimal(scales.size)?
+            },
+            exposure_cost: if trade.inverse {
+                exposure.size.to_decimal(scales.size)?
+            } else {
+                exposure.cost.to_decimal(scales.notional)?
+            },
+            position,
+            inverse: trade.inverse,
+            oversold,
+            closed_position,
+            held_side,
+            holding_us,
+        });
+        if fixed.is_some() {
+            match entries.last().and_then(|entry| entry.fixed_amounts(scales)) {
+                Some(amounts) => fixed.iter_mut().for_each(|rows| rows.push(amounts)),
+                None => fixed = None,
+            }
+        }
+    }
+    options.watch.done(trades.len());
+
+    let mut matches = Vec::with_capacity(ledger.len());
+    for matched in ledger {
+        matches.push(LotMatch {
+            open_trade: matched.open_trade,
+            close_trade: matched.close_trade,
+            size: matched.size.to_decimal(scales.size)?,
+            open_price: matched.open_price.to_decimal(scales.price)?,
+            close_price: matched.close_price.to_decimal(scales.price)?,
+            realized: matched.realized.to_decimal(scales.notional)?,
+        });
+    }
+
+    Ok(Replay {
+        entries,
+        open_position: book.open_position(scales)?,
+        position_series,
+        matches,
+        oversold_count,
+        position_opened_at,
+        fixed: fixed.map(|rows| (rows, scales)),
+    })
+}
+
+fn to_fixed(value: Decimal, decimals: u32, label: &str) -> PyResult<i128> {
+    let mut scaled = value;
+    scaled.rescale(decimals);
+    if scaled != value || scaled.scale() != decimals {
+        return Err(PyValueError::new_err(format!(
+            "{} {} does not fit {} fixed-point decimals",
+            label, value, decimals
+        )));
+    }
+    Ok(scaled.mantissa())
+}
+
+/// Runs the matcher on i128 mantissas with `price_decimals` / `size_decimals`
+/// places; Decimals are only rebuilt (without multiplication) for each entry.
+fn build_entries_fixed(
+    trades: &[Trade],
+    fees: &FeeSchedule,
+    price_decimals: u32,
+    size_decimals: u32,
+    options: ReplayOptions<'_>,
+) -> PyResult<Replay> {
+    let fee_decimals = fees.scale();
+    let scales = EntryScales {
+        price: price_decimals,
+        size: size_decimals,
+        notional: price_decimals + size_decimals,
+        fee: price_decimals + size_decimals + fee_decimals,
+    };
+    if scales.fee > Decimal::MAX_SCALE {
+        return Err(PyValueError::new_err(format!(
+            "fixed-point decimals too large: price {} + size {} + fee {} exceeds {}",
+            price_decimals,
+            size_decimals,
+            fee_decimals,
+            Decimal::MAX_SCALE
+        )));
+    }
+
+    let mut fixed_trades: Vec<Trade<i128>> = Vec::with_capacity(trades.len());
+    for trade in trades {
+        fixed_trades.push(Trade {
+            timestamp: trade.timestamp,
+            side: trade.side,
+            price: to_fixed(trade.price, price_decimals, "price")?,
+            size: to_fixed(trade.size, size_decimals, "size")?,
+            post_only: trade.post_only,
+            product_id: trade.product_id.clone(),
+            leg_group: trade.leg_group.clone(),
+            trade_id: trade.trade_id.clone(),
+            input_index: trade.input_index,
+            commission: trade.commission,
+            commission_currency: trade.commission_currency.clone(),
+            inverse: trade.inverse,
+        });
+    }
+
+    build_entries(&fixed_trades, &fees.to_fixed(fee_decimals, scales.fee)?, scales, options)
+}
+
+/// Trailing windows, in days, of the `rolling_volume` series.
+const ROLLING_VOLUME_DAYS: [i64; 2] = [7, 30];
+
+/// Maker and taker volume over the trailing `ROLLING_VOLUME_DAYS`, sampled at
+/// each UTC midnight after the first trade and once more at `now`, for
+/// volume-based fee tiers and alerts.
+fn rolling_volume_to_py<'py>(
+    py: Python<'py>,
+    entries: &[Entry],
+    now: DateTime<Utc>,
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    // Prefix sums, so each window is a difference of two lookups.
+    let mut cumulative = Vec::with_capacity(entries.len() + 1);
+    cumulative.push((Decimal::ZERO, Decimal::ZERO));
+    for entry in entries {
+        let (maker, taker) = cumulative[cumulative.len() - 1];
+        cumulative.push((maker + entry.maker_volume, taker + entry.taker_volume));
+    }
+    let volume_until = |at: DateTime<Utc>| cumulative[entries.partition_point(|entry| entry.timestamp <= at)];
+
+    let mut samples = Vec::new();
+    if let Some(first) = entries.first().filter(|first| first.timestamp <= now) {
+        let mut midnight = first.timestamp.date_naive().and_time(NaiveTime::MIN).and_utc();
+        loop {
+            midnight += Duration::days(1);
+            if midnight >= now {
+                break;
+            }
+            samples.push(midnight);
+        }
+        samples.push(now);
+    }
+
+    let series = PyList::empty_bound(py);
+    for at in samples {
+        let (maker_total, taker_total) = volume_until(at);
+        let dict = PyDict::new_bound(py);
+        dict.set_item("timestamp_us", at.timestamp_micros())?;
+        for days in ROLLING_VOLUME_DAYS {
+            let (maker_before, taker_before) = volume_until(at - Duration::days(days));
+            dict.set_item(format!("maker_volume_{days}d"), output.value(py, maker_total - maker_before))?;
+            dict.set_item(format!("taker_volume_{days}d"), output.value(py, taker_total - taker_before))?;
+        }
+        series.append(dict)?;
+    }
+    Ok(series)
+}
+
+/// The amounts of one entry the interval sums run on. In fixed-point mode
+/// these are mantissas: realized profit and fee at the fee scale (so they net
+/// without rescaling), volumes and cost at the notional scale, sizes at the
+/// size scale.
+#[derive(Clone, Copy, Default)]
+struct EntryAmounts<A = Decimal> {
+    size: A,
+    realized: A,
+    fee: A,
+    maker_volume: A,
+    taker_volume: A,
+    exposure_size: A,
+    exposure_cost: A,
+}
+
+impl Entry {
+    fn amounts(&self) -> EntryAmounts {
+        EntryAmounts {
+            size: self.size,
+            realized: self.realized_profit,
+            fee: self.fee,
+            maker_volume: self.maker_volume,
+            taker_volume: self.taker_volume,
+            exposure_size: self.exposure_size,
+            exposure_cost: self.exposure_cost,
+        }
+    }
+
+    /// The entry's amounts as mantissas at `scales`; None when one has more
+    /// places than its scale holds, e.g. a commission finer than the fee scale.
+    fn fixed_amounts(&self, scales: EntryScales) -> Option<EntryAmounts<i128>> {
+        Some(EntryAmounts {
+            size: mantissa_at(self.size, scales.size)?,
+            realized: mantissa_at(self.realized_profit, scales.fee)?,
+            fee: mantissa_at(self.fee, scales.fee)?,
+            maker_volume: mantissa_at(self.maker_volume, scales.notional)?,
+            taker_volume: mantissa_at(self.taker_volume, scales.notional)?,
+            exposure_size: mantissa_at(self.exposure_size, scales.size)?,
+            exposure_cost: mantissa_at(self.exposure_cost, scales.notional)?,
+        })
+    }
+}
+
+/// Size-weighted mean and median of (held µs, size closed) pairs. The median
+/// is the time at which half the closed size is reached, averaged with the
+/// next time when that lands exactly on a boundary.
+fn holding_times(holdings: &mut [(i64, Decimal)]) -> PyResult<(Option<i64>, Option<i64>)> {
+    let (mut total, mut weighted) = (Decimal::ZERO, Decimal::ZERO);
+    for (held, size) in holdings.iter() {
+        total = total.try_add(*size)?;
+        weighted = weighted.try_add(Decimal::from(*held).try_mul(*size)?)?;
+    }
+    if total <= Decimal::ZERO {
+        return Ok((None, None));
+    }
+    let mean = weighted.checked_div(total).ok_or_else(amount_overflow)?.round().to_i64();
+    holdings.sort_unstable_by_key(|(held, _)| *held);
+    let mut closed = Decimal::ZERO;
+    let mut median = None;
+    for (index, (held, size)) in holdings.iter().enumerate() {
+        closed = closed.try_add(*size)?;
+        let doubled = closed.try_mul(Decimal::TWO)?;
+        if doubled >= total {
+            median = match holdings.get(index + 1) {
+                Some((next, _)) if doubled == total => Some(((i128::from(*held) + i128::from(*next)) / 2) as i64),
+                _ => Some(*held),
+            };
+            break;
+        }
+    }
+    Ok((mean, median))
+}
+
+/// `value` as a mantissa at `scale`, if it has no more places than that.
+fn mantissa_at(value: Decimal, scale: u32) -> Option<i128> {
+    let value = if value.scale() > scale { value.normalize() } else { value };
+    let shift = scale.checked_sub(value.scale())?;
+    value.mantissa().checked_mul(10i128.checked_pow(shift)?)
+}
+
+/// Replay entries plus, from a fixed-point replay, their mantissas so
+/// interval sums stay on integers until the output.
+#[derive(Clone, Copy)]
+struct Ledger<'a> {
+    entries: &'a [Entry],
+    fixed: Option<(&'a [EntryAmounts<i128>], EntryScales)>,
+}
+
+impl<'a> Ledger<'a> {
+    fn decimal(entries: &'a [Entry]) -> Self {
+        Ledger { entries, fixed: None }
+    }
+
+    /// The entries at or before `end`.
+    fn until(self, end: DateTime<Utc>) -> Self {
+        let known = self.entries.partition_point(|entry| entry.timestamp <= end);
+        Ledger {
+            entries: &self.entries[..known],
+            fixed: self.fixed.map(|(amounts, scales)| (&amounts[..known], scales)),
+        }
+    }
+}
+
+fn summarise_interval(ledger: Ledger<'_>, start: DateTime<Utc>, end: DateTime<Utc>) -> PyResult<RawMetrics> {
+    match ledger.fixed {
+        Some((amounts, scales)) => summarise_amounts(ledger.entries, |index| amounts[index], scales, start, end),
+        None => summarise_amounts(ledger.entries, |index| ledger.entries[index].amounts(), EntryScales::default(), start, end),
+    }
+}
+
+/// Aggregates entries from `start` onwards. `end` bounds the time-weighted
+/// exposure window; exposure is valued at cost basis since no marks are known.
+fn summarise_amounts<A: Amount>(
+    entries: &[Entry],
+    amounts: impl Fn(usize) -> EntryAmounts<A>,
+    scales: EntryScales,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> PyResult<RawMetrics> {
+    let zero = A::ZERO;
+    let mut profit_before = zero;
+    let mut maker_volume = zero;
+    let mut taker_volume = zero;
+    let mut fee_total = zero;
+    let mut fees_paid = zero;
+    let mut rebates_received = zero;
+    let (mut maker_fees, mut taker_fees) = (zero, zero);
+    let (mut saw_inverse, mut saw_linear) = (false, false);
+    let mut fees_by_currency: BTreeMap<String, Decimal> = BTreeMap::new();
+    let mut profit_before_base = Decimal::ZERO;
+    let mut fee_total_base = Decimal::ZERO;
+    let mut buy_count = 0;
+    let mut sell_count = 0;
+    let (mut buy_size, mut buy_notional) = (zero, zero);
+    let (mut sell_size, mut sell_notional) = (zero, zero);
+    let mut peak = zero;
+    let mut max_drawdown = zero;
+    let mut inventory_cycles = 0;
+    let (mut long_profit, mut short_profit) = (zero, zero);
+    let mut holdings: Vec<(i64, Decimal)> = Vec::new();
+
+    let first = entries.partition_point(|entry| entry.timestamp < start);
+    // Inverse exposure is carried as base value, like inverse volume.
+    let carried_inverse = first.checked_sub(1).map(|index| entries[index].inverse);
+    let mut inverse = carried_inverse.unwrap_or(false);
+    let (mut size, mut cost) = match first.checked_sub(1).map(&amounts) {
+        Some(previous) => (previous.exposure_size, previous.exposure_cost),
+        None => (zero, zero),
+    };
+    let mut size_time = zero;
+    let mut cost_time = zero;
+    let mut last_time = start;
+
+    for (index, entry) in entries.iter().enumerate().skip(first) {
+        let amount = amounts(index);
+        profit_before = profit_before.try_add(amount.realized)?;
+        maker_volume = maker_volume.try_add(amount.maker_volume)?;
+        taker_volume = taker_volume.try_add(amount.taker_volume)?;
+        inventory_cycles += usize::from(entry.closed_position);
+        match entry.held_side {
+            Some(Side::Buy) => long_profit = long_profit.try_add(amount.realized)?,
+            Some(Side::Sell) => short_profit = short_profit.try_add(amount.realized)?,
+            None => {}
+        }
+        holdings.extend_from_slice(&entry.holding_us);
+        inverse = entry.inverse;
+        saw_inverse |= entry.inverse;
+        saw_linear |= !entry.inverse;
+        fee_total = fee_total.try_add(amount.fee)?;
+        if amount.fee < zero {
+            rebates_received = rebates_received.try_sub(amount.fee)?;
+        } else {
+            fees_paid = fees_paid.try_add(amount.fee)?;
+        }
+        if entry.is_maker {
+            maker_fees = maker_fees.try_add(amount.fee)?;
+        } else {
+            taker_fees = taker_fees.try_add(amount.fee)?;
+        }
+        let currency = entry.fee_currency.as_deref().unwrap_or("quote");
+        *fees_by_currency.entry(currency.to_string()).or_default() += entry.fee + entry.foreign_fee;
+        let cumulative = profit_before.try_sub(fee_total)?;
+        if cumulative > peak {
+            peak = cumulative;
+        }
+        let drawdown = peak.try_sub(cumulative)?;
+        if drawdown > max_drawdown {
+            max_drawdown = drawdown;
+        }
+        profit_before_base += entry.realized_profit_base;
+        fee_total_base += entry.fee_base;
+        let notional = amount.maker_volume.try_add(amount.taker_volume)?;
+        // Inverse volume is in base units, so weigh the other way round to
+        // get the harmonic mean price.
+        let (size_traded, notional) = if entry.inverse { (notional, amount.size) } else { (amount.size, notional) };
+        match entry.side {
+            Side::Buy => {
+                buy_count += 1;
+                buy_size = buy_size.try_add(size_traded)?;
+                buy_notional = buy_notional.try_add(notional)?;
+            }
+            Side::Sell => {
+                sell_count += 1;
+                sell_size = sell_size.try_add(size_traded)?;
+                sell_notional = sell_notional.try_add(notional)?;
+            }
+        }
+
+        let at = entry.timestamp.min(end);
+        if at > last_time {
+            let elapsed = A::from_i64((at - last_time).num_microseconds().unwrap_or(i64::MAX));
+            size_time = size_time.try_add(size.try_mul(elapsed)?)?;
+            cost_time = cost_time.try_add(cost.try_mul(elapsed)?)?;
+            last_time = at;
+        }
+        size = amount.exposure_size;
+        cost = amount.exposure_cost;
+    }
+
+    // Everything below is output: back to Decimal.
+    let window = (end - start).num_microseconds().unwrap_or(i64::MAX);
+    let (avg_exposure_base, avg_exposure_notional) = if window > 0 {
+        let elapsed = A::from_i64((end - last_time).num_microseconds().unwrap_or(0).max(0));
+        size_time = size_time.try_add(size.try_mul(elapsed)?)?;
+        cost_time = cost_time.try_add(cost.try_mul(elapsed)?)?;
+        let window = Decimal::from(window);
+        (size_time.to_decimal(scales.size)? / window, cost_time.to_decimal(scales.notional)? / window)
+    } else {
+        (size.to_decimal(scales.size)?, cost.to_decimal(scales.notional)?)
+    };
+
+    let maker_volume = maker_volume.to_decimal(scales.notional)?;
+    let taker_volume = taker_volume.to_decimal(scales.notional)?;
+    let average_exposure = if inverse { avg_exposure_base } else { avg_exposure_notional };
+    // Exposure carried in from before the window counts towards the units too.
+    let mixed_units = saw_inverse && saw_linear;
+    let exposure_mixed = mixed_units
+        || carried_inverse.is_some_and(|carried| if carried { saw_linear } else { saw_inverse });
+    let turnover = (!average_exposure.is_zero() && !exposure_mixed)
+        .then(|| ((maker_volume + taker_volume) / average_exposure).round_dp(4).normalize());
+    let (avg_holding_us, median_holding_us) = holding_times(&mut holdings)?;
+    let (buy_size, buy_notional) = (buy_size.to_decimal(scales.size)?, buy_notional.to_decimal(scales.notional)?);
+    let (sell_size, sell_notional) = (sell_size.to_decimal(scales.size)?, sell_notional.to_decimal(scales.notional)?);
+    let profit_before = profit_before.to_decimal(scales.fee)?;
+    let fee_total = fee_total.to_decimal(scales.fee)?;
+    Ok(RawMetrics {
+        profit_before_fees: profit_before,
+        maker_volume,
+        taker_volume,
+        fee_total,
+        fees_paid: fees_paid.to_decimal(scales.fee)?,
+        rebates_received: rebates_received.to_decimal(scales.fee)?,
+        maker_fees: maker_fees.to_decimal(scales.fee)?,
+        taker_fees: taker_fees.to_decimal(scales.fee)?,
+        mixed_units,
+        fees_by_currency,
+        profit_after_fees: profit_before - fee_total,
+        profit_before_fees_base: profit_before_base,
+        profit_after_fees_base: profit_before_base - fee_total_base,
+        avg_exposure_base,
+        avg_exposure_notional,
+        turnover,
+        inventory_cycles,
+        long_profit: long_profit.to_decimal(scales.fee)?,
+        short_profit: short_profit.to_decimal(scales.fee)?,
+        avg_holding_us,
+        median_holding_us,
+        buy_count,
+        sell_count,
+        avg_buy_price: (buy_size > Decimal::ZERO).then(|| buy_notional / buy_size),
+        avg_sell_price: (sell_size > Decimal::ZERO).then(|| sell_notional / sell_size),
+        max_drawdown: max_drawdown.to_decimal(scales.fee)?,
+        funding_pnl: None,
+        borrow_cost: None,
+    })
+}
+
+/// A dated cash amount outside the trade PnL, e.g. a funding settlement.
+struct CashFlow {
+    timestamp: DateTime<Utc>,
+    amount: Decimal,
+}
+
+/// Settles each funding entry against the position the lot book held at its
+/// timestamp (trades at the same microsecond included), so a sell clamped by
+/// the oversell policy only counts what was booked. Inverse contracts are
+/// booked as the opposite side and pay on their base value, in base units.
+/// Needs trades of a single product, as there is one funding series.
+fn funding_payments(entries: &[Entry], funding: Vec<FundingSpec>) -> PyResult<Vec<CashFlow>> {
+    let mut parsed = Vec::with_capacity(funding.len());
+    for spec in funding {
+        let mark_price = parse_decimal(&spec.mark_price, "funding mark_price")?;
+        if mark_price <= Decimal::ZERO {
+            return Err(PyValueError::new_err(format!("funding mark_price must be positive: {}", mark_price)));
+        }
+        parsed.push((
+            timestamp_us_to_datetime(spec.timestamp_us)?,
+            parse_decimal(&spec.rate, "funding rate")?,
+            mark_price,
+        ));
+    }
+    parsed.sort_by_key(|(timestamp, _, _)| *timestamp);
+
+    let mut payments = Vec::with_capacity(parsed.len());
+    let mut applied = 0;
+    let (mut position, mut inverse) = (Decimal::ZERO, false);
+    for (timestamp, rate, mark_price) in parsed {
+        while let Some(entry) = entries.get(applied).filter(|entry| entry.timestamp <= timestamp) {
+            (position, inverse) = (entry.position, entry.inverse);
+            applied += 1;
+        }
+        // Longs pay positive rates; an inverse long is booked short.
+        let amount = if inverse { position * rate / mark_price } else { -(position * mark_price * rate) };
+        payments.push(CashFlow { timestamp, amount });
+    }
+    Ok(payments)
+}
+
+/// Interest accrued at every UTC midnight from `first` to `last` inclusive
+/// at a constant `daily` amount: one stretch over which neither the short
+/// position nor the borrow rate changes.
+struct BorrowAccrual {
+    first: DateTime<Utc>,
+    last: DateTime<Utc>,
+    daily: Decimal,
+}
+
+/// Borrow costs as explicit venue charges plus rate-schedule accruals.
+#[derive(Default)]
+struct BorrowCharges {
+    charges: Vec<CashFlow>,
+    accruals: Vec<BorrowAccrual>,
+}
+
+impl BorrowCharges {
+    fn sum(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Decimal {
+        let mut total = sum_cash_flows(&self.charges, start, end);
+        let Some(from) = midnight_at_or_after(start) else {
+            return total;
+        };
+        let until = midnight_at_or_before(end);
+        for accrual in &self.accruals {
+            let first = accrual.first.max(from);
+            let last = accrual.last.min(until);
+            if last >= first {
+                total += accrual.daily * Decimal::from((last - first).num_days() + 1);
+            }
+        }
+        total
+    }
+}
+
+fn midnight_at_or_before(timestamp: DateTime<Utc>) -> DateTime<Utc> {
+    timestamp.date_naive().and_time(NaiveTime::MIN).and_utc()
+}
+
+fn midnight_at_or_after(timestamp: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let midnight = midnight_at_or_before(timestamp);
+    if midnight == timestamp {
+        Some(midnight)
+    } else {
+        midnight.checked_add_signed(Duration::days(1))
+    }
+}
+
+/// Converts borrow inputs into dated costs. Explicit charges are taken as
+/// given; a rate schedule accrues a day's interest on the cost basis of the
+/// short lots the replay holds at every UTC midnight up to `now`, as venues
+/// charge overnight. A sell clamped by the oversell policy opens no short,
+/// and inverse contracts borrow nothing.
+/// Accruals are integrated per stretch of constant position and rate, so
+/// the work is bounded by the number of trades and rate changes.
+fn borrow_charges(
+    entries: &[Entry],
+    costs: Vec<BorrowCostSpec>,
+    rates: Vec<BorrowRateSpec>,
+    now: DateTime<Utc>,
+) -> PyResult<BorrowCharges> {
+    let mut charges = Vec::with_capacity(costs.len());
+    for cost in costs {
+        charges.push(CashFlow {
+            timestamp: timestamp_us_to_datetime(cost.timestamp_us)?,
+            amount: parse_decimal(&cost.amount, "borrow amount")?,
+        });
+    }
+    charges.sort_by_key(|charge| charge.timestamp);
+
+    let mut schedule = Vec::with_capacity(rates.len());
+    for rate in rates {
+        schedule.push((
+            timestamp_us_to_datetime(rate.timestamp_us)?,
+            parse_decimal(&rate.annual_rate, "borrow annual_rate")?,
+        ));
+    }
+    schedule.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let mut accruals = Vec::new();
+    let first_midnight = schedule
+        .first()
+        .and_then(|(first_effective, _)| midnight_at_or_before(*first_effective).checked_add_signed(Duration::days(1)));
+    if let Some(mut midnight) = first_midnight {
+        let days_per_year = Decimal::from(365);
+        let last_midnight = midnight_at_or_before(now);
+        let mut applied = 0;
+        let mut short_cost = Decimal::ZERO;
+        while midnight <= last_midnight {
+            while let Some(entry) = entries.get(applied).filter(|entry| entry.timestamp <= midnight) {
+                short_cost = if !entry.inverse && entry.position < Decimal::ZERO {
+                    entry.exposure_cost
+                } else {
+                    Decimal::ZERO
+                };
+                applied += 1;
+            }
+            let active = schedule.partition_point(|(timestamp, _)| *timestamp <= midnight);
+            let annual_rate = schedule[active - 1].1;
+            let next_change = [entries.get(applied).map(|entry| entry.timestamp), schedule.get(active).map(|(timestamp, _)| *timestamp)]
+                .into_iter()
+                .flatten()
+                .min();
+            // The state holds for every midnight before the next change.
+            let next_midnight = next_change.and_then(midnight_at_or_after);
+            let last = match next_midnight {
+                Some(next) => (next - Duration::days(1)).min(last_midnight),
+                None => last_midnight,
+            };
+            if short_cost > Decimal::ZERO && !annual_rate.is_zero() {
+                accruals.push(BorrowAccrual {
+                    first: midnight,
+                    last,
+                    daily: short_cost * annual_rate / days_per_year,
+                });
+            }
+            match next_midnight {
+                Some(next) => midnight = next,
+                None => break,
+            }
+        }
+    }
+
+    Ok(BorrowCharges { charges, accruals })
+}
+
+/// Dated flows outside trade PnL that interval reports fold in as their own
+/// line items.
+#[derive(Default)]
+struct CarryFlows {
+    funding: Option<Vec<CashFlow>>,
+    borrow: Option<BorrowCharges>,
+}
+
+fn sum_cash_flows(flows: &[CashFlow], start: DateTime<Utc>, end: DateTime<Utc>) -> Decimal {
+    let first = flows.partition_point(|flow| flow.timestamp < start);
+    flows[first..]
+        .iter()
+        .take_while(|flow| flow.timestamp <= end)
+        .map(|flow| flow.amount)
+        .sum()
+}
+
+/// One interval's PnL marked to mid and split three ways: spread capture
+/// (each fill's edge against mid at fill time), inventory revaluation (mid
+/// drift on the position held between fills) and fees. Positions come from
+/// the replay's lot book, so oversell clamping is honoured, and inverse
+/// contracts are valued as booked (at 1/price), giving base-unit figures.
+/// Needs trades of a single product, as there is one mid series.
+#[derive(Default)]
+struct PnlDecomposition {
+    spread_capture: Decimal,
+    inventory_pnl: Decimal,
+    fees: Decimal,
+    /// Fills with no positive mid at or before their time; their edge is not
+    /// counted.
+    unpriced_trades: u64,
+}
+
+impl PnlDecomposition {
+    /// `trades` and `entries` are the replay's inputs and outputs, index for index.
+    fn build(trades: &[Trade], entries: &[Entry], mids: &PriceSeries, start: DateTime<Utc>, end: DateTime<Utc>) -> Self {
+        let mut result = PnlDecomposition::default();
+        // Prices as booked: inverse positions are short 1/price for a buy.
+        let booked = |price: Decimal, inverse: bool| if inverse { Decimal::ONE / price } else { price };
+        let first = trades.partition_point(|trade| trade.timestamp < start);
+        let mid_at = |timestamp| mids.series_at(timestamp).filter(|mid| *mid > Decimal::ZERO);
+        let mut position = first.checked_sub(1).map_or(Decimal::ZERO, |last| entries[last].position);
+        let mut last_mid = mid_at(start);
+        for (trade, entry) in trades[first..].iter().zip(&entries[first..]) {
+            if trade.timestamp > end {
+                break;
+            }
+            let mid = mid_at(trade.timestamp);
+            if let (Some(previous), Some(mid)) = (last_mid, mid) {
+                result.inventory_pnl += position * (booked(mid, trade.inverse) - booked(previous, trade.inverse));
+            }
+            match mid {
+                Some(mid) => {
+                    let moved = entry.position - position;
+                    result.spread_capture +=
+                        (booked(mid, trade.inverse) - booked(trade.price, trade.inverse)) * moved;
+                    last_mid = Some(mid);
+                }
+                None => result.unpriced_trades += 1,
+            }
+            result.fees += entry.fee;
+            position = entry.position;
+        }
+        let inverse = entries.last().is_some_and(|entry| entry.inverse);
+        if let (Some(previous), Some(mid)) = (last_mid, mid_at(end)) {
+            result.inventory_pnl += position * (booked(mid, inverse) - booked(previous, inverse));
+        }
+        result
+    }
+
+    fn to_py<'py>(&self, py: Python<'py>, spec: &IntervalSpec, output: OutputOptions) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("key", &spec.key)?;
+        dict.set_item("label", spec.label())?;
+        dict.set_item("spread_capture", output.value(py, self.spread_capture))?;
+        dict.set_item("inventory_pnl", output.value(py, self.inventory_pnl))?;
+        dict.set_item("fees", output.value(py, self.fees))?;
+        dict.set_item("total", output.value(py, self.spread_capture + self.inventory_pnl - self.fees))?;
+        dict.set_item("unpriced_trades", self.unpriced_trades)?;
+        Ok(dict)
+    }
+}
+
+/// Formatting switches applied when metrics are converted to Python.
+#[derive(Clone, Copy, Default)]
+struct OutputOptions {
+    base_pnl: bool,
+    fees_by_currency: bool,
+    /// Emit amounts as floats instead of exact decimal strings.
+    floats: bool,
+}
+
+impl OutputOptions {
+    fn value(&self, py: Python<'_>, amount: Decimal) -> PyObject {
+        if self.floats {
+            amount.to_f64().unwrap_or(f64::NAN).into_py(py)
+        } else {
+            amount.to_string().into_py(py)
+        }
+    }
+}
+
+impl RawMetrics {
+    fn to_py<'py>(&self, py: Python<'py>, spec: &IntervalSpec, output: OutputOptions) -> PyResult<Bound<'py, PyDict>> {
+        let interval_dict = PyDict::new_bound(py);
+        interval_dict.set_item("key", &spec.key)?;
+        interval_dict.set_item("label", spec.label())?;
+        interval_dict.set_item("profit_before_fees", output.value(py, self.profit_before_fees))?;
+        interval_dict.set_item("long_profit_before_fees", output.value(py, self.long_profit))?;
+        interval_dict.set_item("short_profit_before_fees", output.value(py, self.short_profit))?;
+        interval_dict.set_item("maker_volume", output.value(py, self.maker_volume))?;
+        interval_dict.set_item("taker_volume", output.value(py, self.taker_volume))?;
+        interval_dict.set_item("fee_total", output.value(py, self.fee_total))?;
+        interval_dict.set_item("fees_paid", output.value(py, self.fees_paid))?;
+        interval_dict.set_item("rebates_received", output.value(py, self.rebates_received))?;
+        // Fees per unit of volume, to hold against the published schedule;
+        // None when inverse and linear volume would be summed.
+        let fee_bps = |fees: Decimal, volume: Decimal| {
+            (!volume.is_zero() && !self.mixed_units).then(|| output.value(py, (fees / volume * Decimal::from(10_000)).round_dp(4).normalize()))
+        };
+        interval_dict.set_item("effective_fee_bps", fee_bps(self.fee_total, self.maker_volume + self.taker_volume))?;
+        interval_dict.set_item("maker_fee_bps", fee_bps(self.maker_fees, self.maker_volume))?;
+        interval_dict.set_item("taker_fee_bps", fee_bps(self.taker_fees, self.taker_volume))?;
+        if output.fees_by_currency {
+            let fees = PyDict::new_bound(py);
+            for (currency, amount) in &self.fees_by_currency {
+                fees.set_item(currency, output.value(py, *amount))?;
+            }
+            interval_dict.set_item("fees_by_currency", fees)?;
+        }
+        interval_dict.set_item("profit_after_fees", output.value(py, self.profit_after_fees))?;
+        interval_dict.set_item("avg_exposure_base", output.value(py, self.avg_exposure_base))?;
+        interval_dict.set_item("avg_exposure_notional", output.value(py, self.avg_exposure_notional))?;
+        interval_dict.set_item("turnover", self.turnover.map(|d| output.value(py, d)))?;
+        interval_dict.set_item("inventory_cycles", self.inventory_cycles)?;
+        interval_dict.set_item("avg_holding_us", self.avg_holding_us)?;
+        interval_dict.set_item("median_holding_us", self.median_holding_us)?;
+        interval_dict.set_item("buy_count", self.buy_count)?;
+        interval_dict.set_item("sell_count", self.sell_count)?;
+        interval_dict.set_item("avg_buy_price", self.avg_buy_price.map(|d| output.value(py, d)))?;
+        interval_dict.set_item("avg_sell_price", self.avg_sell_price.map(|d| output.value(py, d)))?;
+        interval_dict.set_item("max_drawdown", output.value(py, self.max_drawdown))?;
+        if let Some(funding_pnl) = self.funding_pnl {
+            interval_dict.set_item("funding_pnl", output.value(py, funding_pnl))?;
+            interval_dict.set_item(
+                "profit_after_funding",
+                output.value(py, self.profit_after_fees + funding_pnl),
+            )?;
+        }
+        if let Some(borrow_cost) = self.borrow_cost {
+            interval_dict.set_item("borrow_cost", output.value(py, borrow_cost))?;
+        }
+        if self.funding_pnl.is_some() || self.borrow_cost.is_some() {
+            let carry = self.funding_pnl.unwrap_or_default() - self.borrow_cost.unwrap_or_default();
+            interval_dict.set_item("profit_after_carry", output.value(py, self.profit_after_fees + carry))?;
+        }
+        if output.base_pnl {
+            interval_dict.set_item(
+                "profit_before_fees_base",
+                output.value(py, self.profit_before_fees_base),
+            )?;
+            interval_dict.set_item(
+                "profit_after_fees_base",
+                output.value(py, self.profit_after_fees_base),
+            )?;
+        }
+        Ok(interval_dict)
+    }
+}
+
+/// Metrics for every requested interval as of one `now`, with the totals
+/// taken from the interval keyed "all".
+struct IntervalReport {
+    metrics: Vec<RawMetrics>,
+    total_before: Decimal,
+    total_after: Decimal,
+    total_funding: Option<Decimal>,
+    total_borrow: Option<Decimal>,
+}
+
+impl IntervalReport {
+    fn write_py(
+        &self,
+        py: Python<'_>,
+        target: &Bound<'_, PyDict>,
+        intervals: &[IntervalSpec],
+        output: OutputOptions,
+        quote_currency: Option<&str>,
+    ) -> PyResult<()> {
+        let intervals_py = PyList::empty_bound(py);
+        for (spec, metrics) in intervals.iter().zip(&self.metrics) {
+            intervals_py.append(metrics.to_py(py, spec, output)?)?;
+        }
+        target.set_item("intervals", intervals_py)?;
+        target.set_item("quote_currency", quote_currency)?;
+        target.set_item("total_profit_before_fees", output.value(py, self.total_before))?;
+        target.set_item("total_profit_after_fees", output.value(py, self.total_after))?;
+        if let Some(total_funding) = self.total_funding {
+            target.set_item("total_funding_pnl", output.value(py, total_funding))?;
+        }
+        if let Some(total_borrow) = self.total_borrow {
+            target.set_item("total_borrow_cost", output.value(py, total_borrow))?;
+        }
+        Ok(())
+    }
+}
+
+fn build_interval_report(
+    ledger: Ledger<'_>,
+    intervals: &[IntervalSpec],
+    now: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    carry: &CarryFlows,
+) -> PyResult<IntervalReport> {
+    let mut metrics = Vec::with_capacity(intervals.len());
+    let mut total_before = Decimal::ZERO;
+    let mut total_after = Decimal::ZERO;
+    let mut total_funding = carry.funding.as_ref().map(|_| Decimal::ZERO);
+    let mut total_borrow = carry.borrow.as_ref().map(|_| Decimal::ZERO);
+
+    for spec in intervals {
+        let start = interval_start(now, spec, cutoff)?;
+        let mut interval_metrics = summarise_interval(ledger, start, now)?;
+        interval_metrics.funding_pnl = carry.funding.as_deref().map(|flows| sum_cash_flows(flows, start, now));
+        interval_metrics.borrow_cost = carry.borrow.as_ref().map(|borrow| borrow.sum(start, now));
+        if spec.key == "all" {
+            total_before = interval_metrics.profit_before_fees;
+            total_after = interval_metrics.profit_after_fees;
+            total_funding = interval_metrics.funding_pnl;
+            total_borrow = interval_metrics.borrow_cost;
+        }
+        metrics.push(interval_metrics);
+    }
+
+    Ok(IntervalReport {
+        metrics,
+        total_before,
+        total_after,
+        total_funding,
+        total_borrow,
+    })
+}
+
+/// Absolute and percentage change of one metric between two windows; the
+/// percentage is against the magnitude of the previous value and left out
+/// when that is zero.
+fn delta_to_py<'py>(py: Python<'py>, current: Decimal, previous: Decimal) -> PyResult<Bound<'py, PyDict>> {
+    let delta = current - previous;
+    let dict = PyDict::new_bound(py);
+    dict.set_item("absolute", delta.to_string())?;
+    dict.set_item(
+        "percent",
+        (!previous.is_zero()).then(|| (delta / previous.abs() * Decimal::ONE_HUNDRED).round_dp(4).normalize().to_string()),
+    )?;
+    Ok(dict)
+}
+
+fn comparisons_to_py<'py>(
+    py: Python<'py>,
+    ledger: Ledger<'_>,
+    intervals: &[IntervalSpec],
+    comparisons: &[ComparisonSpec],
+    now: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    let rows = PyList::empty_bound(py);
+    for comparison in comparisons {
+        let spec = intervals
+            .iter()
+            .find(|spec| spec.key == comparison.interval_key)
+            .ok_or_else(|| {
+                PyValueError::new_err(format!("unknown interval key: {}", comparison.interval_key))
+            })?;
+        let start = interval_start(now, spec, cutoff)?;
+        let current = summarise_interval(ledger, start, now)?;
+
+        let out_of_range = || {
+            PyValueError::new_err(format!(
+                "comparison {} offset out of range: {}",
+                comparison.key, comparison.offset_seconds
+            ))
+        };
+        let offset = Duration::try_seconds(comparison.offset_seconds.max(0)).ok_or_else(out_of_range)?;
+        let previous_end = now.checked_sub_signed(offset).ok_or_else(out_of_range)?;
+        let previous_start = start.checked_sub_signed(offset).ok_or_else(out_of_range)?.max(cutoff);
+        let previous = summarise_interval(ledger.until(previous_end), previous_start, previous_end)?;
+
+        let deltas = PyDict::new_bound(py);
+        deltas.set_item(
+            "profit_before_fees",
+            delta_to_py(py, current.profit_before_fees, previous.profit_before_fees)?,
+        )?;
+        deltas.set_item(
+            "profit_after_fees",
+            delta_to_py(py, current.profit_after_fees, previous.profit_after_fees)?,
+        )?;
+        deltas.set_item("fee_total", delta_to_py(py, current.fee_total, previous.fee_total)?)?;
+        deltas.set_item(
+            "volume",
+            delta_to_py(
+                py,
+                current.maker_volume + current.taker_volume,
+                previous.maker_volume + previous.taker_volume,
+            )?,
+        )?;
+
+        let row = PyDict::new_bound(py);
+        row.set_item("key", &comparison.key)?;
+        row.set_item("interval_key", &comparison.interval_key)?;
+        row.set_item("offset_seconds", comparison.offset_seconds)?;
+        row.set_item("current", current.to_py(py, spec, output)?)?;
+        row.set_item("previous", previous.to_py(py, spec, output)?)?;
+        row.set_item("deltas", deltas)?;
+        rows.append(row)?;
+    }
+    Ok(rows)
+}
+
+/// Lists each scenario's interval metrics and open position with what it
+/// changes against the base run: `fee_savings` (negative when the scenario
+/// costs more) and `profit_after_fees_delta`, per interval and in total.
+fn scenarios_to_py<'py>(
+    py: Python<'py>,
+    scenarios: &[ScenarioSpec],
+    replays: &[(IntervalReport, OpenPosition)],
+    base: &IntervalReport,
+    intervals: &[IntervalSpec],
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    let rows = PyList::empty_bound(py);
+    let base_fees = base.total_before - base.total_after;
+    for (scenario, (report, open_position)) in scenarios.iter().zip(replays) {
+        let intervals_py = PyList::empty_bound(py);
+        for ((spec, metrics), base_metrics) in intervals.iter().zip(&report.metrics).zip(&base.metrics) {
+            let interval_dict = metrics.to_py(py, spec, output)?;
+            interval_dict.set_item("fee_savings", output.value(py, base_metrics.fee_total - metrics.fee_total))?;
+            interval_dict.set_item(
+                "profit_after_fees_delta",
+                output.value(py, metrics.profit_after_fees - base_metrics.profit_after_fees),
+            )?;
+            intervals_py.append(interval_dict)?;
+        }
+        let fees = report.total_before - report.total_after;
+        let row = PyDict::new_bound(py);
+        row.set_item("key", &scenario.key)?;
+        row.set_item("intervals", intervals_py)?;
+        row.set_item("total_profit_before_fees", output.value(py, report.total_before))?;
+        row.set_item("total_profit_after_fees", output.value(py, report.total_after))?;
+        row.set_item("total_fee_savings", output.value(py, base_fees - fees))?;
+        row.set_item("total_profit_after_fees_delta", output.value(py, report.total_after - base.total_after))?;
+        row.set_item("open_position", open_position.to_py(py)?)?;
+        rows.append(row)?;
+    }
+    Ok(rows)
+}
+
+fn interval_start(now: DateTime<Utc>, spec: &IntervalSpec, cutoff: DateTime<Utc>) -> PyResult<DateTime<Utc>> {
+    let start = match (spec.anchor_timestamp_us, spec.delta_seconds) {
+        (Some(anchor_us), _) => {
+            let anchor = timestamp_us_to_datetime(anchor_us)?;
+            match spec.period_seconds.filter(|seconds| *seconds > 0) {
+                Some(seconds) => {
+                    let period_us = seconds.saturating_mul(1_000_000);
+                    let elapsed_us = (now - anchor).num_microseconds().unwrap_or(0);
+                    let periods = elapsed_us.div_euclid(period_us);
+                    anchor
+                        .checked_add_signed(Duration::microseconds(periods.saturating_mul(period_us)))
+                        .ok_or_else(|| interval_out_of_range(spec))?
+                }
+                None => anchor,
+            }
+        }
+        (None, Some(seconds)) => Duration::try_seconds(seconds.max(0))
+            .and_then(|delta| now.checked_sub_signed(delta))
+            .ok_or_else(|| interval_out_of_range(spec))?,
+        (None, None) => cutoff,
+    };
+    Ok(if start < cutoff { cutoff } else { start })
+}
+
+fn interval_out_of_range(spec: &IntervalSpec) -> PyErr {
+    PyValueError::new_err(format!("interval {} starts out of range", spec.key))
+}
+
+/// Pins `since_last_flat` intervals to the replay's boundary; while flat they
+/// start at `now` and so cover nothing. Any period or delta on them is
+/// dropped so the boundary is used as is rather than snapped to a period.
+fn anchor_since_last_flat(intervals: &[IntervalSpec], replay: &Replay, now: DateTime<Utc>) -> Vec<IntervalSpec> {
+    let boundary = replay.position_opened_at.unwrap_or(now);
+    intervals
+        .iter()
+        .map(|spec| {
+            if !spec.since_last_flat {
+                return spec.clone();
+            }
+            IntervalSpec {
+                anchor_timestamp_us: Some(boundary.timestamp_micros()),
+                period_seconds: None,
+                delta_seconds: None,
+                ..spec.clone()
+            }
+        })
+        .collect()
+}
+
+/// Include/exclude lists on trade `product_id`. With an include list, trades
+/// without a product id are dropped.
+#[derive(Default)]
+struct ProductFilter {
+    include: Option<FxHashSet<String>>,
+    exclude: FxHashSet<String>,
+}
+
+impl ProductFilter {
+    fn new(include: Option<Vec<String>>, exclude: Option<Vec<String>>) -> Self {
+        ProductFilter {
+            include: include.map(|ids| ids.into_iter().collect()),
+            exclude: exclude.into_iter().flatten().collect(),
+        }
+    }
+
+    fn allows(&self, product_id: Option<&str>) -> bool {
+        let included = match (&self.include, product_id) {
+            (None, _) => true,
+            (Some(include), Some(product_id)) => include.contains(product_id),
+            (Some(_), None) => false,
+        };
+        included && !product_id.is_some_and(|product_id| self.exclude.contains(product_id))
+    }
+}
+
+/// Parses and time-orders trades, dropping non-positive prices/sizes,
+/// anything before `cutoff` and products the filter rejects. Side and price
+/// are only validated on trades that pass the product and size checks.
+fn parse_trades(
+    TradeInputs(trades): TradeInputs,
+    cutoff: Option<DateTime<Utc>>,
+    products: &ProductFilter,
+) -> PyResult<Vec<Trade>> {
+    let mut parsed_trades: Vec<Trade> = Vec::with_capacity(trades.len());
+    let mut labels = Interner::default();
+    for (input_index, trade) in trades.into_iter().enumerate() {
+        if !products.allows(trade.product_id.as_deref()) {
+            continue;
+        }
+        let size = trade.size;
+        if size <= Decimal::ZERO {
+            continue;
+        }
+        let (side, price) = (trade.side?, trade.price?);
+        if price <= Decimal::ZERO {
+            continue;
+        }
+        let timestamp = timestamp_us_to_datetime(trade.timestamp_us)?;
+        if cutoff.is_some_and(|cutoff| timestamp < cutoff) {
+            continue;
+        }
+        parsed_trades.push(Trade {
+            timestamp,
+            side,
+            price,
+            size,
+            post_only: trade.post_only,
+            product_id: trade.product_id.as_deref().map(|id| labels.intern(id)),
+            leg_group: trade.leg_group.as_deref().map(|id| labels.intern(id)),
+            trade_id: trade.trade_id.as_deref().map(Arc::from),
+            input_index,
+            commission: trade.commission,
+            commission_currency: trade
+                .commission_currency
+                .as_deref()
+                .map(|currency| labels.intern(&currency.to_ascii_uppercase())),
+            inverse: false,
+        });
+    }
+
+    parsed_trades.sort_by_key(|trade| trade.timestamp);
+    Ok(parsed_trades)
+}
+
+/// Flags trades of the listed coin-margined products as inverse contracts.
+fn mark_inverse(trades: &mut [Trade], inverse_products: Option<Vec<String>>) {
+    let Some(inverse_products) = inverse_products else {
+        return;
+    };
+    let inverse_products: FxHashSet<String> = inverse_products.into_iter().collect();
+    for trade in trades {
+        trade.inverse = trade
+            .product_id
+            .as_deref()
+            .is_some_and(|product_id| inverse_products.contains(product_id));
+    }
+}
+
+/// What to do when one replay would match trades of different products
+/// against each other's lots.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MixedProductPolicy {
+    Error,
+    Warn,
+    Allow,
+}
+
+impl TryFrom<&str> for MixedProductPolicy {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "error" => Ok(MixedProductPolicy::Error),
+            "warn" => Ok(MixedProductPolicy::Warn),
+            "allow" => Ok(MixedProductPolicy::Allow),
+            other => Err(PyValueError::new_err(format!("unknown mixed product policy: {}", other))),
+        }
+    }
+}
+
+/// Fails or warns (per `policy`) when `trades` span more than one product,
+/// since a single lot queue would otherwise close an ETH sell against a BTC
+/// lot. Trades without a product id and `leg_group` legs, which are
+/// multi-product by design, are ignored.
+fn check_single_product(py: Python<'_>, trades: &[Trade], policy: MixedProductPolicy) -> PyResult<()> {
+    if policy == MixedProductPolicy::Allow {
+        return Ok(());
+    }
+    let products: BTreeSet<&str> = trades
+        .iter()
+        .filter(|trade| trade.leg_group.is_none())
+        .filter_map(|trade| trade.product_id.as_deref())
+        .collect();
+    if products.len() < 2 {
+        return Ok(());
+    }
+    let message = format!(
+        "trades span several products ({}); filter to one product or replay each separately",
+        products.into_iter().collect::<Vec<_>>().join(", ")
+    );
+    match policy {
+        MixedProductPolicy::Warn => PyErr::warn_bound(py, &py.get_type_bound::<PyUserWarning>(), &message, 1),
+        _ => Err(PyValueError::new_err(message)),
+    }
+}
+
+/// Fails when `trades` span more than one product whatever the mixed product
+/// policy, for inputs that describe a single instrument (`input`).
+fn require_single_product(trades: &[Trade], input: &str) -> PyResult<()> {
+    let products: BTreeSet<&str> = trades.iter().filter_map(|trade| trade.product_id.as_deref()).collect();
+    if products.len() > 1 {
+        return Err(PyValueError::new_err(format!(
+            "{} needs trades of a single product, got {}",
+            input,
+            products.into_iter().collect::<Vec<_>>().join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// Reads a split ratio as `(new units, old units)`, keeping "n/d" ratios
+/// exact instead of rounding them to a decimal.
+fn parse_split_ratio(text: &str) -> PyResult<(Decimal, Decimal)> {
+    let (numerator, denominator) = match text.split_once('/') {
+        Some((numerator, denominator)) => (
+            parse_decimal(numerator.trim(), "split ratio")?,
+            parse_decimal(denominator.trim(), "split ratio")?,
+        ),
+        None => (parse_decimal(text, "split ratio")?, Decimal::ONE),
+    };
+    if numerator <= Decimal::ZERO || denominator <= Decimal::ZERO {
+        return Err(PyValueError::new_err(format!("split ratio must be positive: {}", text)));
+    }
+    Ok((numerator, denominator))
+}
+
+/// Restates trades executed before each split in post-split units (size
+/// times ratio, price divided by it) and under any new product id. Notional
+/// is unchanged, so lots opened before the event keep their cost basis and
+/// match against later trades. Splits are applied oldest first and their
+/// ratios multiplied out per trade, so each price is divided only once.
+fn apply_splits(trades: &mut [Trade], splits: Vec<SplitSpec>) -> PyResult<()> {
+    let mut events = Vec::with_capacity(splits.len());
+    for split in splits {
+        if split.new_product_id.is_some() && split.product_id.is_none() {
+            return Err(PyValueError::new_err("split with new_product_id needs a product_id"));
+        }
+        let effective = timestamp_us_to_datetime(split.timestamp_us)?;
+        let ratio = parse_split_ratio(&split.ratio)?;
+        events.push((effective, ratio, split.product_id, split.new_product_id.map(Arc::<str>::from)));
+    }
+    events.sort_by_key(|(effective, ..)| *effective);
+
+    let overflow = || PyValueError::new_err("split ratios overflow");
+    let mut ratios: Vec<Option<(Decimal, Decimal)>> = vec![None; trades.len()];
+    for (effective, (numerator, denominator), product_id, new_product_id) in events {
+        for (trade, ratio) in trades.iter_mut().zip(&mut ratios).take_while(|(trade, _)| trade.timestamp < effective) {
+            if product_id
+                .as_deref()
+                .is_some_and(|product_id| trade.product_id.as_deref() != Some(product_id))
+            {
+                continue;
+            }
+            *ratio = Some(match *ratio {
+                Some((n, d)) => (
+                    n.checked_mul(numerator).ok_or_else(overflow)?,
+                    d.checked_mul(denominator).ok_or_else(overflow)?,
+                ),
+                None => (numerator, denominator),
+            });
+            if let Some(new_product_id) = &new_product_id {
+                trade.product_id = Some(new_product_id.clone());
+            }
+        }
+    }
+    for (trade, ratio) in trades.iter_mut().zip(ratios) {
+        if let Some((numerator, denominator)) = ratio {
+            trade.size = trade.size.checked_mul(numerator).ok_or_else(overflow)? / denominator;
+            trade.price = trade.price.checked_mul(denominator).ok_or_else(overflow)? / numerator;
+        }
+    }
+    Ok(())
+}
+
+/// Product-id quote segments that name a contract type rather than a
+/// currency, mapped to the currency they settle in; see
+/// `register_quote_aliases`.
+static QUOTE_ALIASES: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+
+/// Splits `BASE-QUOTE` product ids into their currencies.
+fn product_currencies(product_id: &str) -> Option<(&str, Cow<'_, str>)> {
+    let (base, rest) = product_id.split_once('-')?;
+    let quote = rest.split('-').next().unwrap_or(rest);
+    if base.is_empty() || quote.is_empty() {
+        return None;
+    }
+    let registered = QUOTE_ALIASES.read().ok().and_then(|aliases| {
+        aliases
+            .iter()
+            .find(|(alias, _)| alias.eq_ignore_ascii_case(quote))
+            .map(|(_, currency)| currency.clone())
+    });
+    let quote = match registered {
+        Some(currency) => Cow::Owned(currency),
+        // International exchange perps (`BTC-PERP-INTX`) are USDC-margined
+        // unless an alias says otherwise.
+        None if quote == "PERP" => Cow::Borrowed("USDC"),
+        None => Cow::Borrowed(quote),
+    };
+    Some((base, quote))
+}
+
+/// Maps product-id quote segments to settlement currencies (e.g. `PERP` to
+/// `USDC`, the built-in default). Applies to every later call in the process.
+#[pyfunction]
+#[pyo3(signature = (aliases, *, replace = false))]
+fn register_quote_aliases(aliases: BTreeMap<String, String>, replace: bool) -> PyResult<()> {
+    let mut registry = QUOTE_ALIASES
+        .write()
+        .map_err(|_| PyValueError::new_err("quote alias registry poisoned"))?;
+    if replace {
+        registry.clear();
+    }
+    for (alias, currency) in aliases {
+        if currency.is_empty() {
+            return Err(PyValueError::new_err(format!("empty currency for quote alias {}", alias)));
+        }
+        registry.retain(|(existing, _)| !existing.eq_ignore_ascii_case(&alias));
+        registry.push((alias, currency));
+    }
+    Ok(())
+}
+
+/// Currency the PnL of `trades` is denominated in: `explicit` when given,
+/// else their product's quote (base for inverse contracts). None when the
+/// trades span several or carry no product id. Leg groups are left out.
+fn pnl_currency(trades: &[Trade], explicit: Option<String>) -> Option<String> {
+    if explicit.is_some() {
+        return explicit;
+    }
+    let mut currencies = trades.iter().filter(|trade| trade.leg_group.is_none()).map(|trade| {
+        let (base, quote) = product_currencies(trade.product_id.as_deref()?)?;
+        Some(if trade.inverse { Cow::Borrowed(base) } else { quote })
+    });
+    let first = currencies.next()??;
+    currencies
+        .all(|currency| currency.as_ref() == Some(&first))
+        .then(|| first.into_owned())
+}
+
+/// Net currency flows of trades sharing a `leg_group`, e.g. the three legs of
+/// a triangular arbitrage cycle. A closed cycle nets to zero in every currency
+/// except the one it started from, which carries the group's PnL.
+struct LegGroup {
+    leg_group: Arc<str>,
+    trade_count: usize,
+    products: Vec<Arc<str>>,
+    net_flows: BTreeMap<String, Decimal>,
+    fees: BTreeMap<String, Decimal>,
+    /// Sells of more base than the group had acquired so far.
+    oversold_count: usize,
+}
+
+/// `oversell` applies per group: a leg may only sell the base the group's
+/// earlier legs bought, so a cycle starting from an outside holding counts.
+fn summarise_leg_groups(trades: &[Trade], fees: &FeeSchedule, oversell: OversellPolicy) -> PyResult<Vec<LegGroup>> {
+    let mut groups: Vec<LegGroup> = Vec::new();
+    let mut positions: FxHashMap<Arc<str>, usize> = FxHashMap::default();
+
+    for trade in trades {
+        let Some(leg_group) = &trade.leg_group else {
+            continue;
+        };
+        let product_id = trade.product_id.as_ref().ok_or_else(|| {
+            PyValueError::new_err(format!("trade in leg_group {} is missing product_id", leg_group))
+        })?;
+        let (base, quote) = product_currencies(product_id).ok_or_else(|| {
+            PyValueError::new_err(format!("cannot derive currencies from product_id: {}", product_id))
+        })?;
+
+        let index = *positions.entry(leg_group.clone()).or_insert_with(|| {
+            groups.push(LegGroup {
+                leg_group: leg_group.clone(),
+                trade_count: 0,
+                products: Vec::new(),
+                net_flows: BTreeMap::new(),
+                fees: BTreeMap::new(),
+                oversold_count: 0,
+            });
+            groups.len() - 1
+        });
+        let group = &mut groups[index];
+        group.trade_count += 1;
+        if !group.products.contains(product_id) {
+            group.products.push(product_id.clone());
+        }
+
+        let notional = trade.price * trade.size;
+        let mut booked_size = trade.size;
+        let held = group.net_flows.get(base).copied().unwrap_or_default().max(Decimal::ZERO);
+        if trade.side == Side::Sell && booked_size > held {
+            group.oversold_count += 1;
+            booked_size = oversell.sell_size(trade.size, held, trade.timestamp, 0)?;
+        }
+        let (base_flow, quote_flow) = match trade.side {
+            Side::Buy => (booked_size, -notional),
+            Side::Sell => (-booked_size, trade.price * booked_size),
+        };
+        *group.net_flows.entry(base.to_string()).or_default() += base_flow;
+        *group.net_flows.entry(quote.to_string()).or_default() += quote_flow;
+        let fee_rate = fees.rate(trade.timestamp, trade.post_only);
+        let fee_currency = trade.commission_currency.as_deref().unwrap_or(&quote);
+        let fee = match trade.commission {
+            Some(commission) => commission,
+            None => fees.charge(notional, fee_rate)?,
+        };
+        *group.fees.entry(fee_currency.to_string()).or_default() += fee;
+    }
+
+    Ok(groups)
+}
+
+fn currency_map_to_py<'py>(py: Python<'py>, values: &BTreeMap<String, Decimal>) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    for (currency, amount) in values {
+        dict.set_item(currency, amount.to_string())?;
+    }
+    Ok(dict)
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    trades,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    maker_fee_rate,
+    taker_fee_rate,
+    *,
+    fixed_point_decimals = None,
+    include_position_series = false,
+    include_base_pnl = false,
+    as_of_timestamps_us = None,
+    funding_rates = None,
+    borrow_costs = None,
+    borrow_rates = None,
+    comparisons = None,
+    include_match_ledger = false,
+    splits = None,
+    include_rolling_volume = false,
+    float_output = false,
+    include_products = None,
+    exclude_products = None,
+    mid_prices = None,
+    mixed_products = "error",
+    inverse_products = None,
+    oversell = "allow_short",
+    fee_schedule = None,
+    min_fee = None,
+    max_fee = None,
+    lot_method = "fifo",
+    scenarios = None,
+    quote_currency = None,
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn summarise_trades(
+    py: Python<'_>,
+    trades: TradeInputs,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    fixed_point_decimals: Option<FixedPointDecimals>,
+    include_position_series: bool,
+    include_base_pnl: bool,
+    as_of_timestamps_us: Option<Vec<i64>>,
+    funding_rates: Option<Vec<FundingSpec>>,
+    borrow_costs: Option<Vec<BorrowCostSpec>>,
+    borrow_rates: Option<Vec<BorrowRateSpec>>,
+    comparisons: Option<Vec<ComparisonSpec>>,
+    include_match_ledger: bool,
+    splits: Option<Vec<SplitSpec>>,
+    include_rolling_volume: bool,
+    float_output: bool,
+    include_products: Option<Vec<String>>,
+    exclude_products: Option<Vec<String>>,
+    mid_prices: Option<Vec<PricePointSpec>>,
+    mixed_products: &str,
+    inverse_products: Option<Vec<String>>,
+    oversell: &str,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<&str>,
+    max_fee: Option<&str>,
+    lot_method: &str,
+    scenarios: Option<Vec<ScenarioSpec>>,
+    quote_currency: Option<String>,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let lot_method = LotMethod::try_from(lot_method)?;
+    let scenarios = scenarios.unwrap_or_default();
+    let mut scenario_settings = Vec::with_capacity(scenarios.len());
+    for scenario in &scenarios {
+        let fees = FeeSchedule::parse(
+            scenario.maker_fee_rate.as_deref().unwrap_or(maker_fee_rate),
+            scenario.taker_fee_rate.as_deref().unwrap_or(taker_fee_rate),
+            scenario.fee_schedule.clone().or_else(|| fee_schedule.clone()),
+            scenario.min_fee.as_deref().or(min_fee),
+            scenario.max_fee.as_deref().or(max_fee),
+        )?;
+        let method = scenario.lot_method.as_deref().map_or(Ok(lot_method), LotMethod::try_from)?;
+        let classification = scenario
+            .maker_classification
+            .as_deref()
+            .map_or(Ok(MakerClassification::default()), MakerClassification::try_from)?;
+        scenario_settings.push((fees, method, classification, scenario.overrides_fees()));
+    }
+    let fees = FeeSchedule::parse(maker_fee_rate, taker_fee_rate, fee_schedule, min_fee, max_fee)?;
+    let now = timestamp_us_to_datetime(now_timestamp_us)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+    let mids = PriceSeries::parse(None, mid_prices)?;
+
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let products = ProductFilter::new(include_products, exclude_products);
+    let mut parsed_trades = parse_trades(trades, Some(cutoff), &products)?;
+    if let Some(splits) = splits {
+        // Restated prices need not fit the fixed-point scale.
+        if fixed_point_decimals.is_some() {
+            return Err(PyValueError::new_err("splits are not supported with fixed_point_decimals"));
+        }
+        apply_splits(&mut parsed_trades, splits)?;
+    }
+    check_single_product(py, &parsed_trades, MixedProductPolicy::try_from(mixed_products)?)?;
+    if mids.is_some() {
+        require_single_product(&parsed_trades, "mid_prices")?;
+    }
+    if funding_rates.is_some() {
+        require_single_product(&parsed_trades, "funding_rates")?;
+    }
+    mark_inverse(&mut parsed_trades, inverse_products);
+    let fixed_point_decimals = fixed_point_decimals
+        .map(|decimals| decimals.resolve(&parsed_trades))
+        .transpose()?;
+    let options = ReplayOptions {
+        position_series: include_position_series,
+        base_pnl: include_base_pnl,
+        match_ledger: include_match_ledger,
+        oversell: OversellPolicy::try_from(oversell)?,
+        lot_method,
+        maker_classification: MakerClassification::default(),
+        ignore_commissions: false,
+        watch: Tracker(Some(&watch)),
+    };
+    // The replay and report hold no Python objects, so other threads (and an
+    // asyncio loop awaiting `summarise_trades_async`) run meanwhile.
+    let replay_with = |fees: &FeeSchedule, options| match fixed_point_decimals {
+        Some((price_decimals, size_decimals)) => {
+            build_entries_fixed(&parsed_trades, fees, price_decimals, size_decimals, options)
+        }
+        None => build_entries(&parsed_trades, fees, EntryScales::default(), options),
+    };
+    let replay = run_watched(py, &watch, "replay", parsed_trades.len(), || replay_with(&fees, options))?;
+
+    let output = OutputOptions {
+        base_pnl: include_base_pnl,
+        fees_by_currency: parsed_trades.iter().any(|trade| trade.commission_currency.is_some()),
+        floats: float_output,
+    };
+    let borrow = match (borrow_costs, borrow_rates) {
+        (None, None) => None,
+        (costs, rates) => Some(borrow_charges(
+            &replay.entries,
+            costs.unwrap_or_default(),
+            rates.unwrap_or_default(),
+            now,
+        )?),
+    };
+    let carry = CarryFlows {
+        funding: funding_rates
+            .map(|rates| funding_payments(&replay.entries, rates))
+            .transpose()?,
+        borrow,
+    };
+    let intervals = anchor_since_last_flat(&intervals, &replay, now);
+    let report = py.allow_threads(|| build_interval_report(replay.ledger(), &intervals, now, cutoff, &carry))?;
+    // Fees, lot order and liquidity never change the position, so scenarios
+    // share the anchored intervals and carry flows; only the replay itself is
+    // repeated.
+    let scenario_options = ReplayOptions {
+        position_series: false,
+        base_pnl: false,
+        match_ledger: false,
+        ..options
+    };
+    let scenario_replays: Vec<(IntervalReport, OpenPosition)> =
+        run_watched(py, &watch, "scenarios", parsed_trades.len() * scenario_settings.len(), || {
+            scenario_settings
+                .par_iter()
+                .map(|(fees, lot_method, maker_classification, ignore_commissions)| {
+                    let options = ReplayOptions {
+                        lot_method: *lot_method,
+                        maker_classification: *maker_classification,
+                        ignore_commissions: *ignore_commissions,
+                        ..scenario_options
+                    };
+                    let replay = replay_with(fees, options)?;
+                    let report = build_interval_report(replay.ledger(), &intervals, now, cutoff, &carry)?;
+                    Ok((report, replay.open_position))
+                })
+                .collect()
+        })?;
+
+    let result = PyDict::new_bound(py);
+    let quote_currency = pnl_currency(&parsed_trades, quote_currency);
+    report.write_py(py, &result, &intervals, output, quote_currency.as_deref())?;
+    result.set_item("open_position", replay.open_position.to_py(py)?)?;
+    result.set_item("oversold_count", replay.oversold_count)?;
+    if !scenarios.is_empty() {
+        result.set_item(
+            "scenarios",
+            scenarios_to_py(py, &scenarios, &scenario_replays, &report, &intervals, output)?,
+        )?;
+    }
+
+    let leg_groups = summarise_leg_groups(&parsed_trades, &fees, options.oversell)?;
+    if !leg_groups.is_empty() {
+        let groups_py = PyList::empty_bound(py);
+        for group in &leg_groups {
+            let mut net_after_fees = group.net_flows.clone();
+            for (currency, fee) in &group.fees {
+                *net_after_fees.entry(currency.clone()).or_default() -= *fee;
+            }
+            let products: Vec<&str> = group.products.iter().map(|id| id.as_ref()).collect();
+            let dict = PyDict::new_bound(py);
+            dict.set_item("leg_group", group.leg_group.as_ref())?;
+            dict.set_item("trade_count", group.trade_count)?;
+            dict.set_item("products", products)?;
+            dict.set_item("net_flows", currency_map_to_py(py, &group.net_flows)?)?;
+            dict.set_item("fees", currency_map_to_py(py, &group.fees)?)?;
+            dict.set_item("net_after_fees", currency_map_to_py(py, &net_after_fees)?)?;
+            dict.set_item("oversold_count", group.oversold_count)?;
+            groups_py.append(dict)?;
+        }
+        result.set_item("leg_groups", groups_py)?;
+    }
+    if let Some(as_of_timestamps_us) = as_of_timestamps_us {
+        let as_of_py = PyList::empty_bound(py);
+        for as_of_us in as_of_timestamps_us {
+            let as_of = timestamp_us_to_datetime(as_of_us)?;
+            let report = build_interval_report(replay.ledger().until(as_of), &intervals, as_of, cutoff, &carry)?;
+            let dict = PyDict::new_bound(py);
+            dict.set_item("now_timestamp_us", as_of_us)?;
+            report.write_py(py, &dict, &intervals, output, quote_currency.as_deref())?;
+            as_of_py.append(dict)?;
+        }
+        result.set_item("as_of", as_of_py)?;
+    }
+    if let Some(comparisons) = comparisons {
+        result.set_item(
+            "comparisons",
+            comparisons_to_py(py, replay.ledger(), &intervals, &comparisons, now, cutoff, output)?,
+        )?;
+    }
+    if let Some(mids) = &mids {
+        let rows = PyList::empty_bound(py);
+        for spec in &intervals {
+            let start = interval_start(now, spec, cutoff)?;
+            let decomposition = PnlDecomposition::build(&parsed_trades, &replay.entries, mids, start, now);
+            rows.append(decomposition.to_py(py, spec, output)?)?;
+        }
+        result.set_item("pnl_decomposition", rows)?;
+    }
+    if include_match_ledger {
+        let ledger = PyList::empty_bound(py);
+        for matched in &replay.matches {
+            let open = &parsed_trades[matched.open_trade];
+            let close = &parsed_trades[matched.close_trade];
+            let dict = PyDict::new_bound(py);
+            dict.set_item("open_trade_index", open.input_index)?;
+            dict.set_item("open_trade_id", open.trade_id.as_deref())?;
+            dict.set_item("open_timestamp_us", open.timestamp.timestamp_micros())?;
+            dict.set_item("close_trade_index", close.input_index)?;
+            dict.set_item("close_trade_id", close.trade_id.as_deref())?;
+            dict.set_item("close_timestamp_us", close.timestamp.timestamp_micros())?;
+            dict.set_item("direction", if open.side == Side::Buy { "long" } else { "short" })?;
+            dict.set_item("matched_size", matched.size.to_string())?;
+            dict.set_item("open_price", matched.open_price.to_string())?;
+            dict.set_item("close_price", matched.close_price.to_string())?;
+            dict.set_item("realized_profit", matched.realized.to_string())?;
+            ledger.append(dict)?;
+        }
+        result.set_item("match_ledger", ledger)?;
+    }
+    if include_position_series {
+        let series = PyList::empty_bound(py);
+        for point in &replay.position_series {
+            let dict = PyDict::new_bound(py);
+            dict.set_item("timestamp_us", point.timestamp.timestamp_micros())?;
+            dict.set_item("net_position", point.position.signed_size().to_string())?;
+            dict.set_item(
+                "avg_entry_price",
+                point.position.average_entry_price().map(|d| d.to_string()),
+            )?;
+            series.append(dict)?;
+        }
+        result.set_item("position_series", series)?;
+    }
+    if include_rolling_volume {
+        result.set_item("rolling_volume", rolling_volume_to_py(py, &replay.entries, now, output)?)?;
+    }
+
+    Ok(result.into())
+}
+
+/// Summarises several accounts' trades at once. Inputs are parsed under the
+/// GIL, then accounts are replayed on the rayon pool with the GIL released.
+#[pyfunction]
+#[pyo3(signature = (
+    accounts,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    maker_fee_rate,
+    taker_fee_rate,
+    *,
+    float_output = false,
+    mixed_products = "error",
+    oversell = "allow_short",
+    fee_schedule = None,
+    min_fee = None,
+    max_fee = None,
+    quote_currency = None,
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn summarise_accounts(
+    py: Python<'_>,
+    accounts: BTreeMap<String, TradeInputs>,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    float_output: bool,
+    mixed_products: &str,
+    oversell: &str,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<&str>,
+    max_fee: Option<&str>,
+    quote_currency: Option<String>,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let mixed_products = MixedProductPolicy::try_from(mixed_products)?;
+    let oversell = OversellPolicy::try_from(oversell)?;
+    let fees = FeeSchedule::parse(maker_fee_rate, taker_fee_rate, fee_schedule, min_fee, max_fee)?;
+    let now = timestamp_us_to_datetime(now_timestamp_us)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+
+    let mut parsed: Vec<(String, Vec<Trade>)> = Vec::with_capacity(accounts.len());
+    for (account_id, trades) in accounts {
+        let trades = parse_trades(trades, Some(cutoff), &ProductFilter::default())?;
+        check_single_product(py, &trades, mixed_products)?;
+        parsed.push((account_id, trades));
+    }
+
+    let options = ReplayOptions {
+        oversell,
+        watch: Tracker(Some(&watch)),
+        ..ReplayOptions::default()
+    };
+    let trade_count = parsed.iter().map(|(_, trades)| trades.len()).sum();
+    let reports: Vec<(IntervalReport, OpenPosition, usize)> = run_watched(py, &watch, "replay", trade_count, || {
+        parsed
+            .par_iter()
+            .map(|(_, trades)| {
+                let replay = build_entries(trades, &fees, EntryScales::default(), options)?;
+                let intervals = anchor_since_last_flat(&intervals, &replay, now);
+                let report = build_interval_report(replay.ledger(), &intervals, now, cutoff, &CarryFlows::default())?;
+                Ok((report, replay.open_position, replay.oversold_count))
+            })
+            .collect()
+    })?;
+
+    let output = OutputOptions {
+        floats: float_output,
+        ..OutputOptions::default()
+    };
+    let result = PyDict::new_bound(py);
+    for ((account_id, trades), (report, open_position, oversold_count)) in parsed.iter().zip(reports) {
+        let dict = PyDict::new_bound(py);
+        let quote_currency = pnl_currency(trades, quote_currency.clone());
+        report.write_py(py, &dict, &intervals, output, quote_currency.as_deref())?;
+        dict.set_item("open_position", open_position.to_py(py)?)?;
+        dict.set_item("oversold_count", oversold_count)?;
+        result.set_item(account_id, dict)?;
+    }
+    Ok(result.into())
+}
+
+/// Replays trades up to each snapshot timestamp (inclusive) and values the open
+/// position at the snapshot's mark price. Fees are charged as in
+/// `summarise_trades`. Snapshots are returned in input order.
+#[pyfunction]
+#[pyo3(signature = (
+    trades,
+    snapshots,
+    maker_fee_rate,
+    taker_fee_rate,
+    *,
+    cutoff_timestamp_us = None,
+    splits = None,
+    inverse_products = None,
+    oversell = "allow_short",
+    mixed_products = "error",
+    fee_schedule = None,
+    min_fee = None,
+    max_fee = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn mark_to_market(
+    py: Python<'_>,
+    trades: TradeInputs,
+    snapshots: Vec<SnapshotSpec>,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    cutoff_timestamp_us: Option<i64>,
+    splits: Option<Vec<SplitSpec>>,
+    inverse_products: Option<Vec<String>>,
+    oversell: &str,
+    mixed_products: &str,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<&str>,
+    max_fee: Option<&str>,
+) -> PyResult<PyObject> {
+    let oversell = OversellPolicy::try_from(oversell)?;
+    let mixed_products = MixedProductPolicy::try_from(mixed_products)?;
+    let fees = FeeSchedule::parse(maker_fee_rate, taker_fee_rate, fee_schedule, min_fee, max_fee)?;
+    let cutoff = cutoff_timestamp_us.map(timestamp_us_to_datetime).transpose()?;
+    let mut parsed_trades = parse_trades(trades, cutoff, &ProductFilter::default())?;
+    if let Some(splits) = splits {
+        apply_splits(&mut parsed_trades, splits)?;
+    }
+    check_single_product(py, &parsed_trades, mixed_products)?;
+    mark_inverse(&mut parsed_trades, inverse_products);
+
+    let mut points: Vec<(usize, DateTime<Utc>, Decimal)> = Vec::with_capacity(snapshots.len());
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        points.push((
+            index,
+            timestamp_us_to_datetime(snapshot.timestamp_us)?,
+            parse_decimal(&snapshot.mark_price, "mark_price")?,
+        ));
+    }
+    points.sort_by_key(|(_, timestamp, _)| *timestamp);
+
+    let options = ReplayOptions {
+        position_series: true,
+        oversell,
+        ..ReplayOptions::default()
+    };
+    let replay = build_entries(&parsed_trades, &fees, EntryScales::default(), options)?;
+    let flat = OpenPosition::default();
+    let mut applied = 0;
+    let mut realized = Decimal::ZERO;
+    let mut fee_total = Decimal::ZERO;
+    let mut oversold_count = 0;
+    let mut rows: Vec<Option<Bound<'_, PyDict>>> = vec![None; snapshots.len()];
+
+    for (index, timestamp, mark_price) in points {
+        while let Some(entry) = replay.entries.get(applied).filter(|entry| entry.timestamp <= timestamp) {
+            realized += entry.realized_profit;
+            fee_total += entry.fee;
+            oversold_count += usize::from(entry.oversold);
+            applied += 1;
+        }
+
+        let position = match applied.checked_sub(1) {
+            Some(last) => &replay.position_series[last].position,
+            None => &flat,
+        };
+        let row = PyDict::new_bound(py);
+        row.set_item("timestamp_us", snapshots[index].timestamp_us)?;
+        row.set_item("mark_price", mark_price.to_string())?;
+        row.set_item("position_size", position.signed_size().to_string())?;
+        row.set_item("direction", position.direction())?;
+        row.set_item(
+            "average_entry_price",
+            position.average_entry_price().map(|d| d.to_string()),
+        )?;
+        row.set_item("realized_profit", realized.to_string())?;
+        row.set_item("fee_total", fee_total.to_string())?;
+        row.set_item("unrealized_profit", position.unrealized_profit(mark_price).to_string())?;
+        row.set_item("oversold_count", oversold_count)?;
+        rows[index] = Some(row);
+    }
+
+    let rows: Vec<Bound<'_, PyDict>> = rows.into_iter().flatten().collect();
+    Ok(PyList::new_bound(py, rows).into())
+}
+
+/// SplitMix64: a small seeded generator, plenty for bootstrap draws and
+/// reproducible across runs and platforms.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A generator for stream `stream` of `seed`: both are mixed through
+    /// SplitMix64 so neighbouring seeds and streams start far apart.
+    fn stream(seed: u64, stream: u64) -> Self {
+        let mut mixer = SplitMix64(seed);
+        let mut mixer = SplitMix64(mixer.next_u64() ^ stream);
+        SplitMix64(mixer.next_u64())
+    }
+
+    /// Uniform index below `n` (n > 0).
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next_u64() as u128 * n as u128) >> 64) as usize
+    }
+}
+
+/// Most samples a bootstrap may draw across all paths.
+const BOOTSTRAP_MAX_DRAWS: u128 = 1_000_000_000;
+
+/// Most path-day outcomes a bootstrap may hold (8 bytes each, kept twice
+/// while they are regrouped by day).
+const BOOTSTRAP_MAX_OUTCOMES: u128 = 10_000_000;
+
+/// Nearest-rank percentile of an ascending slice.
+fn nearest_rank(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = (percentile / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Bootstraps forward PnL from the realized history: every replayed trade
+/// contributes its realized profit after fees as one sample, and each path
+/// draws (with replacement) as many samples as the historical trade rate
+/// puts in each of the next `horizon_days` days. Returns nearest-rank
+/// percentile bands of cumulative PnL per day, and with `ruin_loss` the share
+/// of paths whose running PnL fell to `-ruin_loss` or below. Each path's
+/// generator is derived from `seed` and the path index, so results repeat
+/// exactly whatever the thread count; amounts are floats. Fails when the
+/// draws (`paths` x trades per day x `horizon_days`) or the per-day outcomes
+/// (`paths` x `horizon_days`) exceed `BOOTSTRAP_MAX_DRAWS` or
+/// `BOOTSTRAP_MAX_OUTCOMES`.
+#[pyfunction]
+#[pyo3(signature = (
+    trades,
+    maker_fee_rate,
+    taker_fee_rate,
+    horizon_days,
+    *,
+    paths = 10_000,
+    percentiles = None,
+    seed = 0,
+    ruin_loss = None,
+    cutoff_timestamp_us = None,
+    inverse_products = None,
+    mixed_products = "error",
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn bootstrap_forward_pnl(
+    py: Python<'_>,
+    trades: TradeInputs,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    horizon_days: u32,
+    paths: usize,
+    percentiles: Option<Vec<f64>>,
+    seed: u64,
+    ruin_loss: Option<&str>,
+    cutoff_timestamp_us: Option<i64>,
+    inverse_products: Option<Vec<String>>,
+    mixed_products: &str,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let mixed_products = MixedProductPolicy::try_from(mixed_products)?;
+    if horizon_days == 0 || paths == 0 {
+        return Err(PyValueError::new_err("horizon_days and paths must be positive"));
+    }
+    let outcomes = paths as u128 * u128::from(horizon_days);
+    if outcomes > BOOTSTRAP_MAX_OUTCOMES {
+        return Err(PyValueError::new_err(format!(
+            "paths x horizon_days is {}, above the limit of {}; use fewer paths or a shorter horizon",
+            outcomes, BOOTSTRAP_MAX_OUTCOMES
+        )));
+    }
+    let percentiles = percentiles.unwrap_or_else(|| vec![5.0, 25.0, 50.0, 75.0, 95.0]);
+    if let Some(bad) = percentiles.iter().find(|p| !(0.0..=100.0).contains(*p)) {
+        return Err(PyValueError::new_err(format!("percentile out of range: {}", bad)));
+    }
+    let ruin_loss = ruin_loss
+        .map(|loss| parse_decimal(loss, "ruin_loss"))
+        .transpose()?;
+    if ruin_loss.is_some_and(|loss| loss <= Decimal::ZERO) {
+        return Err(PyValueError::new_err("ruin_loss must be positive"));
+    }
+    let ruin_loss = ruin_loss.map(|loss| loss.to_f64().unwrap_or(f64::INFINITY));
+    let fees = FeeSchedule::flat(
+        parse_decimal(maker_fee_rate, "maker_fee_rate")?,
+        parse_decimal(taker_fee_rate, "taker_fee_rate")?,
+    );
+    let cutoff = cutoff_timestamp_us.map(timestamp_us_to_datetime).transpose()?;
+    let mut parsed_trades = parse_trades(trades, cutoff, &ProductFilter::default())?;
+    mark_inverse(&mut parsed_trades, inverse_products);
+    check_single_product(py, &parsed_trades, mixed_products)?;
+    let (Some(first), Some(last)) = (parsed_trades.first(), parsed_trades.last()) else {
+        return Err(PyValueError::new_err("no trades to resample"));
+    };
+    // A history shorter than a day still counts as one day of trading.
+    let span_days = ((last.timestamp - first.timestamp).num_microseconds().unwrap_or(i64::MAX) as f64
+        / 86_400_000_000.0)
+        .max(1.0);
+    let trades_per_day = parsed_trades.len() as f64 / span_days;
+
+    let replay = build_entries(&parsed_trades, &fees, EntryScales::default(), ReplayOptions::default())?;
+    let samples: Vec<f64> = replay
+        .entries
+        .iter()
+        .map(|entry| (entry.realized_profit - entry.fee).to_f64().unwrap_or(0.0))
+        .collect();
+    let checkpoints: Vec<usize> = (1..=horizon_days)
+        .map(|day| (trades_per_day * f64::from(day)).round() as usize)
+        .collect();
+    let draws = paths as u128 * checkpoints.last().copied().unwrap_or_default() as u128;
+    if draws > BOOTSTRAP_MAX_DRAWS {
+        return Err(PyValueError::new_err(format!(
+            "paths x trades per day x horizon_days is {}, above the limit of {}; use fewer paths or a shorter horizon",
+            draws, BOOTSTRAP_MAX_DRAWS
+        )));
+    }
+
+    let (by_day, ruined) = run_watched(py, &watch, "bootstrap", paths, || {
+        let outcomes: Vec<(Vec<f64>, bool)> = (0..paths)
+            .into_par_iter()
+            .map(|path| {
+                if watch.cancelled() {
+                    return Err(cancelled_error());
+                }
+                let mut rng = SplitMix64::stream(seed, path as u64);
+                let mut cumulative = 0.0;
+                let mut low = 0.0_f64;
+                let mut drawn = 0;
+                let mut day_ends = Vec::with_capacity(checkpoints.len());
+                for &until in &checkpoints {
+                    while drawn < until {
+                        cumulative += samples[rng.below(samples.len())];
+                        low = low.min(cumulative);
+                        drawn += 1;
+                    }
+                    day_ends.push(cumulative);
+                }
+                watch.advance(1);
+                Ok((day_ends, ruin_loss.is_some_and(|loss| low <= -loss)))
+            })
+            .collect::<PyResult<_>>()?;
+        let ruined = outcomes.iter().filter(|(_, ruined)| *ruined).count();
+        let mut by_day: Vec<Vec<f64>> = (0..checkpoints.len())
+            .map(|day| outcomes.iter().map(|(day_ends, _)| day_ends[day]).collect())
+            .collect();
+        for values in &mut by_day {
+            values.sort_unstable_by(f64::total_cmp);
+        }
+        Ok((by_day, ruined))
+    })?;
+
+    let bands = PyList::empty_bound(py);
+    for (day, (values, trade_count)) in by_day.iter().zip(&checkpoints).enumerate() {
+        let points = PyList::empty_bound(py);
+        for &percentile in &percentiles {
+            let point = PyDict::new_bound(py);
+            point.set_item("percentile", percentile)?;
+            point.set_item("pnl", nearest_rank(values, percentile))?;
+            points.append(point)?;
+        }
+        let band = PyDict::new_bound(py);
+        band.set_item("day", day + 1)?;
+        band.set_item("trade_count", trade_count)?;
+        band.set_item("percentiles", points)?;
+        bands.append(band)?;
+    }
+
+    let horizon = by_day.last().map(Vec::as_slice).unwrap_or_default();
+    let result = PyDict::new_bound(py);
+    result.set_item("sample_count", samples.len())?;
+    result.set_item("trades_per_day", trades_per_day)?;
+    result.set_item("horizon_days", horizon_days)?;
+    result.set_item("paths", paths)?;
+    result.set_item("mean", horizon.iter().sum::<f64>() / paths as f64)?;
+    result.set_item(
+        "probability_of_loss",
+        horizon.iter().filter(|pnl| **pnl < 0.0).count() as f64 / paths as f64,
+    )?;
+    result.set_item("risk_of_ruin", ruin_loss.map(|_| ruined as f64 / paths as f64))?;
+    result.set_item("bands", bands)?;
+    Ok(result.into())
+}
+
+/// Turns the current inventory into a quote skew for the quoting loop. The
+/// inventory ratio is the signed position over `max_position`, clamped to
+/// [-1, 1]; a long book shifts both quotes down by up to `max_skew_bps` and
+/// shrinks the bid while growing the ask (and the reverse when short), so
+/// fills tend to pull inventory back toward flat. The position comes from the
+/// same replay as `summarise_trades`, so `oversell` and `inverse_products`
+/// apply; trades of several products need `product_id` to pick one.
+#[pyfunction]
+#[pyo3(signature = (
+    trades,
+    max_position,
+    *,
+    max_skew_bps = "10",
+    base_quote_size = None,
+    mid_price = None,
+    product_id = None,
+    mixed_products = "error",
+    inverse_products = None,
+    oversell = "allow_short",
+))]
+#[allow(clippy::too_many_arguments)]
+fn inventory_skew(
+    py: Python<'_>,
+    trades: TradeInputs,
+    max_position: &str,
+    max_skew_bps: &str,
+    base_quote_size: Option<&str>,
+    mid_price: Option<&str>,
+    product_id: Option<String>,
+    mixed_products: &str,
+    inverse_products: Option<Vec<String>>,
+    oversell: &str,
+) -> PyResult<PyObject> {
+    let mixed_products = MixedProductPolicy::try_from(mixed_products)?;
+    let oversell = OversellPolicy::try_from(oversell)?;
+    let max_position = parse_decimal(max_position, "max_position")?;
+    if max_position <= Decimal::ZERO {
+        return Err(PyValueError::new_err("max_position must be positive"));
+    }
+    let max_skew_bps = parse_decimal(max_skew_bps, "max_skew_bps")?;
+    let base_quote_size = base_quote_size.map(|size| parse_decimal(size, "base_quote_size")).transpose()?;
+    let mid_price = mid_price.map(|price| parse_decimal(price, "mid_price")).transpose()?;
+    let products = ProductFilter::new(product_id.map(|id| vec![id]), None);
+
+    let mut parsed_trades = parse_trades(trades, None, &products)?;
+    check_single_product(py, &parsed_trades, mixed_products)?;
+    mark_inverse(&mut parsed_trades, inverse_products);
+    let options = ReplayOptions {
+        oversell,
+        ..ReplayOptions::default()
+    };
+    let fees = FeeSchedule::flat(Decimal::ZERO, Decimal::ZERO);
+    let position = build_entries(&parsed_trades, &fees, EntryScales::default(), options)?.open_position;
+    let ratio = (position.signed_size() / max_position).clamp(Decimal::NEGATIVE_ONE, Decimal::ONE);
+    let offset_bps = -ratio * max_skew_bps;
+    let bid_multiplier = Decimal::ONE - ratio;
+    let ask_multiplier = Decimal::ONE + ratio;
+
+    let result = PyDict::new_bound(py);
+    result.set_item("position", position.signed_size().to_string())?;
+    result.set_item("direction", position.direction())?;
+    result.set_item("average_entry_price", position.average_entry_price().map(|d| d.to_string()))?;
+    result.set_item("inventory_ratio", ratio.round_dp(6).normalize().to_string())?;
+    result.set_item("price_offset_bps", offset_bps.round_dp(4).normalize().to_string())?;
+    result.set_item(
+        "price_offset",
+        mid_price.map(|mid| (mid * offset_bps / Decimal::from(10_000)).normalize().to_string()),
+    )?;
+    result.set_item("bid_size_multiplier", bid_multiplier.round_dp(6).normalize().to_string())?;
+    result.set_item("ask_size_multiplier", ask_multiplier.round_dp(6).normalize().to_string())?;
+    result.set_item("bid_size", base_quote_size.map(|size| (size * bid_multiplier).normalize().to_string()))?;
+    result.set_item("ask_size", base_quote_size.map(|size| (size * ask_multiplier).normalize().to_string()))?;
+    Ok(result.into())
+}
+
+/// EWMA estimate of trade-to-trade return volatility for one product.
+struct VolatilityState {
+    last_price: Decimal,
+    last_timestamp: DateTime<Utc>,
+    /// EWMA of squared log returns, in bps squared.
+    variance: f64,
+    trade_count: u64,
+}
+
+/// Suggests a minimum profitable quoting spread per product: two maker fees
+/// plus `volatility_multiplier` times an EWMA volatility estimate (RiskMetrics
+/// style, weight `decay` on the previous variance). Trades are fed
+/// incrementally through `update`, so the estimate tracks the live tape.
+/// Figures are decimal strings unless `float_output` is set.
+#[pyclass(module = "_pnl_rs")]
+struct SpreadAdvisor {
+    fee_rate: Decimal,
+    decay: f64,
+    volatility_multiplier: Decimal,
+    output: OutputOptions,
+    products: BTreeMap<String, VolatilityState>,
+}
+
+impl SpreadAdvisor {
+    fn suggestion_to_py<'py>(&self, py: Python<'py>, product_id: &str, state: &VolatilityState) -> PyResult<Bound<'py, PyDict>> {
+        let output = self.output;
+        let fee_bps = self.fee_rate * Decimal::from(10_000);
+        let volatility_bps = Decimal::try_from(state.variance.sqrt()).unwrap_or_default().round_dp(4);
+        let min_spread_bps = Decimal::TWO * fee_bps + self.volatility_multiplier * volatility_bps;
+        let min_spread = state.last_price * min_spread_bps / Decimal::from(10_000);
+        let dict = PyDict::new_bound(py);
+        dict.set_item("product_id", (!product_id.is_empty()).then_some(product_id))?;
+        dict.set_item("trade_count", state.trade_count)?;
+        dict.set_item("last_price", output.value(py, state.last_price))?;
+        dict.set_item("volatility_bps", output.value(py, volatility_bps.normalize()))?;
+        dict.set_item("fee_bps", output.value(py, fee_bps.normalize()))?;
+        dict.set_item("min_spread_bps", output.value(py, min_spread_bps.round_dp(4).normalize()))?;
+        dict.set_item("min_spread", output.value(py, min_spread.round_dp(8).normalize()))?;
+        Ok(dict)
+    }
+}
+
+#[pymethods]
+impl SpreadAdvisor {
+    #[new]
+    #[pyo3(signature = (maker_fee_rate, *, decay = 0.94, volatility_multiplier = "1", float_output = false))]
+    fn new(maker_fee_rate: &str, decay: f64, volatility_multiplier: &str, float_output: bool) -> PyResult<Self> {
+        if !(0.0..1.0).contains(&decay) {
+            return Err(PyValueError::new_err("decay must be in [0, 1)"));
+        }
+        Ok(SpreadAdvisor {
+            fee_rate: parse_decimal(maker_fee_rate, "maker_fee_rate")?,
+            decay,
+            volatility_multiplier: parse_decimal(volatility_multiplier, "volatility_multiplier")?,
+            output: OutputOptions {
+                floats: float_output,
+                ..OutputOptions::default()
+            },
+            products: BTreeMap::new(),
+        })
+    }
+
+    /// Folds new trades into the estimate. A batch may arrive in any order
+    /// (it is sorted by time), but a trade older than the last one already
+    /// folded in for its product would corrupt the EWMA, so the whole batch
+    /// is rejected.
+    fn update(&mut self, trades: TradeInputs) -> PyResult<()> {
+        let trades = parse_trades(trades, None, &ProductFilter::default())?;
+        for trade in &trades {
+            let key = trade.product_id.as_deref().unwrap_or_default();
+            if let Some(state) = self.products.get(key).filter(|state| trade.timestamp < state.last_timestamp) {
+                return Err(PyValueError::new_err(format!(
+                    "trade at {} is older than the last trade folded in for {:?} ({})",
+                    trade.timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+                    key,
+                    state.last_timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+                )));
+            }
+        }
+        for trade in trades {
+            let key = trade.product_id.as_deref().unwrap_or_default();
+            match self.products.get_mut(key) {
+                Some(state) => {
+                    let ratio = (trade.price / state.last_price).to_f64().unwrap_or(1.0);
+                    let return_bps = ratio.ln() * 10_000.0;
+                    state.variance = self.decay * state.variance + (1.0 - self.decay) * return_bps * return_bps;
+                    state.last_price = trade.price;
+                    state.last_timestamp = trade.timestamp;
+                    state.trade_count += 1;
+                }
+                None => {
+                    self.products.insert(
+                        key.to_string(),
+                        VolatilityState {
+                            last_price: trade.price,
+                            last_timestamp: trade.timestamp,
+                            variance: 0.0,
+                            trade_count: 1,
+                        },
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Suggestion for one product (`None` for trades without a product id).
+    #[pyo3(signature = (product_id = None))]
+    fn suggest(&self, py: Python<'_>, product_id: Option<&str>) -> PyResult<Option<PyObject>> {
+        let key = product_id.unwrap_or_default();
+        self.products
+            .get(key)
+            .map(|state| Ok(self.suggestion_to_py(py, key, state)?.into()))
+            .transpose()
+    }
+
+    fn suggestions(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let rows = PyList::empty_bound(py);
+        for (product_id, state) in &self.products {
+            rows.append(self.suggestion_to_py(py, product_id, state)?)?;
+        }
+        Ok(rows.into())
+    }
+}
+
+/// Conversion rates ordered by the time they take effect.
+struct RateTable(Vec<(DateTime<Utc>, Decimal)>);
+
+impl RateTable {
+    fn parse(input: &ConversionInput) -> PyResult<Self> {
+        match input {
+            ConversionInput::Spot(price) => Ok(RateTable(vec![(
+                DateTime::<Utc>::MIN_UTC,
+                parse_decimal(price, "conversion price")?,
+            )])),
+            ConversionInput::Table(points) => {
+                let mut rates = Vec::with_capacity(points.len());
+                for point in points {
+                    rates.push((
+                        timestamp_us_to_datetime(point.timestamp_us)?,
+                        parse_decimal(&point.price, "conversion price")?,
+                    ));
+                }
+                if rates.is_empty() {
+                    return Err(PyValueError::new_err("conversion price table is empty"));
+                }
+                rates.sort_by_key(|(timestamp, _)| *timestamp);
+                Ok(RateTable(rates))
+            }
+        }
+    }
+
+    /// Rate in effect at `timestamp`; times before the table use its first row.
+    fn rate_at(&self, timestamp: DateTime<Utc>) -> Decimal {
+        let index = self.0.partition_point(|(effective, _)| *effective <= timestamp);
+        self.0[index.saturating_sub(1)].1
+    }
+}
+
+/// Restates an entry's money fields in the reporting currency.
+fn convert_entry(entry: &mut Entry, rate: Decimal) {
+    entry.realized_profit *= rate;
+    entry.maker_volume *= rate;
+    entry.taker_volume *= rate;
+    entry.fee *= rate;
+    entry.exposure_cost *= rate;
+}
+
+/// Interval figures that add up across products once converted to the
+/// reporting currency.
+#[derive(Default)]
+struct PortfolioMetrics {
+    profit_before_fees: Decimal,
+    fee_total: Decimal,
+    fees_paid: Decimal,
+    rebates_received: Decimal,
+    maker_volume: Decimal,
+    taker_volume: Decimal,
+    avg_exposure_notional: Decimal,
+}
+
+impl PortfolioMetrics {
+    fn add(&mut self, metrics: &RawMetrics) {
+        self.profit_before_fees += metrics.profit_before_fees;
+        self.fee_total += metrics.fee_total;
+        self.fees_paid += metrics.fees_paid;
+        self.rebates_received += metrics.rebates_received;
+        self.maker_volume += metrics.maker_volume;
+        self.taker_volume += metrics.taker_volume;
+        self.avg_exposure_notional += metrics.avg_exposure_notional;
+    }
+
+    fn to_py<'py>(&self, py: Python<'py>, spec: &IntervalSpec, output: OutputOptions) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("key", &spec.key)?;
+        dict.set_item("label", spec.label())?;
+        dict.set_item("profit_before_fees", output.value(py, self.profit_before_fees))?;
+        dict.set_item("maker_volume", output.value(py, self.maker_volume))?;
+        dict.set_item("taker_volume", output.value(py, self.taker_volume))?;
+        dict.set_item("fee_total", output.value(py, self.fee_total))?;
+        dict.set_item("fees_paid", output.value(py, self.fees_paid))?;
+        dict.set_item("rebates_received", output.value(py, self.rebates_received))?;
+        dict.set_item("profit_after_fees", output.value(py, self.profit_before_fees - self.fee_total))?;
+        dict.set_item("avg_exposure_notional", output.value(py, self.avg_exposure_notional))?;
+        Ok(dict)
+    }
+}
+
+/// Replays every product and returns its interval table, with the same
+/// metrics as `summarise_trades` in the product's own quote currency, plus a
+/// portfolio table. The portfolio only sums what adds up across instruments
+/// once each entry is converted to `reporting_currency` at the rate in effect
+/// at its timestamp: PnL, fees, volume and average exposure at cost. Prices,
+/// counts, sizes and drawdowns stay per product.
+#[pyfunction]
+#[pyo3(signature = (
+    products,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    conversion_prices,
+    *,
+    reporting_currency = "USD",
+    float_output = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn aggregate_portfolio(
+    py: Python<'_>,
+    products: Vec<PortfolioProduct>,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    conversion_prices: BTreeMap<String, ConversionInput>,
+    reporting_currency: &str,
+    float_output: bool,
+) -> PyResult<PyObject> {
+    let output = OutputOptions {
+        floats: float_output,
+        ..OutputOptions::default()
+    };
+    let now = timestamp_us_to_datetime(now_timestamp_us)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+    let mut prices: FxHashMap<String, RateTable> = FxHashMap::default();
+    for (currency, input) in &conversion_prices {
+        prices.insert(currency.to_ascii_uppercase(), RateTable::parse(input)?);
+    }
+    let unit = RateTable(vec![(DateTime::<Utc>::MIN_UTC, Decimal::ONE)]);
+
+    let mut starts = Vec::with_capacity(intervals.len());
+    for spec in &intervals {
+        starts.push(interval_start(now, spec, cutoff)?);
+    }
+
+    let mut totals: Vec<PortfolioMetrics> = intervals.iter().map(|_| PortfolioMetrics::default()).collect();
+    let products_py = PyList::empty_bound(py);
+    for product in products {
+        let quote = product_currencies(&product.product_id)
+            .map(|(_, quote)| quote.to_ascii_uppercase())
+            .ok_or_else(|| {
+                PyValueError::new_err(format!("cannot determine quote currency of {}", product.product_id))
+            })?;
+        let rates = if quote.eq_ignore_ascii_case(reporting_currency) {
+            &unit
+        } else {
+            prices.get(&quote).ok_or_else(|| {
+                PyValueError::new_err(format!("missing conversion price for {}", quote))
+            })?
+        };
+
+        let maker_fee = parse_decimal(&product.maker_fee_rate, "maker_fee_rate")?;
+        let taker_fee = parse_decimal(&product.taker_fee_rate, "taker_fee_rate")?;
+        let trades = parse_trades(product.trades, Some(cutoff), &ProductFilter::default())?;
+        let mut entries = build_entries(
+            &trades,
+            &FeeSchedule::flat(maker_fee, taker_fee),
+            EntryScales::default(),
+            ReplayOptions::default(),
+        )?
+        .entries;
+        let product_intervals = PyList::empty_bound(py);
+        for (spec, start) in intervals.iter().zip(&starts) {
+            product_intervals.append(summarise_interval(Ledger::decimal(&entries), *start, now)?.to_py(py, spec, output)?)?;
+        }
+        for entry in &mut entries {
+            convert_entry(entry, rates.rate_at(entry.timestamp));
+        }
+        for (total, start) in totals.iter_mut().zip(&starts) {
+            total.add(&summarise_interval(Ledger::decimal(&entries), *start, now)?);
+        }
+
+        let dict = PyDict::new_bound(py);
+        dict.set_item("product_id", &product.product_id)?;
+        dict.set_item("quote_currency", &quote)?;
+        dict.set_item("conversion_price", output.value(py, rates.rate_at(now)))?;
+        dict.set_item("trade_count", entries.len())?;
+        dict.set_item("intervals", product_intervals)?;
+        products_py.append(dict)?;
+    }
+
+    let result = PyDict::new_bound(py);
+    let intervals_py = PyList::empty_bound(py);
+    for (spec, total) in intervals.iter().zip(&totals) {
+        if spec.key == "all" {
+            result.set_item("total_profit_before_fees", output.value(py, total.profit_before_fees))?;
+            result.set_item("total_profit_after_fees", output.value(py, total.profit_before_fees - total.fee_total))?;
+        }
+        intervals_py.append(total.to_py(py, spec, output)?)?;
+    }
+    result.set_item("reporting_currency", reporting_currency)?;
+    result.set_item("intervals", intervals_py)?;
+    result.set_item("products", products_py)?;
+    Ok(result.into())
+}
+
+/// Strings some feeds send in place of JSON null.
+fn is_null_sentinel(text: &str) -> bool {
+    let text = text.trim();
+    ["null", "none", "n/a"].iter().any(|sentinel| text.eq_ignore_ascii_case(sentinel))
+}
+
+/// Deserializes an optional string, reading null sentinels as absent.
+fn nullable_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(Option::<String>::deserialize(deserializer)?.filter(|text| !is_null_sentinel(text)))
+}
+
+/// `nullable_string` for time fields. Some payloads send epoch numbers; those
+/// are read here and kept as RFC 3339 text, so only numeric fields are ever
+/// taken as epochs.
+fn nullable_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    Ok(match Option::<Value>::deserialize(deserializer)? {
+        Some(value @ Value::Number(_)) => {
+            parse_datetime_value(Some(&value)).map(|dt| dt.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        }
+        value => value.as_ref().and_then(value_to_string),
+    })
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) if is_null_sentinel(s) => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn option_to_string(value: Option<&Value>) -> Option<String> {
+    value.and_then(value_to_string)
+}
+
+fn decimal_from_value(value: Option<&Value>) -> Option<Decimal> {
+    option_to_string(value).and_then(|text| Decimal::from_str(text.trim()).ok())
+}
+
+fn parse_datetime_text(text: &str) -> Option<DateTime<Utc>> {
+    let trimmed = text.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    if let Ok(dt) = DateTime::parse_from_rfc3339(trimmed) {
+        return Some(dt.with_timezone(&Utc));
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(dt.and_utc());
+    }
+    if let Ok(dt) = NaiveDateTime::parse_from_str(trimmed, "%Y-%m-%d %H:%M:%S%.f") {
+        return Some(dt.and_utc());
+    }
+    None
+}
+
+/// Epochs outside 2000-01-01..2100-01-01 UTC, zero included, are read as a
+/// missing time: they are placeholders or unit mix-ups, not real activity.
+const EPOCH_RANGE_US: std::ops::Range<i64> = 946_684_800_000_000..4_102_444_800_000_000;
+
+/// Reads a numeric epoch, inferring its unit from the magnitude: seconds up
+/// to 1e11 (year 5138), then milliseconds, microseconds and nanoseconds.
+fn epoch_to_datetime(epoch: Decimal) -> Option<DateTime<Utc>> {
+    let magnitude = epoch.abs();
+    let micros_per_unit = if magnitude < Decimal::from(100_000_000_000_i64) {
+        Decimal::from(1_000_000)
+    } else if magnitude < Decimal::from(100_000_000_000_000_i64) {
+        Decimal::from(1_000)
+    } else if magnitude < Decimal::from(100_000_000_000_000_000_i64) {
+        Decimal::ONE
+    } else {
+        Decimal::new(1, 3)
+    };
+    let micros = epoch.checked_mul(micros_per_unit)?.floor().to_i64()?;
+    if !EPOCH_RANGE_US.contains(&micros) {
+        return None;
+    }
+    timestamp_us_to_datetime(micros).ok()
+}
+
+/// Times are text, or epoch numbers; numeric strings are not epochs.
+fn parse_datetime_value(value: Option<&Value>) -> Option<DateTime<Utc>> {
+    match value? {
+        Value::Number(number) => {
+            let text = number.to_string();
+            let epoch = Decimal::from_str(&text).or_else(|_| Decimal::from_scientific(&text)).ok()?;
+            epoch_to_datetime(epoch)
+        }
+        Value::String(text) if !is_null_sentinel(text) => parse_datetime_text(text),
+        _ => None,
+    }
+}
+
+/// Fractional-second digits of emitted timestamps. Milliseconds by default;
+/// finer precision keeps fills within the same millisecond apart.
+#[derive(Clone, Copy, Default)]
+enum TimestampPrecision {
+    #[default]
+    Millis,
+    Micros,
+    Nanos,
+}
+
+impl TryFrom<&str> for TimestampPrecision {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "millis" => Ok(TimestampPrecision::Millis),
+            "micros" => Ok(TimestampPrecision::Micros),
+            "nanos" => Ok(TimestampPrecision::Nanos),
+            other => Err(PyValueError::new_err(format!("unknown timestamp precision: {}", other))),
+        }
+    }
+}
+
+impl TimestampPrecision {
+    fn format(self, dt: DateTime<Utc>) -> String {
+        let format = match self {
+            TimestampPrecision::Millis => SecondsFormat::Millis,
+            TimestampPrecision::Micros => SecondsFormat::Micros,
+            TimestampPrecision::Nanos => SecondsFormat::Nanos,
+        };
+        dt.to_rfc3339_opts(format, true)
+    }
+}
+
+fn parse_boolish(value: Option<&Value>) -> Option<bool> {
+    match value {
+        Some(Value::Bool(b)) => Some(*b),
+        Some(other) => option_to_string(Some(other)).as_deref().and_then(|s| {
+            let lower = s.trim().to_ascii_lowercase();
+            match lower.as_str() {
+                "true" => Some(true),
+                "false" => Some(false),
+                _ => None,
+            }
+        }),
+        None => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct FillData<'a> {
+    size: Decimal,
+    price: Decimal,
+    trade_time: Option<DateTime<Utc>>,
+    trade_time_raw: Option<&'a str>,
+    trade_id: Option<&'a str>,
+    /// From the fill's `liquidity_indicator`, when it says.
+    maker: Option<bool>,
+    commission: Option<Decimal>,
+}
+
+/// Fills grouped by order id. Keys borrow from the deserialized fills so large
+/// backfills don't pay for a String clone per fill, and FxHash replaces the
+/// default SipHash since order ids are not attacker-controlled.
+type FillMap<'a> = FxHashMap<&'a str, Vec<FillData<'a>>>;
+
+fn collect_fills(fills: &[RawFill]) -> FillMap<'_> {
+    let mut map: FillMap<'_> = FxHashMap::default();
+    map.reserve(fills.len());
+    for fill in fills {
+        let Some(order_id) = fill.order_id.as_deref().filter(|s| !s.is_empty()) else {
+            continue;
+        };
+        let size = decimal_from_value(fill.size.as_ref())
+            .or_else(|| decimal_from_value(fill.base_size.as_ref()));
+        let price = decimal_from_value(fill.price.as_ref())
+            .or_else(|| decimal_from_value(fill.unit_price.as_ref()))
+            .or_else(|| decimal_from_value(fill.average_price.as_ref()));
+        let (Some(size), Some(price)) = (size, price) else { continue; };
+        if size <= Decimal::ZERO || price <= Decimal::ZERO {
+            continue;
+        }
+        let trade_time = fill
+            .trade_time
+            .as_deref()
+            .and_then(parse_datetime_text);
+        map.entry(order_id)
+            .or_default()
+            .push(FillData {
+                size,
+                price,
+                trade_time,
+                trade_time_raw: trade_time.and(fill.trade_time.as_deref()),
+                trade_id: fill.trade_id.as_deref(),
+                maker: fill_is_maker(fill),
+                commission: decimal_from_value(fill.commission.as_ref()),
+            });
+    }
+    map
+}
+
+#[derive(Clone, Copy)]
+enum OrderConfigType {
+    Limit,
+    StopLimit,
+    TriggerBracket,
+    Market,
+}
+
+impl OrderConfigType {
+    fn as_str(self) -> &'static str {
+        match self {
+            OrderConfigType::Limit => "limit",
+            OrderConfigType::StopLimit => "stop_limit",
+            OrderConfigType::TriggerBracket => "trigger_bracket",
+            OrderConfigType::Market => "market",
+        }
+    }
+}
+
+/// Which way the market has to move to trigger a stop.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum StopDirection {
+    Up,
+    Down,
+}
+
+impl StopDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            StopDirection::Up => "up",
+            StopDirection::Down => "down",
+        }
+    }
+
+    /// Reads `STOP_DIRECTION_STOP_UP` / `STOP_DIRECTION_STOP_DOWN` (or a bare
+    /// `UP` / `DOWN`) from an order config.
+    fn parse(value: Option<&Value>) -> Option<Self> {
+        let text = value_to_string(value?)?.to_ascii_uppercase();
+        if text.ends_with("UP") {
+            Some(StopDirection::Up)
+        } else if text.ends_with("DOWN") {
+            Some(StopDirection::Down)
+        } else {
+            None
+        }
+    }
+
+    /// Protective default when the config doesn't say: buy stops fire on the
+    /// way up, sell stops on the way down.
+    fn for_side(side: Side) -> Self {
+        match side {
+            Side::Buy => StopDirection::Up,
+            Side::Sell => StopDirection::Down,
+        }
+    }
+
+    fn is_triggered(self, stop_price: Decimal, price: Decimal) -> bool {
+        match self {
+            StopDirection::Up => price >= stop_price,
+            StopDirection::Down => price <= stop_price,
+        }
+    }
+}
+
+struct OrderConfig<'a> {
+    kind: OrderConfigType,
+    entry: &'a serde_json::Map<String, Value>,
+    good_till_date: bool,
+}
+
+const ORDER_CONFIG_KEYS: [(&str, OrderConfigType, bool); 11] = [
+    ("limit_limit_gtd", OrderConfigType::Limit, true),
+    ("limit_limit_gtc", OrderConfigType::Limit, false),
+    ("limit_limit_fok", OrderConfigType::Limit, false),
+    ("sor_limit_ioc", OrderConfigType::Limit, false),
+    ("stop_limit_stop_limit_gtd", OrderConfigType::StopLimit, true),
+    ("stop_limit_stop_limit_gtc", OrderConfigType::StopLimit, false),
+    ("trigger_bracket_gtd", OrderConfigType::TriggerBracket, true),
+    ("trigger_bracket_gtc", OrderConfigType::TriggerBracket, false),
+    ("market_market_ioc", OrderConfigType::Market, false),
+    ("market_market_gtc", OrderConfigType::Market, false),
+    ("market_market_fok", OrderConfigType::Market, false),
+];
+
+fn extract_order_config(value: Option<&Value>) -> Option<OrderConfig<'_>> {
+    let container = value.and_then(|v| v.as_object())?;
+    ORDER_CONFIG_KEYS.iter().find_map(|(key, kind, good_till_date)| {
+        container.get(*key).and_then(|v| v.as_object()).map(|entry| OrderConfig {
+            kind: *kind,
+            entry,
+            good_till_date: *good_till_date,
+        })
+    })
+}
+
+/// Asset class of a product, so spot and derivatives can be aggregated apart.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProductType {
+    Spot,
+    Future,
+    Perpetual,
+}
+
+impl ProductType {
+    fn as_str(self) -> &'static str {
+        match self {
+            ProductType::Spot => "spot",
+            ProductType::Future => "future",
+            ProductType::Perpetual => "perpetual",
+        }
+    }
+
+    /// Uses the payload's `product_type` when present, otherwise the
+    /// product id: `*-PERP-*` is a perp, dated `*-CDE` contracts are futures.
+    fn detect(product_id: &str, explicit: Option<&str>) -> Self {
+        let is_perp = product_id.split('-').any(|part| part.eq_ignore_ascii_case("PERP"));
+        match explicit.map(|text| text.to_ascii_uppercase()).as_deref() {
+            Some("SPOT") => ProductType::Spot,
+            Some("FUTURE") if is_perp => ProductType::Perpetual,
+            Some("FUTURE") => ProductType::Future,
+            _ if is_perp => ProductType::Perpetual,
+            _ if product_id.to_ascii_uppercase().ends_with("-CDE") => ProductType::Future,
+            _ => ProductType::Spot,
+        }
+    }
+}
+
+/// A place an order timestamp can be read from when resolving `ts_submitted`
+/// or `ts_filled`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TimeSource {
+    SubmittedTime,
+    CreatedTime,
+    OrderPlacedTime,
+    LastFillTime,
+    CompletedTime,
+    FirstFill,
+    LastFill,
+}
+
+impl TryFrom<&str> for TimeSource {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "submitted_time" => Ok(TimeSource::SubmittedTime),
+            "created_time" => Ok(TimeSource::CreatedTime),
+            "order_placed_time" => Ok(TimeSource::OrderPlacedTime),
+            "last_fill_time" => Ok(TimeSource::LastFillTime),
+            "completed_time" => Ok(TimeSource::CompletedTime),
+            "first_fill" => Ok(TimeSource::FirstFill),
+            "last_fill" => Ok(TimeSource::LastFill),
+            other => Err(PyValueError::new_err(format!("unknown time source: {}", other))),
+        }
+    }
+}
+
+const DEFAULT_SUBMITTED_SOURCES: [TimeSource; 6] = [
+    TimeSource::SubmittedTime,
+    TimeSource::CreatedTime,
+    TimeSource::OrderPlacedTime,
+    TimeSource::LastFillTime,
+    TimeSource::FirstFill,
+    TimeSource::CompletedTime,
+];
+
+const DEFAULT_COMPLETED_SOURCES: [TimeSource; 2] = [TimeSource::CompletedTime, TimeSource::LastFill];
+
+fn parse_time_sources(names: Option<Vec<String>>, default: &[TimeSource]) -> PyResult<Vec<TimeSource>> {
+    match names {
+        Some(names) => names.iter().map(|name| TimeSource::try_from(name.as_str())).collect(),
+        None => Ok(default.to_vec()),
+    }
+}
+
+/// What to do when the same order_id appears more than once in a payload,
+/// which is common when pages are merged.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum DuplicatePolicy {
+    KeepAll,
+    KeepLatest,
+    Error,
+}
+
+impl TryFrom<&str> for DuplicatePolicy {
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, PyErr> {
+        match value {
+            "keep_all" => Ok(DuplicatePolicy::KeepAll),
+            "keep_latest" => Ok(DuplicatePolicy::KeepLatest),
+            "error" => Ok(DuplicatePolicy::Error),
+            other => Err(PyValueError::new_err(format!("unknown duplicate order policy: {}", other))),
+        }
+    }
+}
+
+/// Ranks statuses for `DuplicatePolicy::KeepLatest` so a stale page cannot
+/// bring a finished order back to life. Ties go to the later copy.
+fn status_precedence(order: &RawOrder) -> u8 {
+    match normalise_status(order.status.as_deref().or(order.legacy_status.as_deref())).as_ref() {
+        "FILLED" => 3,
+        "CANCELLED" | "EXPIRED" | "FAILED" => 2,
+        "OPEN" => 1,
+        _ => 0,
+    }
+}
+
+/// Collapses repeated order_ids according to `policy`, returning the orders
+/// to process and how many copies were merged away.
+fn dedupe_orders(orders: Vec<RawOrder>, policy: DuplicatePolicy) -> PyResult<(Vec<RawOrder>, usize)> {
+    if policy == DuplicatePolicy::KeepAll {
+        return Ok((orders, 0));
+    }
+    let mut kept: Vec<RawOrder> = Vec::with_capacity(orders.len());
+    let mut positions: FxHashMap<String, usize> = FxHashMap::default();
+    let mut merged = 0;
+    for order in orders {
+        let Some(order_id) = order.order_id.clone().filter(|id| !id.is_empty()) else {
+            kept.push(order);
+            continue;
+        };
+        match positions.get(&order_id) {
+            None => {
+                positions.insert(order_id, kept.len());
+                kept.push(order);
+            }
+            Some(_) if policy == DuplicatePolicy::Error => {
+                return Err(PyValueError::new_err(format!("duplicate order_id in orders payload: {}", order_id)));
+            }
+            Some(&index) => {
+                merged += 1;
+                if status_precedence(&order) >= status_precedence(&kept[index]) {
+                    kept[index] = order;
+                }
+            }
+        }
+    }
+    Ok((kept, merged))
+}
+
+/// Options controlling how `process_orders_internal` interprets orders.
+struct ProcessOptions {
+    /// Statuses that count as still able to execute; these orders produce
+    /// open records. Stored upper-cased.
+    working_statuses: Vec<String>,
+    submitted_sources: Vec<TimeSource>,
+    completed_sources: Vec<TimeSource>,
+    sort_records: bool,
+    /// Reference time for expiry checks.
+    now: DateTime<Utc>,
+    /// When set, open records with nothing left to fill, or with less than
+    /// this size remaining, are dropped.
+    dust_size: Option<Decimal>,
+    watch: Option<Arc<Watch>>,
+}
+
+impl ProcessOptions {
+    fn is_working(&self, status: &str) -> bool {
+        self.working_statuses.iter().any(|working| working == status)
+    }
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            working_statuses: vec!["OPEN".to_string()],
+            submitted_sources: DEFAULT_SUBMITTED_SOURCES.to_vec(),
+            completed_sources: DEFAULT_COMPLETED_SOURCES.to_vec(),
+            sort_records: false,
+            now: Utc::now(),
+            dust_size: None,
+            watch: None,
+        }
+    }
+}
+
+/// A parsed timestamp together with the payload text it came from, so the
+/// original offset can be shown back verbatim.
+type Stamp<'a> = (DateTime<Utc>, &'a str);
+
+/// Reads a single time source. The order's `completed_time` field is ignored
+/// while the order is still OPEN.
+fn read_time_source<'a>(
+    source: TimeSource,
+    order: &'a RawOrder,
+    fills: Option<&Vec<FillData<'a>>>,
+    is_open: bool,
+) -> Option<Stamp<'a>> {
+    let field = |value: &'a Option<String>| {
+        let text = value.as_deref()?;
+        Some((parse_datetime_text(text)?, text))
+    };
+    let fill_stamp = |fill: &FillData<'a>| Some((fill.trade_time?, fill.trade_time_raw?));
+    match source {
+        TimeSource::SubmittedTime => field(&order.submitted_time),
+        TimeSource::CreatedTime => field(&order.created_time),
+        TimeSource::OrderPlacedTime => field(&order.order_placed_time),
+        TimeSource::LastFillTime => field(&order.last_fill_time),
+        TimeSource::CompletedTime if is_open => None,
+        TimeSource::CompletedTime => field(&order.completed_time),
+        TimeSource::FirstFill => fills?.iter().filter_map(fill_stamp).min_by_key(|(dt, _)| *dt),
+        TimeSource::LastFill => fills?.iter().filter_map(fill_stamp).next_back(),
+    }
+}
+
+fn resolve_completed_time<'a>(
+    order: &'a RawOrder,
+    fills: Option<&Vec<FillData<'a>>>,
+    is_open: bool,
+    sources: &[TimeSource],
+) -> Option<Stamp<'a>> {
+    sources
+        .iter()
+        .find_map(|source| read_time_source(*source, order, fills, is_open))
+}
+
+/// Submission time, whether it had to be inferred, and the text it was read
+/// from (none when inferred).
+fn resolve_submitted_time<'a>(
+    order: &'a RawOrder,
+    fills: Option<&Vec<FillData<'a>>>,
+    completed: Option<Stamp<'a>>,
+    sources: &[TimeSource],
+) -> (DateTime<Utc>, bool, Option<&'a str>) {
+    // Within the submitted chain CompletedTime means the resolved ts_filled.
+    let resolved = sources.iter().find_map(|source| match source {
+        TimeSource::CompletedTime => completed,
+        other => read_time_source(*other, order, fills, false),
+    });
+    match resolved {
+        Some((dt, raw)) => (dt, false, Some(raw)),
+        None => (Utc::now(), true, None),
+    }
+}
+
+fn average_fill_price(fills: Option<&Vec<FillData<'_>>>) -> Option<Decimal> {
+    let fills_vec = fills?;
+    let mut total_size = Decimal::ZERO;
+    let mut total_value = Decimal::ZERO;
+    for fill in fills_vec {
+        if fill.size <= Decimal::ZERO || fill.price <= Decimal::ZERO {
+            continue;
+        }
+        total_size += fill.size;
+        total_value += fill.size * fill.price;
+    }
+    if total_size > Decimal::ZERO && total_value > Decimal::ZERO {
+        Some(total_value / total_size)
+    } else {
+        None
+    }
+}
+
+// Records borrow their text fields from the deserialized orders; strings are
+// only materialized when the Python dicts are built.
+struct ProcessedOpenRecord<'a> {
+    order_id: &'a str,
+    side: Side,
+    limit_price: Decimal,
+    base_size: Decimal,
+    status: Cow<'a, str>,
+    client_order_id: &'a str,
+    end_time: Option<DateTime<Utc>>,
+    /// Payload text `end_time` was read from, offset included.
+    end_time_raw: Option<&'a str>,
+    product_id: &'a str,
+    stop_price: Option<Decimal>,
+    filled_size: Option<Decimal>,
+    avg_fill_price: Option<Decimal>,
+    /// GTD order past its end_time whose status the exchange hasn't updated yet.
+    expired_pending: bool,
+    leverage: Option<Decimal>,
+    margin_type: Option<&'a str>,
+    product_type: ProductType,
+}
+
+impl ProcessedOpenRecord<'_> {
+    fn remaining_size(&self) -> Decimal {
+        let filled = self.filled_size.unwrap_or(Decimal::ZERO);
+        (self.base_size - filled).max(Decimal::ZERO)
+    }
+
+    fn percent_filled(&self) -> Decimal {
+        let filled = self.filled_size.unwrap_or(Decimal::ZERO);
+        if self.base_size > Decimal::ZERO {
+            (filled / self.base_size * Decimal::ONE_HUNDRED).min(Decimal::ONE_HUNDRED)
+        } else {
+            Decimal::ZERO
+        }
+    }
+}
+
+struct ProcessedExecutedRecord<'a> {
+    order_id: &'a str,
+    ts_submitted: DateTime<Utc>,
+    ts_submitted_inferred: bool,
+    ts_filled: Option<DateTime<Utc>>,
+    /// Payload text the two timestamps were read from, offset included.
+    ts_submitted_raw: Option<&'a str>,
+    ts_filled_raw: Option<&'a str>,
+    side: Side,
+    limit_price: Decimal,
+    base_size: Decimal,
+    status: Cow<'a, str>,
+    filled_size: Option<Decimal>,
+    client_order_id: &'a str,
+    end_time: Option<DateTime<Utc>>,
+    end_time_raw: Option<&'a str>,
+    product_id: &'a str,
+    stop_price: Option<Decimal>,
+    post_only: bool,
+    /// Whether any fill took liquidity, per the fills' liquidity indicator;
+    /// falls back to `!post_only` when no fill carries one.
+    taker: bool,
+    avg_fill_price: Option<Decimal>,
+    leverage: Option<Decimal>,
+    margin_type: Option<&'a str>,
+    product_type: ProductType,
+    order_type: OrderConfigType,
+    stop_direction: Option<StopDirection>,
+    oco_group: Option<&'a str>,
+    parent_order_id: Option<&'a str>,
+    /// Sibling whose execution logically cancelled this working order.
+    oco_cancelled_by: Option<&'a str>,
+    /// Reference price when the order arrived; see `PriceSeries`.
+    arrival_price: Option<Decimal>,
+    edit_count: usize,
+    fill_count: usize,
+}
+
+impl ProcessedExecutedRecord<'_> {
+    /// Full contract value of what executed; zero until something fills.
+    /// Venues bill fees on this figure, not on the margin posted, so fee
+    /// maths keeps using it.
+    fn notional(&self) -> Decimal {
+        match (self.filled_size, self.avg_fill_price) {
+            (Some(size), Some(price)) => size * price,
+            _ => Decimal::ZERO,
+        }
+    }
+
+    /// Collateral tied up by the position: notional divided by leverage.
+    fn margin_used(&self) -> Decimal {
+        match self.leverage {
+            Some(leverage) if leverage > Decimal::ZERO => self.notional() / leverage,
+            _ => self.notional(),
+        }
+    }
+
+    /// Per-unit distance of the average fill from `reference`, signed so a
+    /// positive value is slippage (paid more / received less) and a negative
+    /// one price improvement.
+    fn slippage_from(&self, reference: Decimal) -> Option<Decimal> {
+        let avg_fill = self.avg_fill_price?;
+        (reference > Decimal::ZERO && self.filled_size.is_some()).then(|| match self.side {
+            Side::Buy => avg_fill - reference,
+            Side::Sell => reference - avg_fill,
+        })
+    }
+
+    /// Slippage against the order's own limit. Market orders have no limit
+    /// of their own, so they are skipped.
+    fn limit_slippage(&self) -> Option<Decimal> {
+        if matches!(self.order_type, OrderConfigType::Market) {
+            return None;
+        }
+        self.slippage_from(self.limit_price)
+    }
+
+    fn arrival_slippage(&self) -> Option<Decimal> {
+        self.slippage_from(self.arrival_price?)
+    }
+}
+
+/// Reference prices over time, such as arrival (decision) prices or mids:
+/// explicit per-order values win, otherwise the last point of `series` at or
+/// before the moment asked about.
+struct PriceSeries {
+    by_order: FxHashMap<String, Decimal>,
+    series: Vec<(DateTime<Utc>, Decimal)>,
+}
+
+impl PriceSeries {
+    fn parse(
+        by_order: Option<BTreeMap<String, String>>,
+        series: Option<Vec<PricePointSpec>>,
+    ) -> PyResult<Option<Self>> {
+        if by_order.is_none() && series.is_none() {
+            return Ok(None);
+        }
+        let by_order = by_order
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(order_id, price)| Ok((order_id, parse_decimal(&price, "arrival price")?)))
+            .collect::<PyResult<_>>()?;
+        let mut points = Vec::new();
+        for point in series.unwrap_or_default() {
+            points.push((timestamp_us_to_datetime(point.timestamp_us)?, parse_decimal(&point.price, "price")?));
+        }
+        points.sort_by_key(|(timestamp, _)| *timestamp);
+        Ok(Some(PriceSeries { by_order, series: points }))
+    }
+
+    fn lookup(&self, order_id: &str, submitted: DateTime<Utc>) -> Option<Decimal> {
+        self.by_order.get(order_id).copied().or_else(|| self.series_at(submitted))
+    }
+
+    /// Last series price at or before `timestamp`.
+    fn series_at(&self, timestamp: DateTime<Utc>) -> Option<Decimal> {
+        let index = self.series.partition_point(|(point, _)| *point <= timestamp);
+        index.checked_sub(1).map(|index| self.series[index].1)
+    }
+
+    fn assign(&self, records: &mut [ProcessedExecutedRecord<'_>]) {
+        for record in records {
+            record.arrival_price = self.lookup(record.order_id, record.ts_submitted);
+        }
+    }
+}
+
+/// Slippage per unit expressed in basis points of `reference`.
+fn slippage_bps(slippage: Decimal, reference: Decimal) -> Decimal {
+    (slippage / reference * Decimal::from(10_000)).round_dp(4).normalize()
+}
+
+/// How often, and by how much, taker orders filled better than the price
+/// they were expected to get: the limit, or the arrival price for market
+/// orders. Market orders without an arrival price are only counted.
+#[derive(Default)]
+struct PriceImprovement {
+    taker_orders: u64,
+    missing_arrival_orders: u64,
+    improved_orders: u64,
+    /// Quote gained on improved orders.
+    improvement: Decimal,
+    /// Reference price times filled size of the improved orders.
+    improved_notional: Decimal,
+}
+
+impl PriceImprovement {
+    fn add(&mut self, record: &ProcessedExecutedRecord<'_>, filled: Decimal) {
+        let reference = match record.order_type {
+            OrderConfigType::Market => record.arrival_price,
+            _ => Some(record.limit_price),
+        };
+        let Some(reference) = reference else {
+            self.missing_arrival_orders += 1;
+            return;
+        };
+        let Some(slippage) = record.slippage_from(reference) else {
+            return;
+        };
+        self.taker_orders += 1;
+        if slippage < Decimal::ZERO {
+            self.improved_orders += 1;
+            self.improvement -= slippage * filled;
+            self.improved_notional += reference * filled;
+        }
+    }
+
+    fn to_py<'py>(&self, py: Python<'py>, product_id: &str, output: OutputOptions) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("product_id", product_id)?;
+        dict.set_item("taker_orders", self.taker_orders)?;
+        dict.set_item("missing_arrival_orders", self.missing_arrival_orders)?;
+        dict.set_item("improved_orders", self.improved_orders)?;
+        dict.set_item(
+            "improvement_rate",
+            (self.taker_orders > 0).then(|| {
+                let rate = Decimal::from(self.improved_orders) / Decimal::from(self.taker_orders);
+                output.value(py, rate.round_dp(4).normalize())
+            }),
+        )?;
+        dict.set_item("improvement", output.value(py, self.improvement))?;
+        dict.set_item(
+            "improvement_bps",
+            (!self.improved_notional.is_zero())
+                .then(|| output.value(py, slippage_bps(self.improvement, self.improved_notional))),
+        )?;
+        Ok(dict)
+    }
+}
+
+/// Execution-quality totals for the orders filled within one interval.
+#[derive(Default)]
+struct ExecutionStats<'a> {
+    order_count: u64,
+    filled_size: Decimal,
+    /// Sum of per-unit limit slippage times filled size, in quote.
+    limit_slippage_cost: Decimal,
+    /// Limit price times filled size over the same orders, for the bps figure.
+    limit_notional: Decimal,
+    arrival_slippage_cost: Decimal,
+    arrival_notional: Decimal,
+    price_improvement: BTreeMap<&'a str, PriceImprovement>,
+}
+
+impl<'a> ExecutionStats<'a> {
+    fn add(&mut self, record: &ProcessedExecutedRecord<'a>) {
+        let Some(filled) = record.filled_size else {
+            return;
+        };
+        self.order_count += 1;
+        self.filled_size += filled;
+        if let Some(slippage) = record.limit_slippage() {
+            self.limit_slippage_cost += slippage * filled;
+            self.limit_notional += record.limit_price * filled;
+        }
+        if let (Some(slippage), Some(arrival)) = (record.arrival_slippage(), record.arrival_price) {
+            self.arrival_slippage_cost += slippage * filled;
+            self.arrival_notional += arrival * filled;
+        }
+        if record.taker {
+            self.price_improvement.entry(record.product_id).or_default().add(record, filled);
+        }
+    }
+
+    fn to_py<'py>(&self, py: Python<'py>, spec: &IntervalSpec, output: OutputOptions) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("key", &spec.key)?;
+        dict.set_item("label", spec.label())?;
+        dict.set_item("order_count", self.order_count)?;
+        dict.set_item("filled_size", output.value(py, self.filled_size))?;
+        dict.set_item("limit_slippage_cost", output.value(py, self.limit_slippage_cost))?;
+        dict.set_item(
+            "limit_slippage_bps",
+            (!self.limit_notional.is_zero())
+                .then(|| output.value(py, slippage_bps(self.limit_slippage_cost, self.limit_notional))),
+        )?;
+        dict.set_item("arrival_slippage_cost", output.value(py, self.arrival_slippage_cost))?;
+        dict.set_item(
+            "arrival_slippage_bps",
+            (!self.arrival_notional.is_zero())
+                .then(|| output.value(py, slippage_bps(self.arrival_slippage_cost, self.arrival_notional))),
+        )?;
+        let improvement = PyList::empty_bound(py);
+        for (product_id, stats) in &self.price_improvement {
+            improvement.append(stats.to_py(py, product_id, output)?)?;
+        }
+        dict.set_item("price_improvement", improvement)?;
+        Ok(dict)
+    }
+}
+
+/// Message-efficiency figures venues watch, over the orders submitted within
+/// one interval. Messages are submissions, replaces (edits) and cancels.
+#[derive(Default)]
+struct OrderFlowStats {
+    orders: u64,
+    replaces: u64,
+    cancels: u64,
+    trades: u64,
+    lifetime_seconds: i64,
+    finished_orders: u64,
+}
+
+impl OrderFlowStats {
+    fn add(&mut self, record: &ProcessedExecutedRecord<'_>, options: &ProcessOptions) {
+        self.orders += 1;
+        self.replaces += record.edit_count as u64;
+        self.trades += record.fill_count as u64;
+        if record.status == "CANCELLED" {
+            self.cancels += 1;
+        }
+        if let (Some(finished), false) = (record.ts_filled, options.is_working(&record.status)) {
+            self.lifetime_seconds += (finished - record.ts_submitted).num_seconds().max(0);
+            self.finished_orders += 1;
+        }
+    }
+
+    fn to_py<'py>(&self, py: Python<'py>, spec: &IntervalSpec) -> PyResult<Bound<'py, PyDict>> {
+        let messages = self.orders + self.replaces + self.cancels;
+        let ratio = |numerator: u64, denominator: u64| {
+            (denominator > 0).then(|| {
+                (Decimal::from(numerator) / Decimal::from(denominator)).round_dp(4).normalize().to_string()
+            })
+        };
+        let dict = PyDict::new_bound(py);
+        dict.set_item("key", &spec.key)?;
+        dict.set_item("label", spec.label())?;
+        dict.set_item("orders", self.orders)?;
+        dict.set_item("replaces", self.replaces)?;
+        dict.set_item("cancels", self.cancels)?;
+        dict.set_item("trades", self.trades)?;
+        dict.set_item("messages", messages)?;
+        dict.set_item("order_to_trade_ratio", ratio(messages, self.trades))?;
+        dict.set_item("cancel_rate", ratio(self.cancels, self.orders))?;
+        dict.set_item(
+            "avg_order_lifetime_seconds",
+            (self.finished_orders > 0).then(|| self.lifetime_seconds as f64 / self.finished_orders as f64),
+        )?;
+        Ok(dict)
+    }
+}
+
+/// Per-interval `OrderFlowStats`, placing each order at its submission.
+fn order_flow_to_py<'py>(
+    py: Python<'py>,
+    records: &[ProcessedExecutedRecord<'_>],
+    intervals: &[IntervalSpec],
+    options: &ProcessOptions,
+    cutoff: DateTime<Utc>,
+) -> PyResult<Bound<'py, PyList>> {
+    let rows = PyList::empty_bound(py);
+    for spec in intervals {
+        let start = interval_start(options.now, spec, cutoff)?;
+        let mut stats = OrderFlowStats::default();
+        for record in records {
+            if record.ts_submitted >= start && record.ts_submitted <= options.now {
+                stats.add(record, options);
+            }
+        }
+        rows.append(stats.to_py(py, spec)?)?;
+    }
+    Ok(rows)
+}
+
+/// Per-interval `ExecutionStats`, placing each order at its fill time.
+fn execution_quality_to_py<'py>(
+    py: Python<'py>,
+    records: &[ProcessedExecutedRecord<'_>],
+    intervals: &[IntervalSpec],
+    now: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    let rows = PyList::empty_bound(py);
+    for spec in intervals {
+        let start = interval_start(now, spec, cutoff)?;
+        let mut stats = ExecutionStats::default();
+        for record in records {
+            let timestamp = record.ts_filled.unwrap_or(record.ts_submitted);
+            if timestamp >= start && timestamp <= now {
+                stats.add(record);
+            }
+        }
+        rows.append(stats.to_py(py, spec, output)?)?;
+    }
+    Ok(rows)
+}
+
+fn normalise_status(raw: Option<&str>) -> Cow<'_, str> {
+    match raw {
+        Some(text) if text.bytes().any(|b| b.is_ascii_lowercase()) => {
+            Cow::Owned(text.to_ascii_uppercase())
+        }
+        Some(text) => Cow::Borrowed(text),
+        None => Cow::Borrowed("NEW"),
+    }
+}
+
+type ProcessedRecords<'a> = (Vec<ProcessedOpenRecord<'a>>, Vec<ProcessedExecutedRecord<'a>>);
+
+fn process_orders_internal<'a>(
+    orders: &'a [RawOrder],
+    fills: &'a [RawFill],
+    default_product_id: &'a str,
+    options: &ProcessOptions,
+) -> Result<ProcessedRecords<'a>, String> {
+    let fills_by_order = collect_fills(fills);
+    let mut open_records: Vec<ProcessedOpenRecord<'a>> = Vec::new();
+    let mut executed_records: Vec<ProcessedExecutedRecord<'a>> = Vec::with_capacity(orders.len());
+    let watch = Tracker(options.watch.as_deref());
+
+    for (index, order) in orders.iter().enumerate() {
+        if watch.stop(index) {
+            return Err("computation cancelled".to_string());
+        }
+        let Some(order_id) = order.order_id.as_deref().filter(|s| !s.is_empty()) else {
+            continue;
+        };
+
+        let status = normalise_status(order.status.as_deref().or(order.legacy_status.as_deref()));
+
+        let Some(order_config) = extract_order_config(order.order_configuration.as_ref()) else {
+            continue;
+        };
+        let config_type = order_config.kind;
+        let config = order_config.entry;
+
+        let client_order_id = order.client_order_id.as_deref().unwrap_or_default();
+        let side = order
+            .side
+            .as_deref()
+            .and_then(|text| Side::try_from(text).ok())
+            .unwrap_or(Side::Buy);
+
+        let fills_vec = fills_by_order.get(order_id);
+        let filled_size = fills_vec.map(|vec| {
+            vec.iter().fold(Decimal::ZERO, |acc, fill| acc + fill.size)
+        }).filter(|total| *total > Decimal::ZERO);
+
+        let is_working = options.is_working(&status);
+        let completed_stamp =
+            resolve_completed_time(order, fills_vec, is_working, &options.completed_sources);
+        let completed_time = completed_stamp.map(|(dt, _)| dt);
+
+        let (submitted_time, submitted_inferred, submitted_raw) =
+            resolve_submitted_time(order, fills_vec, completed_stamp, &options.submitted_sources);
+
+        let mut base_size = decimal_from_value(config.get("base_size"))
+            .or_else(|| decimal_from_value(config.get("base_order_size")))
+            .unwrap_or(Decimal::ZERO);
+        if let (true, Some(filled)) = (base_size.is_zero(), filled_size) {
+            base_size = filled;
+        }
+
+        let order_avg_price = order
+            .average_filled_price
+            .as_ref()
+            .and_then(|text| Decimal::from_str(text.trim()).ok());
+
+        let product_id = order
+            .product_id
+            .as_deref()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(default_product_id);
+
+        let expire_time = order
+            .expire_time
+            .as_deref()
+            .and_then(parse_datetime_text);
+
+        let avg_fill_price = average_fill_price(fills_vec);
+        let leverage = decimal_from_value(order.leverage.as_ref());
+        let margin_type = order.margin_type.as_deref().filter(|s| !s.is_empty());
+        let product_type = ProductType::detect(product_id, order.product_type.as_deref());
+
+        // A stop-limit or bracket config without a usable limit price would
+        // zero out notionals downstream, so fall back to the fill price as
+        // market orders already do.
+        let limit_price_or_fill = || {
+            decimal_from_value(config.get("limit_price"))
+                .filter(|price| *price > Decimal::ZERO)
+                .or(avg_fill_price)
+                .or(order_avg_price)
+                .unwrap_or(Decimal::ZERO)
+        };
+        // Explicit end (config end_time, else expire_time) with the payload
+        // text it was read from; numeric values have no raw text.
+        let configured_end = parse_datetime_value(config.get("end_time"))
+            .map(|end| (end, config.get("end_time").and_then(Value::as_str)))
+            .or_else(|| expire_time.map(|end| (end, order.expire_time.as_deref())));
+        let submitted_end = (submitted_time, submitted_raw);
+        let (limit_price, stop_price, (end_time, end_time_raw), post_only_flag) = match config_type {
+            OrderConfigType::Market => {
+                let limit_price = avg_fill_price
+                    .or(order_avg_price)
+                    .unwrap_or(Decimal::ZERO);
+                let end_time = completed_stamp.map(|(end, raw)| (end, Some(raw))).unwrap_or(submitted_end);
+                (limit_price, None, end_time, false)
+            }
+            OrderConfigType::TriggerBracket => {
+                let limit_price = limit_price_or_fill();
+                let stop_price = decimal_from_value(config.get("stop_trigger_price"))
+                    .or_else(|| decimal_from_value(config.get("stop_price")));
+                let end_time = configured_end.unwrap_or(submitted_end);
+                (limit_price, stop_price, end_time, false)
+            }
+            OrderConfigType::StopLimit => {
+                let limit_price = limit_price_or_fill();
+                let stop_price = decimal_from_value(config.get("stop_price"));
+                let end_time = configured_end.unwrap_or(submitted_end);
+                (limit_price, stop_price, end_time, false)
+            }
+            OrderConfigType::Limit => {
+                let limit_price = decimal_from_value(config.get("limit_price"))
+                    .unwrap_or(Decimal::ZERO);
+                let raw_post_only = parse_boolish(config.get("post_only"));
+                let post_only = raw_post_only.unwrap_or(false);
+                let end_time = configured_end.unwrap_or(submitted_end);
+                (limit_price, None, end_time, post_only)
+            }
+        };
+
+        // Only an explicit expiry counts; the submitted-time fallback above
+        // would make every GTD order look expired.
+        let expired_pending =
+            order_config.good_till_date && configured_end.is_some_and(|(expiry, _)| expiry <= options.now);
+        let end_time = Some(end_time);
+
+        if is_working {
+            let record = ProcessedOpenRecord {
+                order_id,
+                side,
+                limit_price,
+                base_size,
+                status: status.clone(),
+                client_order_id,
+                end_time,
+                end_time_raw,
+                product_id,
+                stop_price,
+                filled_size,
+                avg_fill_price,
+                expired_pending,
+                leverage,
+                margin_type,
+                product_type,
+            };
+            let is_dust = options.dust_size.is_some_and(|dust| {
+                let remaining = record.remaining_size();
+                remaining.is_zero() || remaining < dust
+            });
+            if !is_dust {
+                open_records.push(record);
+            }
+        }
+
+        executed_records.push(ProcessedExecutedRecord {
+            order_id,
+            ts_submitted: submitted_time,
+            ts_submitted_inferred: submitted_inferred,
+            ts_filled: completed_time,
+            ts_submitted_raw: submitted_raw,
+            ts_filled_raw: completed_stamp.map(|(_, raw)| raw),
+            side,
+            limit_price,
+            base_size,
+            status,
+            filled_size,
+            client_order_id,
+            end_time,
+            end_time_raw,
+            product_id,
+            stop_price,
+            post_only: matches!(config_type, OrderConfigType::Limit) && post_only_flag,
+            taker: fills_vec
+                .and_then(|fills| fills.iter().filter_map(|fill| fill.maker).reduce(|all, maker| all && maker))
+                .map_or(!(matches!(config_type, OrderConfigType::Limit) && post_only_flag), |maker| !maker),
+            avg_fill_price,
+            leverage,
+            margin_type,
+            product_type,
+            order_type: config_type,
+            stop_direction: StopDirection::parse(config.get("stop_direction")),
+            oco_group: order.oco_group_id.as_deref().filter(|s| !s.is_empty()),
+            parent_order_id: order.originating_order_id.as_deref().filter(|s| !s.is_empty()),
+            oco_cancelled_by: None,
+            arrival_price: None,
+            edit_count: order.edit_history.as_ref().map_or(0, Vec::len),
+            fill_count: fills_vec.map_or(0, Vec::len),
+        });
+    }
+    watch.done(orders.len());
+
+    apply_oco_groups(&mut open_records, &mut executed_records, options);
+
+    if options.sort_records {
+        sort_records(&mut open_records, &mut executed_records);
+    }
+
+    Ok((open_records, executed_records))
+}
+
+/// Once any leg of a one-cancels-other group has executed, its still-working
+/// siblings are reported as cancelled and dropped from the open records, as
+/// the exchange will cancel them. Groups come from `oco_group_id` or, failing
+/// that, a shared bracket parent. The earliest fill wins; legs without a fill
+/// time only win when no leg has one.
+fn apply_oco_groups<'a>(
+    open_records: &mut Vec<ProcessedOpenRecord<'a>>,
+    executed_records: &mut [ProcessedExecutedRecord<'a>],
+    options: &ProcessOptions,
+) {
+    let mut groups: FxHashMap<&str, Vec<usize>> = FxHashMap::default();
+    for (index, record) in executed_records.iter().enumerate() {
+        if let Some(group) = record.oco_group.or(record.parent_order_id) {
+            groups.entry(group).or_default().push(index);
+        }
+    }
+
+    let mut cancelled: FxHashSet<&str> = FxHashSet::default();
+    for members in groups.values().filter(|members| members.len() > 1) {
+        let winner = members
+            .iter()
+            .map(|&index| &executed_records[index])
+            .filter(|record| record.filled_size.is_some() && !options.is_working(&record.status))
+            .min_by_key(|record| (record.ts_filled.is_none(), record.ts_filled))
+            .map(|record| record.order_id);
+        let Some(winner) = winner else {
+            continue;
+        };
+        for &index in members {
+            let record = &mut executed_records[index];
+            if record.order_id != winner && options.is_working(&record.status) {
+                record.status = Cow::Borrowed("CANCELLED");
+                record.oco_cancelled_by = Some(winner);
+                cancelled.insert(record.order_id);
+            }
+        }
+    }
+    if !cancelled.is_empty() {
+        open_records.retain(|record| !cancelled.contains(record.order_id));
+    }
+}
+
+/// Orders open records like a book (bids by descending price, then asks by
+/// ascending price) and executed records by submission time, breaking ties
+/// on order_id so the output is independent of payload order.
+fn sort_records(open_records: &mut [ProcessedOpenRecord<'_>], executed_records: &mut [ProcessedExecutedRecord<'_>]) {
+    open_records.sort_by(|a, b| {
+        let side_rank = |side: Side| matches!(side, Side::Sell);
+        side_rank(a.side)
+            .cmp(&side_rank(b.side))
+            .then_with(|| match a.side {
+                Side::Buy => b.limit_price.cmp(&a.limit_price),
+                Side::Sell => a.limit_price.cmp(&b.limit_price),
+            })
+            .then_with(|| a.order_id.cmp(b.order_id))
+    });
+    executed_records.sort_by(|a, b| {
+        a.ts_submitted
+            .cmp(&b.ts_submitted)
+            .then_with(|| a.order_id.cmp(b.order_id))
+    });
+}
+
+/// Position state for one product rebuilt from fills alone.
+struct FillPosition {
+    fill_count: usize,
+    skipped_fills: usize,
+    realized_profit: Decimal,
+    commission_total: Decimal,
+    position: OpenPosition,
+}
+
+fn fill_size_and_price(fill: &RawFill) -> Option<(Decimal, Decimal)> {
+    let size = decimal_from_value(fill.size.as_ref())
+        .or_else(|| decimal_from_value(fill.base_size.as_ref()))?;
+    let price = decimal_from_value(fill.price.as_ref())
+        .or_else(|| decimal_from_value(fill.unit_price.as_ref()))
+        .or_else(|| decimal_from_value(fill.average_price.as_ref()))?;
+    (size > Decimal::ZERO && price > Decimal::ZERO).then_some((size, price))
+}
+
+/// Replays fills per product in trade_time order. Fills without a usable
+/// side, size or price are counted as skipped rather than guessed at.
+fn compute_fill_positions(fills: &[RawFill], default_product_id: &str) -> PyResult<BTreeMap<String, FillPosition>> {
+    struct ParsedFill {
+        trade_time: Option<DateTime<Utc>>,
+        side: Side,
+        size: Decimal,
+        price: Decimal,
+        commission: Decimal,
+    }
+
+    let mut by_product: BTreeMap<String, (Vec<ParsedFill>, usize)> = BTreeMap::new();
+    for fill in fills {
+        let product_id = fill
+            .product_id
+            .as_deref()
+            .filter(|s| !s.is_empty())
+            .unwrap_or(default_product_id);
+        let (parsed, skipped) = by_product.entry(product_id.to_string()).or_default();
+        let side = fill.side.as_deref().and_then(|text| Side::try_from(text).ok());
+        let (Some(side), Some((size, price))) = (side, fill_size_and_price(fill)) else {
+            *skipped += 1;
+            continue;
+        };
+        parsed.push(ParsedFill {
+            trade_time: fill.trade_time.as_deref().and_then(parse_datetime_text),
+            side,
+            size,
+            price,
+            commission: decimal_from_value(fill.commission.as_ref()).unwrap_or(Decimal::ZERO),
+        });
+    }
+
+    let mut positions = BTreeMap::new();
+    for (product_id, (mut parsed, skipped_fills)) in by_product {
+        parsed.sort_by_key(|fill| fill.trade_time);
+        let mut book: Book<Decimal> = Book::default();
+        let mut realized_profit = Decimal::ZERO;
+        let mut commission_total = Decimal::ZERO;
+        for fill in &parsed {
+            realized_profit += book.execute(fill.side, fill.price, fill.size, Decimal::ZERO)?;
+            commission_total += fill.commission;
+        }
+        positions.insert(
+            product_id,
+            FillPosition {
+                fill_count: parsed.len(),
+                skipped_fills,
+                realized_profit,
+                commission_total,
+                position: book.open_position(EntryScales::default())?,
+            },
+        );
+    }
+    Ok(positions)
+}
+
+struct TimelineEvent<'a> {
+    order_id: &'a str,
+    event: &'static str,
+    timestamp: Option<DateTime<Utc>>,
+    price: Option<Decimal>,
+    size: Option<Decimal>,
+    trade_id: Option<&'a str>,
+}
+
+fn terminal_event(status: &str) -> Option<&'static str> {
+    match status {
+        "FILLED" => Some("filled"),
+        "CANCELLED" => Some("cancelled"),
+        "EXPIRED" => Some("expired"),
+        "FAILED" => Some("failed"),
+        _ => None,
+    }
+}
+
+/// Assembles each order's submission, amendments, fills and terminal state
+/// into one time-ordered event list. Events without a timestamp sort last.
+fn build_timelines<'a>(
+    orders: &'a [RawOrder],
+    fills: &'a [RawFill],
+    options: &ProcessOptions,
+) -> Vec<TimelineEvent<'a>> {
+    let fills_by_order = collect_fills(fills);
+    let mut events: Vec<TimelineEvent<'a>> = Vec::new();
+
+    for order in orders {
+        let Some(order_id) = order.order_id.as_deref().filter(|s| !s.is_empty()) else {
+            continue;
+        };
+        let status = normalise_status(order.status.as_deref().or(order.legacy_status.as_deref()));
+        let fills_vec = fills_by_order.get(order_id);
+        let is_working = options.is_working(&status);
+        let completed_time =
+            resolve_completed_time(order, fills_vec, is_working, &options.completed_sources);
+        let (submitted_time, submitted_inferred, _) =
+            resolve_submitted_time(order, fills_vec, completed_time, &options.submitted_sources);
+
+        let first = events.len();
+        events.push(TimelineEvent {
+            order_id,
+            event: "submitted",
+            timestamp: (!submitted_inferred).then_some(submitted_time),
+            price: None,
+            size: None,
+            trade_id: None,
+        });
+        for edit in order.edit_history.iter().flatten() {
+            events.push(TimelineEvent {
+                order_id,
+                event: "amended",
+                timestamp: edit.replace_accept_timestamp.as_deref().and_then(parse_datetime_text),
+                price: decimal_from_value(edit.price.as_ref()),
+                size: decimal_from_value(edit.size.as_ref()),
+                trade_id: None,
+            });
+        }
+        for fill in fills_vec.into_iter().flatten() {
+            events.push(TimelineEvent {
+                order_id,
+                event: "fill",
+                timestamp: fill.trade_time,
+                price: Some(fill.price),
+                size: Some(fill.size),
+                trade_id: fill.trade_id,
+            });
+        }
+        if let Some(event) = terminal_event(&status) {
+            events.push(TimelineEvent {
+                order_id,
+                event,
+                timestamp: completed_time.map(|(dt, _)| dt),
+                price: None,
+                size: None,
+                trade_id: None,
+            });
+        }
+
+        // Submission stays first; everything after it follows the clock.
+        events[first + 1..].sort_by_key(|event| (event.timestamp.is_none(), event.timestamp));
+    }
+
+    events
+}
+
+/// Extra top-level field names (alias, canonical) applied to order and fill
+/// payloads before deserializing; see `register_field_aliases`.
+static ORDER_ALIASES: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+static FILL_ALIASES: RwLock<Vec<(String, String)>> = RwLock::new(Vec::new());
+
+/// Record with aliased fields copied onto their canonical names where the
+/// canonical key is missing or null. The caller's dict is copied, not
+/// modified, and only when an alias applies.
+fn apply_aliases<'py>(record: Bound<'py, PyAny>, aliases: &[(String, String)]) -> PyResult<Bound<'py, PyAny>> {
+    let Ok(dict) = record.downcast::<PyDict>() else {
+        return Ok(record);
+    };
+    let mut copy: Option<Bound<'py, PyDict>> = None;
+    for (alias, canonical) in aliases {
+        let target = copy.as_ref().unwrap_or(dict);
+        if target.get_item(canonical)?.is_some_and(|value| !value.is_none()) {
+            continue;
+        }
+        if let Some(value) = target.get_item(alias)? {
+            if copy.is_none() {
+                copy = Some(dict.copy()?);
+            }
+            if let Some(copy) = &copy {
+                copy.set_item(canonical, value)?;
+            }
+        }
+    }
+    Ok(copy.map_or(record, Bound::into_any))
+}
+
+/// Bare records of a payload. Accepts a list of records, a `{"orders": [...]}`
+/// envelope, or a list or iterator of pages that are each one of those;
+/// records may be wrapped as `{"order": {...}}`. Nesting goes no deeper.
+fn payload_records<'py>(
+    payload: &Bound<'py, PyAny>,
+    aliases: &RwLock<Vec<(String, String)>>,
+    label: &str,
+) -> PyResult<Bound<'py, PyList>> {
+    let item = label.strip_suffix('s').unwrap_or(label);
+    let aliases = aliases.read().map_err(|_| PyValueError::new_err("field alias registry poisoned"))?;
+    let records = PyList::empty_bound(payload.py());
+    let envelope = |value: &Bound<'py, PyAny>| -> PyResult<Option<Bound<'py, PyList>>> {
+        let Ok(dict) = value.downcast::<PyDict>() else {
+            return Ok(None);
+        };
+        Ok(dict.get_item(label)?.and_then(|page| page.downcast_into::<PyList>().ok()))
+    };
+    let push_record = |record: Bound<'py, PyAny>| -> PyResult<()> {
+        if record.is_instance_of::<PyList>() {
+            return Err(PyValueError::new_err(format!(
+                "Failed to parse {label} payload: pages must hold records, not further lists"
+            )));
+        }
+        let record = match record.downcast::<PyDict>() {
+            Ok(dict) if dict.len() == 1 => match dict.get_item(item)? {
+                Some(inner) if inner.is_instance_of::<PyDict>() => inner,
+                _ => record,
+            },
+            _ => record,
+        };
+        records.append(apply_aliases(record, &aliases)?)
+    };
+    let push_page = |page: Bound<'py, PyAny>| -> PyResult<()> {
+        let page = match envelope(&page)? {
+            Some(list) => list.into_any(),
+            None if page.is_instance_of::<PyList>() => page,
+            None => return push_record(page),
+        };
+        for record in page.iter()? {
+            push_record(record?)?;
+        }
+        Ok(())
+    };
+
+    if let Some(page) = envelope(payload)? {
+        for record in page.iter() {
+            push_record(record)?;
+        }
+    } else if payload.is_instance_of::<PyDict>() {
+        push_record(payload.clone())?;
+    } else {
+        for page in payload.iter()? {
+            push_page(page?)?;
+        }
+    }
+    check_input_size(label, records.len())?;
+    Ok(records)
+}
+
+/// Deserializes bare records (see `payload_records`) in one pass.
+fn deserialize_records<T: DeserializeOwned>(
+    json: &Bound<'_, PyModule>,
+    records: &Bound<'_, PyList>,
+    label: &str,
+) -> PyResult<T> {
+    let text: String = json.call_method1("dumps", (records,))?.extract()?;
+    serde_json::from_str(&text).map_err(|err| PyValueError::new_err(format!("Failed to parse {label} payload: {err}")))
+}
+
+/// Payload as a JSON array of records, for checks that look at raw fields.
+fn payload_value(
+    json: &Bound<'_, PyModule>,
+    payload: &Bound<'_, PyAny>,
+    aliases: &RwLock<Vec<(String, String)>>,
+    label: &str,
+) -> PyResult<Value> {
+    deserialize_records(json, &payload_records(payload, aliases, label)?, label)
+}
+
+fn parse_records<T: DeserializeOwned>(
+    json: &Bound<'_, PyModule>,
+    payload: &Bound<'_, PyAny>,
+    aliases: &RwLock<Vec<(String, String)>>,
+    label: &str,
+) -> PyResult<Vec<T>> {
+    deserialize_records(json, &payload_records(payload, aliases, label)?, label)
+}
+
+fn load_payloads(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+) -> PyResult<(Vec<RawOrder>, Vec<RawFill>)> {
+    let json = py.import_bound("json")?;
+    let orders = parse_records(&json, orders, &ORDER_ALIASES, "orders")?;
+    let fills = parse_records(&json, fills, &FILL_ALIASES, "fills")?;
+    Ok((orders, fills))
+}
+
+/// One problem found by `validate_payload`.
+struct ValidationIssue {
+    index: usize,
+    record_id: Option<String>,
+    kind: &'static str,
+    field: String,
+    detail: String,
+}
+
+/// Collects issues for one record of a payload.
+struct RecordCheck<'a> {
+    index: usize,
+    record: &'a serde_json::Map<String, Value>,
+    issues: &'a mut Vec<ValidationIssue>,
+    record_id: Option<String>,
+}
+
+impl RecordCheck<'_> {
+    fn push(&mut self, kind: &'static str, field: &str, detail: String) {
+        self.issues.push(ValidationIssue {
+            index: self.index,
+            record_id: self.record_id.clone(),
+            kind,
+            field: field.to_string(),
+            detail,
+        });
+    }
+
+    /// Null sentinels read as absent, as they do in the parser.
+    fn present<'v>(&self, value: Option<&'v Value>) -> Option<&'v Value> {
+        value.filter(|value| !value.is_null() && !value.as_str().is_some_and(is_null_sentinel))
+    }
+
+    fn require_any(&mut self, fields: &[&str]) {
+        if !fields.iter().any(|field| self.present(self.record.get(*field)).is_some()) {
+            self.push("missing_field", &fields.join("|"), "required field is missing or null".to_string());
+        }
+    }
+
+    fn check_string(&mut self, field: &str) {
+        if let Some(value) = self.present(self.record.get(field)) {
+            if !value.is_string() {
+                self.push("type_mismatch", field, format!("expected string, got {}", value));
+            }
+        }
+    }
+
+    fn check_decimal(&mut self, field: &str, value: Option<&Value>) {
+        if let Some(value) = self.present(value) {
+            if decimal_from_value(Some(value)).is_none() {
+                self.push("type_mismatch", field, format!("expected decimal, got {}", value));
+            }
+        }
+    }
+
+    fn check_datetime(&mut self, field: &str, value: Option<&Value>) {
+        if let Some(value) = self.present(value) {
+            if parse_datetime_value(Some(value)).is_none() {
+                self.push("type_mismatch", field, format!("expected timestamp, got {}", value));
+            }
+        }
+    }
+
+    fn check_side(&mut self) {
+        if let Some(side) = self.present(self.record.get("side")).and_then(Value::as_str) {
+            if Side::try_from(side).is_err() {
+                self.push("type_mismatch", "side", format!("expected BUY or SELL, got {}", side));
+            }
+        }
+    }
+}
+
+const ORDER_TIME_FIELDS: [&str; 6] = [
+    "submitted_time",
+    "created_time",
+    "order_placed_time",
+    "last_fill_time",
+    "completed_time",
+    "expire_time",
+];
+
+fn validate_orders(orders: &Value, issues: &mut Vec<ValidationIssue>, unknown_keys: &mut BTreeSet<String>) -> usize {
+    let Some(orders) = orders.as_array() else {
+        return 0;
+    };
+    for (index, order) in orders.iter().enumerate() {
+        let Some(record) = order.as_object() else {
+            issues.push(ValidationIssue {
+                index,
+                record_id: None,
+                kind: "type_mismatch",
+                field: String::new(),
+                detail: "order is not an object".to_string(),
+            });
+            continue;
+        };
+        let mut check = RecordCheck {
+            index,
+            record,
+            issues: &mut *issues,
+            record_id: record.get("order_id").and_then(option_to_string_value),
+        };
+        // The parser skips orders without these; a missing side reads as BUY.
+        check.require_any(&["order_id"]);
+        check.require_any(&["order_configuration"]);
+        for field in ["order_id", "status", "order_status", "side", "product_id", "client_order_id"] {
+            check.check_string(field);
+        }
+        check.check_side();
+        for field in ORDER_TIME_FIELDS {
+            check.check_datetime(field, record.get(field));
+        }
+        check.check_decimal("average_filled_price", record.get("average_filled_price"));
+        check.check_decimal("leverage", record.get("leverage"));
+
+        let Some(config) = check.present(record.get("order_configuration")) else {
+            continue;
+        };
+        let Some(config) = config.as_object() else {
+            check.push("type_mismatch", "order_configuration", "expected object".to_string());
+            continue;
+        };
+        for key in config.keys() {
+            if !ORDER_CONFIG_KEYS.iter().any(|(known, _, _)| known == key) {
+                unknown_keys.insert(key.clone());
+                check.push("unknown_config_key", key, "order_configuration key is not recognised".to_string());
+            }
+        }
+        if let Some(order_config) = extract_order_config(record.get("order_configuration")) {
+            for field in ["limit_price", "base_size", "quote_size", "stop_price", "stop_trigger_price"] {
+                check.check_decimal(field, order_config.entry.get(field));
+            }
+            check.check_datetime("end_time", order_config.entry.get("end_time"));
+        }
+    }
+    orders.len()
+}
+
+fn validate_fills(fills: &Value, issues: &mut Vec<ValidationIssue>) -> usize {
+    let Some(fills) = fills.as_array() else {
+        return 0;
+    };
+    for (index, fill) in fills.iter().enumerate() {
+        let Some(record) = fill.as_object() else {
+            issues.push(ValidationIssue {
+                index,
+                record_id: None,
+                kind: "type_mismatch",
+                field: String::new(),
+                detail: "fill is not an object".to_string(),
+            });
+            continue;
+        };
+        let mut check = RecordCheck {
+            index,
+            record,
+            issues: &mut *issues,
+            record_id: record.get("trade_id").and_then(option_to_string_value),
+        };
+        // Fills without an order id or trade_time are still usable: they
+        // fall back to an explicit side and to their order's times.
+        check.require_any(&["size", "base_size"]);
+        check.require_any(&["price", "unit_price", "average_price"]);
+        for field in ["order_id", "trade_id", "side", "product_id", "liquidity_indicator"] {
+            check.check_string(field);
+        }
+        check.check_side();
+        for field in ["size", "base_size", "price", "unit_price", "average_price", "commission"] {
+            check.check_decimal(field, record.get(field));
+        }
+        check.check_datetime("trade_time", record.get("trade_time"));
+    }
+    fills.len()
+}
+
+fn option_to_string_value(value: &Value) -> Option<String> {
+    value_to_string(value).filter(|text| !text.is_empty())
+}
+
+fn issues_to_py<'py>(py: Python<'py>, issues: &[ValidationIssue]) -> PyResult<Bound<'py, PyList>> {
+    let rows = PyList::empty_bound(py);
+    for issue in issues {
+        let dict = PyDict::new_bound(py);
+        dict.set_item("index", issue.index)?;
+        dict.set_item("record_id", issue.record_id.as_deref())?;
+        dict.set_item("kind", issue.kind)?;
+        dict.set_item("field", &issue.field)?;
+        dict.set_item("detail", &issue.detail)?;
+        rows.append(dict)?;
+    }
+    Ok(rows)
+}
+
+/// Checks order and fill payloads without processing them and reports
+/// missing required fields, unrecognised order_configuration keys and values
+/// of the wrong type, per record. Fields are judged as the parser reads
+/// them: null sentinels are absent and only what it cannot do without is
+/// required.
+#[pyfunction]
+fn validate_payload(py: Python<'_>, orders: &Bound<'_, PyAny>, fills: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let json = py.import_bound("json")?;
+    let orders = payload_value(&json, orders, &ORDER_ALIASES, "orders")?;
+    let fills = payload_value(&json, fills, &FILL_ALIASES, "fills")?;
+
+    let mut order_issues = Vec::new();
+    let mut fill_issues = Vec::new();
+    let mut unknown_keys = BTreeSet::new();
+    let order_count = validate_orders(&orders, &mut order_issues, &mut unknown_keys);
+    let fill_count = validate_fills(&fills, &mut fill_issues);
+
+    let orders_py = PyDict::new_bound(py);
+    orders_py.set_item("count", order_count)?;
+    orders_py.set_item("issues", issues_to_py(py, &order_issues)?)?;
+    let fills_py = PyDict::new_bound(py);
+    fills_py.set_item("count", fill_count)?;
+    fills_py.set_item("issues", issues_to_py(py, &fill_issues)?)?;
+
+    let result = PyDict::new_bound(py);
+    result.set_item("valid", order_issues.is_empty() && fill_issues.is_empty())?;
+    result.set_item("orders", orders_py)?;
+    result.set_item("fills", fills_py)?;
+    result.set_item("unknown_config_keys", unknown_keys.into_iter().collect::<Vec<_>>())?;
+    Ok(result.into())
+}
+
+/// Ids of finished orders and fills already reported, kept across calls so
+/// overlapping re-syncs are not counted twice. With a `path` the set is loaded
+/// from that JSON file on creation and written back once `flush_every` new
+/// ids have built up, on `save()` and when the store is dropped; otherwise it
+/// lives in memory and round-trips via `dumps`/`loads`.
+#[pyclass(module = "_pnl_rs")]
+#[derive(Default, Serialize, Deserialize)]
+struct SeenIdStore {
+    orders: BTreeSet<String>,
+    fills: BTreeSet<String>,
+    #[serde(skip)]
+    path: Option<String>,
+    #[serde(skip)]
+    flush_every: usize,
+    /// Ids added since the file was last written.
+    #[serde(skip)]
+    pending: usize,
+}
+
+impl SeenIdStore {
+    /// Writes the store to `path` via a temporary file and a rename, so a
+    /// crash mid-write leaves the previous file intact.
+    fn write_file(&self, path: &str) -> PyResult<()> {
+        let text = serde_json::to_string(self).map_err(|err| PyValueError::new_err(err.to_string()))?;
+        let tmp = format!("{}.tmp", path);
+        std::fs::write(&tmp, text)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .map_err(|err| PyOSError::new_err(format!("failed to write {}: {}", path, err)))
+    }
+
+    fn flush(&mut self, py: Python<'_>) -> PyResult<()> {
+        if let Some(path) = &self.path {
+            let store = &*self;
+            py.allow_threads(|| store.write_file(path))?;
+        }
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// Counts `added` new ids and writes the file once enough have built up.
+    fn record_added(&mut self, py: Python<'_>, added: usize) -> PyResult<()> {
+        self.pending += added;
+        if self.pending > 0 && self.pending >= self.flush_every {
+            self.flush(py)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SeenIdStore {
+    fn drop(&mut self) {
+        if let (Some(path), true) = (&self.path, self.pending > 0) {
+            // Nowhere to raise to; a failed final write keeps the old file.
+            let _ = self.write_file(path);
+        }
+    }
+}
+
+#[pymethods]
+impl SeenIdStore {
+    #[new]
+    #[pyo3(signature = (path = None, *, flush_every = 1_000))]
+    fn new(path: Option<String>, flush_every: usize) -> PyResult<Self> {
+        let mut store = match path.as_deref().map(std::fs::read_to_string) {
+            Some(Ok(text)) => SeenIdStore::loads(&text)?,
+            Some(Err(err)) if err.kind() == std::io::ErrorKind::NotFound => SeenIdStore::default(),
+            Some(Err(err)) => return Err(PyOSError::new_err(err.to_string())),
+            None => SeenIdStore::default(),
+        };
+        store.path = path;
+        store.flush_every = flush_every;
+        Ok(store)
+    }
+
+    #[staticmethod]
+    fn loads(text: &str) -> PyResult<Self> {
+        serde_json::from_str(text).map_err(|err| PyValueError::new_err(format!("invalid seen-id store: {}", err)))
+    }
+
+    fn dumps(&self) -> PyResult<String> {
+        serde_json::to_string(self).map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    fn save(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.flush(py)
+    }
+
+    fn clear(&mut self, py: Python<'_>) -> PyResult<()> {
+        self.orders.clear();
+        self.fills.clear();
+        self.flush(py)
+    }
+
+    fn has_order(&self, order_id: &str) -> bool {
+        self.orders.contains(order_id)
+    }
+
+    fn has_fill(&self, trade_id: &str) -> bool {
+        self.fills.contains(trade_id)
+    }
+
+    fn __len__(&self) -> usize {
+        self.orders.len() + self.fills.len()
+    }
+}
+
+/// Registers extra field names for `record_type` "order" or "fill", mapping
+/// each alias to the canonical key (e.g. `avg_price` to
+/// `average_filled_price`). Applies to every later call in the process.
+#[pyfunction]
+#[pyo3(signature = (record_type, aliases, *, replace = false))]
+fn register_field_aliases(record_type: &str, aliases: BTreeMap<String, String>, replace: bool) -> PyResult<()> {
+    let registry = match record_type {
+        "order" => &ORDER_ALIASES,
+        "fill" => &FILL_ALIASES,
+        other => return Err(PyValueError::new_err(format!("unknown record type: {}", other))),
+    };
+    let mut registry = registry
+        .write()
+        .map_err(|_| PyValueError::new_err("field alias registry poisoned"))?;
+    if replace {
+        registry.clear();
+    }
+    for (alias, canonical) in aliases {
+        registry.retain(|(existing, _)| *existing != alias);
+        registry.push((alias, canonical));
+    }
+    Ok(())
+}
+
+/// Record-count ceilings enforced on every later call; `None` is unlimited.
+#[derive(Clone, Copy)]
+struct InputLimits {
+    max_trades: Option<usize>,
+    max_orders: Option<usize>,
+    max_fills: Option<usize>,
+}
+
+static INPUT_LIMITS: RwLock<InputLimits> = RwLock::new(InputLimits {
+    max_trades: None,
+    max_orders: None,
+    max_fills: None,
+});
+
+fn input_limits() -> PyResult<InputLimits> {
+    INPUT_LIMITS
+        .read()
+        .map(|limits| *limits)
+        .map_err(|_| PyValueError::new_err("input limit registry poisoned"))
+}
+
+/// Limit for `label` ("trades", "orders" or "fills").
+fn input_limit(limits: InputLimits, label: &str) -> Option<usize> {
+    match label {
+        "trades" => limits.max_trades,
+        "orders" => limits.max_orders,
+        "fills" => limits.max_fills,
+        _ => None,
+    }
+}
+
+/// Fails when a payload holds more `label` records than the configured limit.
+fn check_input_size(label: &str, count: usize) -> PyResult<()> {
+    match input_limit(input_limits()?, label) {
+        Some(limit) if count > limit => Err(PyValueError::new_err(format!(
+            "{} payload has {} records, above the limit of {}; narrow the query or raise it with set_input_limits",
+            label, count, limit
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Caps how many trades, orders and fills a single call accepts, so an
+/// accidental "everything since genesis" fetch fails fast instead of
+/// exhausting memory. Applies to every later call in the process.
+#[pyfunction]
+#[pyo3(signature = (*, max_trades = None, max_orders = None, max_fills = None))]
+fn set_input_limits(max_trades: Option<usize>, max_orders: Option<usize>, max_fills: Option<usize>) -> PyResult<()> {
+    let mut limits = INPUT_LIMITS
+        .write()
+        .map_err(|_| PyValueError::new_err("input limit registry poisoned"))?;
+    *limits = InputLimits {
+        max_trades,
+        max_orders,
+        max_fills,
+    };
+    Ok(())
+}
+
+/// Counts records in an order or fill payload the way `payload_records`
+/// would unwrap them, without serializing it. Only lists, tuples and dicts
+/// can be counted; an iterator or generator would be consumed by counting it.
+fn count_payload_records(payload: &Bound<'_, PyAny>, label: &str) -> PyResult<usize> {
+    let envelope = |value: &Bound<'_, PyAny>| -> PyResult<Option<usize>> {
+        let Ok(dict) = value.downcast::<PyDict>() else {
+            return Ok(None);
+        };
+        Ok(dict.get_item(label)?.and_then(|page| page.downcast_into::<PyList>().ok()).map(|page| page.len()))
+    };
+    if let Some(count) = envelope(payload)? {
+        return Ok(count);
+    }
+    if payload.is_instance_of::<PyDict>() {
+        return Ok(1);
+    }
+    if payload.is_instance_of::<PyList>() || payload.is_instance_of::<PyTuple>() {
+        let mut count = 0;
+        for page in payload.iter()? {
+            let page = page?;
+            count += match envelope(&page)? {
+                Some(records) => records,
+                None => page.downcast::<PyList>().map_or(1, |list| list.len()),
+            };
+        }
+        return Ok(count);
+    }
+    Err(PyTypeError::new_err(format!(
+        "cannot count {} in a {}; pass a list, tuple or dict",
+        label,
+        payload.get_type().name()?
+    )))
+}
+
+/// Per-record (peak bytes, seconds) of a release build, measured on 100k
+/// synthetic records: `memory_usage` peak (with `memory-debug`) plus Python allocations seen by
+/// `tracemalloc`, and wall time. Orders are limit orders processed without
+/// fills; fills were spread 100 per order over 1k orders; trades went
+/// through `summarise_trades` with one all-time interval. Re-measure when
+/// the record structs or output change shape.
+const ORDER_COST: (usize, f64) = (7_700, 22e-6);
+const FILL_COST: (usize, f64) = (900, 4e-6);
+const TRADE_COST: (usize, f64) = (400, 5e-6);
+
+/// Dry run for a payload: counts records without processing them and
+/// estimates peak memory and run time from per-record costs, and names any
+/// configured limit the payload would exceed. Estimates are ballpark figures
+/// for sizing, not guarantees. Unlike the processing entry points, payloads
+/// must be lists, tuples or dicts: an iterator or generator raises
+/// `TypeError` rather than being consumed by the dry run (the Python wrapper
+/// materializes them first).
+#[pyfunction]
+#[pyo3(signature = (*, orders = None, fills = None, trades = None))]
+fn estimate_payload(
+    py: Python<'_>,
+    orders: Option<&Bound<'_, PyAny>>,
+    fills: Option<&Bound<'_, PyAny>>,
+    trades: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let limits = input_limits()?;
+    let counts = [
+        ("orders", orders.map(|orders| count_payload_records(orders, "orders")).transpose()?, ORDER_COST),
+        ("fills", fills.map(|fills| count_payload_records(fills, "fills")).transpose()?, FILL_COST),
+        ("trades", trades.map(|trades| trades.len()).transpose()?, TRADE_COST),
+    ];
+
+    let result = PyDict::new_bound(py);
+    let mut bytes = 0;
+    let mut seconds = 0.0;
+    let exceeded = PyList::empty_bound(py);
+    for (label, count, (record_bytes, record_seconds)) in counts {
+        let count = count.unwrap_or(0);
+        result.set_item(label, count)?;
+        bytes += count * record_bytes;
+        seconds += count as f64 * record_seconds;
+        if input_limit(limits, label).is_some_and(|limit| count > limit) {
+            exceeded.append(format!("max_{}", label))?;
+        }
+    }
+    result.set_item("estimated_bytes", bytes)?;
+    result.set_item("estimated_seconds", seconds)?;
+    result.set_item("exceeded_limits", exceeded)?;
+    Ok(result.into())
+}
+
+/// How `records_to_py` shapes its output.
+#[derive(Default)]
+struct RecordFormat {
+    /// Optional whitelist of keys to build.
+    fields: Option<FxHashSet<String>>,
+    timestamps: TimestampPrecision,
+    raw_timestamps: bool,
+    /// Emit `{key: [values...]}` columns instead of a list of row dicts, which
+    /// pandas turns into a DataFrame much faster.
+    columnar: bool,
+    /// Overrides the `quote_currency` derived from each record's product.
+    quote_currency: Option<String>,
+    /// Inverse contracts settle in the base currency, so their records are
+    /// labelled with it, as `pnl_currency` does for summaries.
+    inverse_products: FxHashSet<String>,
+}
+
+impl RecordFormat {
+    /// Whether `key` is built: it passes the whitelist, and `_raw` keys also
+    /// need `raw_timestamps`.
+    fn selects(&self, key: &str) -> bool {
+        (self.raw_timestamps || !key.ends_with("_raw"))
+            && self.fields.as_ref().is_none_or(|fields| fields.contains(key))
+    }
+
+    /// Currency a record of `product_id` is labelled with.
+    fn currency<'a>(&'a self, product_id: &'a str) -> Option<Cow<'a, str>> {
+        if let Some(currency) = self.quote_currency.as_deref() {
+            return Some(Cow::Borrowed(currency));
+        }
+        let (base, quote) = product_currencies(product_id)?;
+        Some(if self.inverse_products.contains(product_id) { Cow::Borrowed(base) } else { quote })
+    }
+}
+
+/// Keys of the open and executed record tables in the order rows put them,
+/// so columnar output has its columns even when there are no rows.
+const OPEN_RECORD_KEYS: [&str; 19] = [
+    "order_id",
+    "side",
+    "limit_price",
+    "base_size",
+    "status",
+    "client_order_id",
+    "end_time",
+    "end_time_raw",
+    "product_id",
+    "quote_currency",
+    "stop_price",
+    "filled_size",
+    "remaining_size",
+    "percent_filled",
+    "avg_fill_price",
+    "expired_pending",
+    "leverage",
+    "margin_type",
+    "product_type",
+];
+
+const EXECUTED_RECORD_KEYS: [&str; 30] = [
+    "order_id",
+    "ts_submitted",
+    "ts_submitted_inferred",
+    "ts_filled",
+    "ts_submitted_raw",
+    "ts_filled_raw",
+    "side",
+    "limit_price",
+    "base_size",
+    "status",
+    "filled_size",
+    "client_order_id",
+    "end_time",
+    "end_time_raw",
+    "product_id",
+    "quote_currency",
+    "stop_price",
+    "post_only",
+    "avg_fill_price",
+    "notional",
+    "margin_used",
+    "leverage",
+    "margin_type",
+    "product_type",
+    "oco_cancelled_by",
+    "limit_slippage",
+    "limit_slippage_bps",
+    "arrival_price",
+    "arrival_slippage",
+    "arrival_slippage_bps",
+];
+
+/// Records as a list of row dicts or, in columnar mode, a dict of equal-length
+/// lists. Only keys in the format's whitelist are built and values are
+/// computed lazily, so skipped fields cost nothing. Every row must put the
+/// table's `keys` in order, which lets columns be addressed by position.
+struct RecordTable<'py, 'f> {
+    py: Python<'py>,
+    format: &'f RecordFormat,
+    rows: Bound<'py, PyList>,
+    row: Bound<'py, PyDict>,
+    columns: Vec<Bound<'py, PyList>>,
+    column_names: Vec<&'static str>,
+    column: usize,
+}
+
+impl<'py, 'f> RecordTable<'py, 'f> {
+    fn new(py: Python<'py>, format: &'f RecordFormat, keys: &[&'static str]) -> Self {
+        let column_names: Vec<&'static str> = if format.columnar {
+            keys.iter().copied().filter(|key| format.selects(key)).collect()
+        } else {
+            Vec::new()
+        };
+        RecordTable {
+            py,
+            format,
+            rows: PyList::empty_bound(py),
+            row: PyDict::new_bound(py),
+            columns: column_names.iter().map(|_| PyList::empty_bound(py)).collect(),
+            column_names,
+            column: 0,
+        }
+    }
+
+    fn put<V: ToPyObject>(&mut self, key: &'static str, value: impl FnOnce() -> V) -> PyResult<()> {
+        if !self.format.selects(key) {
+            return Ok(());
+        }
+        if !self.format.columnar {
+            return self.row.set_item(key, value());
+        }
+        debug_assert_eq!(self.column_names[self.column], key, "record keys out of order");
+        self.columns[self.column].append(value().to_object(self.py))?;
+        self.column += 1;
+        Ok(())
+    }
+
+    fn end_row(&mut self) -> PyResult<()> {
+        if self.format.columnar {
+            self.column = 0;
+            return Ok(());
+        }
+        let row = std::mem::replace(&mut self.row, PyDict::new_bound(self.py));
+        self.rows.append(row)
+    }
+
+    fn into_py(self) -> PyResult<PyObject> {
+        if !self.format.columnar {
+            return Ok(self.rows.into());
+        }
+        let columns = PyDict::new_bound(self.py);
+        for (name, values) in self.column_names.into_iter().zip(self.columns) {
+            columns.set_item(name, values)?;
+        }
+        Ok(columns.into())
+    }
+}
+
+/// Builds the `{"open_records": ..., "executed_records": ...}` dict shared
+/// by `process_orders_and_fills` and `RecordStream`.
+fn records_to_py<'py>(
+    py: Python<'py>,
+    open_records: Vec<ProcessedOpenRecord<'_>>,
+    executed_records: Vec<ProcessedExecutedRecord<'_>>,
+    format: &RecordFormat,
+) -> PyResult<Bound<'py, PyDict>> {
+    let timestamps = format.timestamps;
+    let mut dict = RecordTable::new(py, format, &OPEN_RECORD_KEYS);
+    for record in open_records {
+        dict.put("order_id", || record.order_id)?;
+        dict.put("side", || record.side.as_str())?;
+        dict.put("limit_price", || record.limit_price.to_string())?;
+        dict.put("base_size", || record.base_size.to_string())?;
+        dict.put("status", || record.status.as_ref())?;
+        dict.put("client_order_id", || record.client_order_id)?;
+        dict.put("end_time", || record.end_time.map(|dt| timestamps.format(dt)))?;
+        dict.put("end_time_raw", || record.end_time_raw)?;
+        dict.put("product_id", || record.product_id)?;
+        dict.put("quote_currency", || format.currency(record.product_id))?;
+        dict.put("stop_price", || record.stop_price.map(|d| d.to_string()))?;
+        dict.put("filled_size", || record.filled_size.map(|d| d.to_string()))?;
+        dict.put("remaining_size", || record.remaining_size().to_string())?;
+        dict.put("percent_filled", || record.percent_filled().to_string())?;
+        dict.put("avg_fill_price", || record.avg_fill_price.map(|d| d.to_string()))?;
+        dict.put("expired_pending", || record.expired_pending)?;
+        dict.put("leverage", || record.leverage.map(|d| d.to_string()))?;
+        dict.put("margin_type", || record.margin_type)?;
+        dict.put("product_type", || record.product_type.as_str())?;
+        dict.end_row()?;
+    }
+    let open_records = dict.into_py()?;
+
+    let mut dict = RecordTable::new(py, format, &EXECUTED_RECORD_KEYS);
+    for record in executed_records {
+        dict.put("order_id", || record.order_id)?;
+        dict.put("ts_submitted", || timestamps.format(record.ts_submitted))?;
+        dict.put("ts_submitted_inferred", || record.ts_submitted_inferred)?;
+        dict.put("ts_filled", || record.ts_filled.map(|dt| timestamps.format(dt)))?;
+        dict.put("ts_submitted_raw", || record.ts_submitted_raw)?;
+        dict.put("ts_filled_raw", || record.ts_filled_raw)?;
+        dict.put("side", || record.side.as_str())?;
+        dict.put("limit_price", || record.limit_price.to_string())?;
+        dict.put("base_size", || record.base_size.to_string())?;
+        dict.put("status", || record.status.as_ref())?;
+        dict.put("filled_size", || record.filled_size.map(|d| d.to_string()))?;
+        dict.put("client_order_id", || record.client_order_id)?;
+        dict.put("end_time", || record.end_time.map(|dt| timestamps.format(dt)))?;
+        dict.put("end_time_raw", || record.end_time_raw)?;
+        dict.put("product_id", || record.product_id)?;
+        dict.put("quote_currency", || format.currency(record.product_id))?;
+        dict.put("stop_price", || record.stop_price.map(|d| d.to_string()))?;
+        dict.put("post_only", || record.post_only)?;
+        dict.put("avg_fill_price", || record.avg_fill_price.map(|d| d.to_string()))?;
+        dict.put("notional", || record.notional().normalize().to_string())?;
+        dict.put("margin_used", || record.margin_used().normalize().to_string())?;
+        dict.put("leverage", || record.leverage.map(|d| d.to_string()))?;
+        dict.put("margin_type", || record.margin_type)?;
+        dict.put("product_type", || record.product_type.as_str())?;
+        dict.put("oco_cancelled_by", || record.oco_cancelled_by)?;
+        dict.put("limit_slippage", || record.limit_slippage().map(|d| d.normalize().to_string()))?;
+        dict.put("limit_slippage_bps", || {
+            record
+                .limit_slippage()
+                .map(|slippage| slippage_bps(slippage, record.limit_price).to_string())
+        })?;
+        dict.put("arrival_price", || record.arrival_price.map(|d| d.to_string()))?;
+        dict.put("arrival_slippage", || record.arrival_slippage().map(|d| d.normalize().to_string()))?;
+        dict.put("arrival_slippage_bps", || {
+            record
+                .arrival_slippage()
+                .zip(record.arrival_price)
+                .map(|(slippage, arrival)| slippage_bps(slippage, arrival).to_string())
+        })?;
+        dict.end_row()?;
+    }
+    let executed_records = dict.into_py()?;
+
+    let result = PyDict::new_bound(py);
+    result.set_item("open_records", open_records)?;
+    result.set_item("executed_records", executed_records)?;
+    Ok(result)
+}
+
+fn process_options(
+    working_statuses: Option<Vec<String>>,
+    submitted_time_sources: Option<Vec<String>>,
+    completed_time_sources: Option<Vec<String>>,
+    now_timestamp_us: Option<i64>,
+    dust_size: Option<&str>,
+) -> PyResult<ProcessOptions> {
+    Ok(ProcessOptions {
+        working_statuses: working_statuses
+            .map(|statuses| statuses.iter().map(|s| s.to_ascii_uppercase()).collect())
+            .unwrap_or_else(|| vec!["OPEN".to_string()]),
+        submitted_sources: parse_time_sources(submitted_time_sources, &DEFAULT_SUBMITTED_SOURCES)?,
+        completed_sources: parse_time_sources(completed_time_sources, &DEFAULT_COMPLETED_SOURCES)?,
+        sort_records: false,
+        now: match now_timestamp_us {
+            Some(ts) => timestamp_us_to_datetime(ts)?,
+            None => Utc::now(),
+        },
+        dust_size: dust_size.map(|size| parse_decimal(size, "dust_size")).transpose()?,
+        watch: None,
+    })
+}
+
+#[pyfunction]
+#[pyo3(signature = (
+    orders,
+    fills,
+    product_id,
+    *,
+    working_statuses = None,
+    submitted_time_sources = None,
+    completed_time_sources = None,
+    sort_records = false,
+    now_timestamp_us = None,
+    fields = None,
+    dust_size = None,
+    duplicate_orders = "keep_all",
+    seen_ids = None,
+    arrival_prices = None,
+    arrival_price_series = None,
+    timestamp_precision = "millis",
+    include_raw_timestamps = false,
+    columnar = false,
+    quote_currency = None,
+    inverse_products = None,
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn process_orders_and_fills(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    product_id: &str,
+    working_statuses: Option<Vec<String>>,
+    submitted_time_sources: Option<Vec<String>>,
+    completed_time_sources: Option<Vec<String>>,
+    sort_records: bool,
+    now_timestamp_us: Option<i64>,
+    fields: Option<Vec<String>>,
+    dust_size: Option<&str>,
+    duplicate_orders: &str,
+    seen_ids: Option<&Bound<'_, SeenIdStore>>,
+    arrival_prices: Option<BTreeMap<String, String>>,
+    arrival_price_series: Option<Vec<PricePointSpec>>,
+    timestamp_precision: &str,
+    include_raw_timestamps: bool,
+    columnar: bool,
+    quote_currency: Option<String>,
+    inverse_products: Option<Vec<String>>,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let arrival = PriceSeries::parse(arrival_prices, arrival_price_series)?;
+    let format = RecordFormat {
+        fields: fields.map(|fields| fields.into_iter().collect()),
+        timestamps: TimestampPrecision::try_from(timestamp_precision)?,
+        raw_timestamps: include_raw_timestamps,
+        columnar,
+        quote_currency,
+        inverse_products: inverse_products.into_iter().flatten().collect(),
+    };
+    let options = ProcessOptions {
+        sort_records,
+        watch: Some(watch.clone()),
+        ..process_options(working_statuses, submitted_time_sources, completed_time_sources, now_timestamp_us, dust_size)?
+    };
+    let duplicate_policy = DuplicatePolicy::try_from(duplicate_orders)?;
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+    let (orders, merged_orders) = dedupe_orders(orders, duplicate_policy)?;
+
+    let (open_records, mut executed_records) = run_watched(py, &watch, "orders", orders.len(), || {
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)
+    })?;
+    if let Some(arrival) = &arrival {
+        arrival.assign(&mut executed_records);
+    }
+    let already_seen = seen_ids
+        .map(|store| suppress_seen_orders(py, store, &mut executed_records, &options))
+        .transpose()?;
+
+    let result = records_to_py(py, open_records, executed_records, &format)?;
+    result.set_item("merged_orders", merged_orders)?;
+    if let Some(already_seen) = already_seen {
+        result.set_item("already_seen", already_seen)?;
+    }
+    Ok(result.into())
+}
+
+/// Drops finished orders `store` has already reported and records the rest;
+/// working orders are reported every time. Returns how many were dropped.
+fn suppress_seen_orders(
+    py: Python<'_>,
+    store: &Bound<'_, SeenIdStore>,
+    records: &mut Vec<ProcessedExecutedRecord<'_>>,
+    options: &ProcessOptions,
+) -> PyResult<usize> {
+    let mut store = store.borrow_mut();
+    let mut added = 0;
+    let mut already_seen = 0;
+    records.retain(|record| {
+        if options.is_working(&record.status) {
+            return true;
+        }
+        let fresh = store.orders.insert(record.order_id.to_string());
+        added += usize::from(fresh);
+        already_seen += usize::from(!fresh);
+        fresh
+    });
+    store.record_added(py, added)?;
+    Ok(already_seen)
+}
+
+/// Iterator over `process_orders_and_fills` results, `chunk_size` orders at a
+/// time, so only one chunk of Python dicts is alive at once. Orders are
+/// deserialized and processed lazily in payload order; there is no global
+/// sort. Fills are grouped by order id up front but parsed with their chunk.
+#[pyclass(module = "_pnl_rs")]
+struct RecordStream {
+    /// Bare order records, unwrapped and aliased but not yet deserialized.
+    orders: Py<PyList>,
+    next_order: usize,
+    fills_by_order: FxHashMap<String, Vec<PyObject>>,
+    /// Repeated order_ids under `keep_latest`: the first copy's position
+    /// yields the winning copy and the later positions are skipped.
+    winners: FxHashMap<usize, usize>,
+    superseded: FxHashSet<usize>,
+    merged_orders: usize,
+    seen_ids: Option<Py<SeenIdStore>>,
+    product_id: String,
+    options: ProcessOptions,
+    format: RecordFormat,
+    chunk_size: usize,
+}
+
+#[pymethods]
+impl RecordStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        let all_orders = self.orders.bind(py);
+        let chunk = PyList::empty_bound(py);
+        while chunk.len() < self.chunk_size && self.next_order < all_orders.len() {
+            let index = self.next_order;
+            self.next_order += 1;
+            if !self.superseded.contains(&index) {
+                chunk.append(all_orders.get_item(*self.winners.get(&index).unwrap_or(&index))?)?;
+            }
+        }
+        if chunk.is_empty() {
+            return Ok(None);
+        }
+        let json = py.import_bound("json")?;
+        let orders: Vec<RawOrder> = deserialize_records(&json, &chunk, "orders")?;
+
+        let fill_records = PyList::empty_bound(py);
+        let mut gathered: FxHashSet<&str> = FxHashSet::default();
+        for order_id in orders.iter().filter_map(|order| order.order_id.as_deref()) {
+            if gathered.insert(order_id) {
+                for fill in self.fills_by_order.get(order_id).into_iter().flatten() {
+                    fill_records.append(fill)?;
+                }
+            }
+        }
+        let fills: Vec<RawFill> = deserialize_records(&json, &fill_records, "fills")?;
+
+        let (open_records, mut executed_records) =
+            process_orders_internal(&orders, &fills, &self.product_id, &self.options).map_err(PyValueError::new_err)?;
+        let already_seen = self
+            .seen_ids
+            .as_ref()
+            .map(|store| suppress_seen_orders(py, store.bind(py), &mut executed_records, &self.options))
+            .transpose()?;
+        let result = records_to_py(py, open_records, executed_records, &self.format)?;
+        if let Some(already_seen) = already_seen {
+            result.set_item("already_seen", already_seen)?;
+        }
+        Ok(Some(result.into()))
+    }
+
+    /// Copies of repeated order_ids merged away by `duplicate_orders`.
+    #[getter]
+    fn merged_orders(&self) -> usize {
+        self.merged_orders
+    }
+}
+
+/// The order_id of a bare record, read without deserializing it.
+fn record_order_id(record: &Bound<'_, PyAny>) -> Option<String> {
+    let order_id = record.downcast::<PyDict>().ok()?.get_item("order_id").ok()??;
+    order_id
+        .extract::<String>()
+        .ok()
+        .filter(|order_id| !order_id.is_empty() && !is_null_sentinel(order_id))
+}
+
+/// `dedupe_orders` by position: which copy of each repeated order_id wins,
+/// which positions are dropped and how many copies were merged away. Only
+/// the repeated copies are deserialized, to read their statuses.
+type StreamDuplicates = (FxHashMap<usize, usize>, FxHashSet<usize>, usize);
+
+fn stream_duplicates(
+    json: &Bound<'_, PyModule>,
+    orders: &Bound<'_, PyList>,
+    policy: DuplicatePolicy,
+) -> PyResult<StreamDuplicates> {
+    let mut winners = FxHashMap::default();
+    let mut superseded = FxHashSet::default();
+    if policy == DuplicatePolicy::KeepAll {
+        return Ok((winners, superseded, 0));
+    }
+    let mut positions: FxHashMap<String, Vec<usize>> = FxHashMap::default();
+    for (index, record) in orders.iter().enumerate() {
+        if let Some(order_id) = record_order_id(&record) {
+            positions.entry(order_id).or_default().push(index);
+        }
+    }
+    let mut merged = 0;
+    for (order_id, copies) in positions.into_iter().filter(|(_, copies)| copies.len() > 1) {
+        if policy == DuplicatePolicy::Error {
+            return Err(PyValueError::new_err(format!("duplicate order_id in orders payload: {}", order_id)));
+        }
+        let records = PyList::empty_bound(orders.py());
+        for &index in &copies {
+            records.append(orders.get_item(index)?)?;
+        }
+        let parsed: Vec<RawOrder> = deserialize_records(json, &records, "orders")?;
+        let mut best = 0;
+        for (copy, order) in parsed.iter().enumerate().skip(1) {
+            if status_precedence(order) >= status_precedence(&parsed[best]) {
+                best = copy;
+            }
+        }
+        winners.insert(copies[0], copies[best]);
+        superseded.extend(copies[1..].iter().copied());
+        merged += copies.len() - 1;
+    }
+    Ok((winners, superseded, merged))
+}
+
+/// Streaming variant of `process_orders_and_fills`: returns a `RecordStream`
+/// yielding one `{"open_records", "executed_records"}` dict per chunk.
+/// `duplicate_orders` and `seen_ids` behave as there; merged copies are
+/// counted on the stream's `merged_orders`.
+#[pyfunction]
+#[pyo3(signature = (
+    orders,
+    fills,
+    product_id,
+    *,
+    chunk_size = 5000,
+    working_statuses = None,
+    submitted_time_sources = None,
+    completed_time_sources = None,
+    now_timestamp_us = None,
+    fields = None,
+    dust_size = None,
+    duplicate_orders = "keep_all",
+    seen_ids = None,
+    timestamp_precision = "millis",
+    include_raw_timestamps = false,
+    columnar = false,
+    quote_currency = None,
+    inverse_products = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn iter_orders_and_fills(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    product_id: String,
+    chunk_size: usize,
+    working_statuses: Option<Vec<String>>,
+    submitted_time_sources: Option<Vec<String>>,
+    completed_time_sources: Option<Vec<String>>,
+    now_timestamp_us: Option<i64>,
+    fields: Option<Vec<String>>,
+    dust_size: Option<&str>,
+    duplicate_orders: &str,
+    seen_ids: Option<Py<SeenIdStore>>,
+    timestamp_precision: &str,
+    include_raw_timestamps: bool,
+    columnar: bool,
+    quote_currency: Option<String>,
+    inverse_products: Option<Vec<String>>,
+) -> PyResult<RecordStream> {
+    if chunk_size == 0 {
+        return Err(PyValueError::new_err("chunk_size must be positive"));
+    }
+    let options =
+        process_options(working_statuses, submitted_time_sources, completed_time_sources, now_timestamp_us, dust_size)?;
+    let json = py.import_bound("json")?;
+    let orders = payload_records(orders, &ORDER_ALIASES, "orders")?;
+    let (winners, superseded, merged_orders) =
+        stream_duplicates(&json, &orders, DuplicatePolicy::try_from(duplicate_orders)?)?;
+    let mut fills_by_order: FxHashMap<String, Vec<PyObject>> = FxHashMap::default();
+    for fill in payload_records(fills, &FILL_ALIASES, "fills")?.iter() {
+        if let Some(order_id) = record_order_id(&fill) {
+            fills_by_order.entry(order_id).or_default().push(fill.unbind());
+        }
+    }
+    Ok(RecordStream {
+        orders: orders.unbind(),
+        next_order: 0,
+        fills_by_order,
+        winners,
+        superseded,
+        merged_orders,
+        seen_ids,
+        product_id,
+        options,
+        format: RecordFormat {
+            fields: fields.map(|fields| fields.into_iter().collect()),
+            timestamps: TimestampPrecision::try_from(timestamp_precision)?,
+            raw_timestamps: include_raw_timestamps,
+            columnar,
+            quote_currency,
+            inverse_products: inverse_products.into_iter().flatten().collect(),
+        },
+        chunk_size,
+    })
+}
+
+/// Walks `prices` forward from each working stop-limit or bracket order's
+/// submission and reports when its stop would trigger and where it would
+/// fill. A stop-limit fills at the trigger price when that is already
+/// marketable, otherwise at its limit once the market reaches it; a bracket
+/// exits at its limit (take profit) or at the first price past its stop.
+/// Expiry is not modelled.
+#[pyfunction]
+#[pyo3(signature = (orders, fills, prices, product_id, *, working_statuses = None, timestamp_precision = "millis"))]
+fn simulate_stops(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    prices: Vec<PricePointSpec>,
+    product_id: &str,
+    working_statuses: Option<Vec<String>>,
+    timestamp_precision: &str,
+) -> PyResult<PyObject> {
+    let timestamps = TimestampPrecision::try_from(timestamp_precision)?;
+    let options = process_options(working_statuses, None, None, None, None)?;
+    let mut points: Vec<(DateTime<Utc>, Decimal)> = Vec::with_capacity(prices.len());
+    for point in &prices {
+        points.push((timestamp_us_to_datetime(point.timestamp_us)?, parse_decimal(&point.price, "price")?));
+    }
+    points.sort_by_key(|(timestamp, _)| *timestamp);
+
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+    let (_, executed_records) =
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)?;
+
+    let rows = PyList::empty_bound(py);
+    for record in &executed_records {
+        let bracket = match record.order_type {
+            OrderConfigType::TriggerBracket => true,
+            OrderConfigType::StopLimit => false,
+            _ => continue,
+        };
+        let (Some(stop_price), true) = (record.stop_price, options.is_working(&record.status)) else {
+            continue;
+        };
+        let direction = record.stop_direction.unwrap_or_else(|| StopDirection::for_side(record.side));
+        let limit = record.limit_price;
+        let marketable = |price: Decimal| {
+            limit > Decimal::ZERO
+                && match record.side {
+                    Side::Buy => price <= limit,
+                    Side::Sell => price >= limit,
+                }
+        };
+
+        let mut trigger: Option<(DateTime<Utc>, Decimal)> = None;
+        let mut fill: Option<(DateTime<Utc>, Decimal, &str)> = None;
+        for &(timestamp, price) in points.iter().filter(|(timestamp, _)| *timestamp >= record.ts_submitted) {
+            match trigger {
+                None if bracket && marketable(price) => {
+                    fill = Some((timestamp, limit, "take_profit"));
+                    break;
+                }
+                None if direction.is_triggered(stop_price, price) => {
+                    trigger = Some((timestamp, price));
+                    if bracket || marketable(price) {
+                        fill = Some((timestamp, price, "stop"));
+                        break;
+                    }
+                }
+                Some(_) if marketable(price) => {
+                    fill = Some((timestamp, limit, "stop"));
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let remaining = (record.base_size - record.filled_size.unwrap_or(Decimal::ZERO)).max(Decimal::ZERO);
+        let row = PyDict::new_bound(py);
+        row.set_item("order_id", record.order_id)?;
+        row.set_item("side", record.side.as_str())?;
+        row.set_item("order_type", record.order_type.as_str())?;
+        row.set_item("stop_price", stop_price.to_string())?;
+        row.set_item("limit_price", (limit > Decimal::ZERO).then(|| limit.to_string()))?;
+        row.set_item("stop_direction", direction.as_str())?;
+        row.set_item("remaining_size", remaining.to_string())?;
+        row.set_item("triggered", trigger.is_some())?;
+        row.set_item("trigger_time", trigger.map(|(timestamp, _)| timestamps.format(timestamp)))?;
+        row.set_item("trigger_price", trigger.map(|(_, price)| price.to_string()))?;
+        row.set_item("filled", fill.is_some())?;
+        row.set_item("fill_time", fill.map(|(timestamp, _, _)| timestamps.format(timestamp)))?;
+        row.set_item("expected_fill_price", fill.map(|(_, price, _)| price.to_string()))?;
+        row.set_item("exit", fill.map(|(_, _, exit)| exit))?;
+        rows.append(row)?;
+    }
+    Ok(rows.into())
+}
+
+/// Turns every fill of an executed order into a trade carrying the order's
+/// side and product, time-ordered and cut off at `cutoff`. Maker status comes
+/// from the fill's `liquidity_indicator`, then the order's post_only flag, as
+/// in `summarise_fills`.
+/// Fills without a trade_time fall back to the order's completion time.
+/// Repeated records of one order contribute its fills once, via the first.
+fn trades_from_records(
+    records: &[ProcessedExecutedRecord<'_>],
+    fills_by_order: &FillMap<'_>,
+    cutoff: DateTime<Utc>,
+) -> Vec<Trade> {
+    let mut labels = Interner::default();
+    let mut trades = Vec::new();
+    let mut seen_orders: FxHashSet<&str> = FxHashSet::default();
+    for (input_index, record) in records.iter().enumerate() {
+        if !seen_orders.insert(record.order_id) {
+            continue;
+        }
+        let Some(fills) = fills_by_order.get(record.order_id) else {
+            continue;
+        };
+        let product_id = labels.intern(record.product_id);
+        for fill in fills {
+            let timestamp = fill
+                .trade_time
+                .or(record.ts_filled)
+                .unwrap_or(record.ts_submitted);
+            if timestamp < cutoff {
+                continue;
+            }
+            trades.push(Trade {
+                timestamp,
+                side: record.side,
+                price: fill.price,
+                size: fill.size,
+                post_only: fill.maker.unwrap_or(record.post_only),
+                product_id: Some(product_id.clone()),
+                leg_group: None,
+                trade_id: fill.trade_id.map(Arc::from),
+                input_index,
+                commission: None,
+                commission_currency: None,
+                inverse: false,
+            });
+        }
+    }
+    trades.sort_by_key(|trade| trade.timestamp);
+    trades
+}
+
+/// A resting limit quote at one price over `[start, end)`.
+struct QuoteSegment {
+    side: Side,
+    price: Decimal,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+}
+
+/// Splits each limit order's life into constant-price segments. The price in
+/// force is the latest accepted edit, or the configured limit before any.
+/// Working orders run until `now`; finished ones until their completion.
+fn quote_segments(
+    records: &[ProcessedExecutedRecord<'_>],
+    orders: &[RawOrder],
+    options: &ProcessOptions,
+) -> Vec<QuoteSegment> {
+    let edits: FxHashMap<&str, &[RawEdit]> = orders
+        .iter()
+        .filter_map(|order| Some((order.order_id.as_deref()?, order.edit_history.as_deref()?)))
+        .collect();
+    let mut segments = Vec::new();
+    for record in records {
+        if !matches!(record.order_type, OrderConfigType::Limit) || record.limit_price <= Decimal::ZERO {
+            continue;
+        }
+        let end = if options.is_working(&record.status) {
+            options.now
+        } else {
+            match record.ts_filled {
+                Some(end) => end,
+                None => continue,
+            }
+        };
+        let mut changes: Vec<(DateTime<Utc>, Decimal)> = edits
+            .get(record.order_id)
+            .into_iter()
+            .flat_map(|edits| edits.iter())
+            .filter_map(|edit| {
+                let timestamp = edit.replace_accept_timestamp.as_deref().and_then(parse_datetime_text)?;
+                Some((timestamp, decimal_from_value(edit.price.as_ref())?))
+            })
+            .filter(|(timestamp, _)| *timestamp > record.ts_submitted && *timestamp < end)
+            .collect();
+        changes.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let (mut start, mut price) = (record.ts_submitted, record.limit_price);
+        for (timestamp, next_price) in changes {
+            segments.push(QuoteSegment { side: record.side, price, start, end: timestamp });
+            (start, price) = (timestamp, next_price);
+        }
+        if start < end {
+            segments.push(QuoteSegment { side: record.side, price, start, end });
+        }
+    }
+    segments
+}
+
+/// A stretch of `[from, to)` over which the mid and the resting quotes are
+/// constant, with whether a bid and an ask were close enough to it.
+struct QuoteSpan {
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    bid: bool,
+    ask: bool,
+}
+
+/// Splits `[start, end)` at every segment boundary and mid change in one
+/// sorted sweep, keeping the resting prices of each side in an ordered map so
+/// each span only needs a range lookup around mid. Spans without a positive
+/// mid are left out.
+fn quote_spans(
+    segments: &[QuoteSegment],
+    mids: &PriceSeries,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    max_distance: Decimal,
+) -> Vec<QuoteSpan> {
+    let mut events: Vec<(DateTime<Utc>, bool, Side, Decimal)> = Vec::with_capacity(segments.len() * 2);
+    for segment in segments.iter().filter(|segment| segment.end > start && segment.start < end) {
+        events.push((segment.start.max(start), true, segment.side, segment.price));
+        events.push((segment.end, false, segment.side, segment.price));
+    }
+    events.sort_by_key(|(timestamp, ..)| *timestamp);
+    let mut breakpoints: Vec<DateTime<Utc>> = events
+        .iter()
+        .map(|(timestamp, ..)| *timestamp)
+        .chain(mids.series.iter().map(|(timestamp, _)| *timestamp))
+        .filter(|timestamp| *timestamp > start && *timestamp < end)
+        .chain([start, end])
+        .collect();
+    breakpoints.sort();
+    breakpoints.dedup();
+
+    let mut resting: [BTreeMap<Decimal, usize>; 2] = Default::default();
+    let mut events = events.into_iter().peekable();
+    let mut spans = Vec::new();
+    for window in breakpoints.windows(2) {
+        let (from, to) = (window[0], window[1]);
+        while let Some((_, opens, side, price)) = events.next_if(|(timestamp, ..)| *timestamp <= from) {
+            let prices = &mut resting[usize::from(side == Side::Sell)];
+            if opens {
+                *prices.entry(price).or_default() += 1;
+            } else if let Some(count) = prices.get_mut(&price) {
+                *count -= 1;
+                if *count == 0 {
+                    prices.remove(&price);
+                }
+            }
+        }
+        let Some(mid) = mids.series_at(from).filter(|mid| *mid > Decimal::ZERO) else {
+            continue;
+        };
+        let reach = mid * max_distance;
+        let quoted = |side: Side| {
+            resting[usize::from(side == Side::Sell)]
+                .range(mid - reach..=mid + reach)
+                .next()
+                .is_some()
+        };
+        spans.push(QuoteSpan { from, to, bid: quoted(Side::Buy), ask: quoted(Side::Sell) });
+    }
+    spans
+}
+
+/// Share of each interval during which a bid and an ask were both resting
+/// within `max_distance_bps` of mid, the usual market-making SLA figure.
+/// Mid is the last `mid_prices` point at or before each moment; time before
+/// the first point counts as unquoted.
+#[pyfunction]
+#[pyo3(signature = (
+    orders,
+    fills,
+    mid_prices,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    product_id,
+    *,
+    max_distance_bps = "50",
+    working_statuses = None,
+))]
+#[allow(clippy::too_many_arguments)]
+fn quote_uptime(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    mid_prices: Vec<PricePointSpec>,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    product_id: &str,
+    max_distance_bps: &str,
+    working_statuses: Option<Vec<String>>,
+) -> PyResult<PyObject> {
+    let options = process_options(working_statuses, None, None, Some(now_timestamp_us), None)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+    let max_distance = parse_decimal(max_distance_bps, "max_distance_bps")?;
+    if max_distance < Decimal::ZERO {
+        return Err(PyValueError::new_err("max_distance_bps must not be negative"));
+    }
+    let max_distance = max_distance / Decimal::from(10_000);
+    let Some(mids) = PriceSeries::parse(None, Some(mid_prices))? else {
+        return Ok(PyList::empty_bound(py).into());
+    };
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+    let (_, executed_records) =
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)?;
+    let segments = quote_segments(&executed_records, &orders, &options);
+
+    let end = options.now;
+    let starts = intervals
+        .iter()
+        .map(|spec| interval_start(end, spec, cutoff))
+        .collect::<PyResult<Vec<_>>>()?;
+    let earliest = starts.iter().copied().min().unwrap_or(end);
+    let spans = quote_spans(&segments, &mids, earliest, end, max_distance);
+
+    let rows = PyList::empty_bound(py);
+    for (spec, start) in intervals.iter().zip(starts) {
+        let (mut bid_us, mut ask_us, mut both_us) = (0i64, 0i64, 0i64);
+        for span in &spans[spans.partition_point(|span| span.to <= start)..] {
+            let length = (span.to - span.from.max(start)).num_microseconds().unwrap_or(0);
+            bid_us += if span.bid { length } else { 0 };
+            ask_us += if span.ask { length } else { 0 };
+            both_us += if span.bid && span.ask { length } else { 0 };
+        }
+
+        let window_us = (end - start).num_microseconds().unwrap_or(0);
+        let percent = |covered: i64| {
+            (window_us > 0).then(|| {
+                (Decimal::from(covered) / Decimal::from(window_us) * Decimal::ONE_HUNDRED)
+                    .round_dp(4)
+                    .normalize()
+                    .to_string()
+            })
+        };
+        let row = PyDict::new_bound(py);
+        row.set_item("key", &spec.key)?;
+        row.set_item("label", spec.label())?;
+        row.set_item("window_seconds", window_us / 1_000_000)?;
+        row.set_item("two_sided_seconds", both_us / 1_000_000)?;
+        row.set_item("uptime_pct", percent(both_us))?;
+        row.set_item("bid_uptime_pct", percent(bid_us))?;
+        row.set_item("ask_uptime_pct", percent(ask_us))?;
+        rows.append(row)?;
+    }
+    Ok(rows.into())
+}
+
+/// Implementation shortfall per parent order against its decision price (the
+/// explicit arrival price for the parent, else the series at the first
+/// submission), split Perold-style: delay cost from decision to the market at
+/// each child's submission, trading cost from there to each fill plus fees,
+/// and opportunity cost on the unfilled remainder marked at the child's end
+/// (or `now`). Orders sharing a `parent_order_id` (bracket and OCO legs, or
+/// the slices of a parent) roll up into one row; other orders are their own
+/// parent. A leg cancelled because its OCO sibling executed carries no
+/// opportunity cost. Fees are the fill's commission, else its notional at the
+/// rate of its liquidity side (falling back to the order's post_only flag).
+/// Costs are in quote and positive when they hurt. Parents without a decision
+/// price are skipped.
+#[pyfunction]
+#[pyo3(signature = (
+    orders,
+    fills,
+    product_id,
+    maker_fee_rate,
+    taker_fee_rate,
+    *,
+    arrival_prices = None,
+    arrival_price_series = None,
+    now_timestamp_us = None,
+    float_output = false,
+))]
+#[allow(clippy::too_many_arguments)]
+fn implementation_shortfall(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    product_id: &str,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    arrival_prices: Option<BTreeMap<String, String>>,
+    arrival_price_series: Option<Vec<PricePointSpec>>,
+    now_timestamp_us: Option<i64>,
+    float_output: bool,
+) -> PyResult<PyObject> {
+    let maker_fee = parse_decimal(maker_fee_rate, "maker_fee_rate")?;
+    let taker_fee = parse_decimal(taker_fee_rate, "taker_fee_rate")?;
+    let options = process_options(None, None, None, now_timestamp_us, None)?;
+    let Some(arrival) = PriceSeries::parse(arrival_prices, arrival_price_series)? else {
+        return Err(PyValueError::new_err("arrival_prices or arrival_price_series is required"));
+    };
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+    let (_, executed_records) =
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)?;
+    let fills_by_order = collect_fills(&fills);
+    let output = OutputOptions {
+        floats: float_output,
+        ..OutputOptions::default()
+    };
+
+    // Parents in order of their first child; repeated records count once.
+    let mut parents: Vec<(&str, Vec<&ProcessedExecutedRecord<'_>>)> = Vec::new();
+    let mut parent_index: FxHashMap<&str, usize> = FxHashMap::default();
+    let mut seen_orders: FxHashSet<&str> = FxHashSet::default();
+    for record in &executed_records {
+        if !seen_orders.insert(record.order_id) {
+            continue;
+        }
+        let parent = record.parent_order_id.unwrap_or(record.order_id);
+        let index = *parent_index.entry(parent).or_insert_with(|| {
+            parents.push((parent, Vec::new()));
+            parents.len() - 1
+        });
+        parents[index].1.push(record);
+    }
+
+    let rows = PyList::empty_bound(py);
+    for (parent, children) in &parents {
+        let first_submitted = children.iter().map(|record| record.ts_submitted).min().unwrap_or(options.now);
+        let Some(decision) = arrival.lookup(parent, first_submitted) else {
+            continue;
+        };
+        let side = children.iter().find(|record| record.order_id == *parent).unwrap_or(&children[0]).side;
+        let (mut base_size, mut filled_size, mut unfilled_size) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        let (mut delay_cost, mut trading_cost, mut fees) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        let (mut opportunity_cost, mut paper_notional) = (Decimal::ZERO, Decimal::ZERO);
+        for record in children {
+            let submission = arrival.series_at(record.ts_submitted).unwrap_or(decision);
+            let end_price = arrival.series_at(record.ts_filled.unwrap_or(options.now));
+            let sign = match record.side {
+                Side::Buy => Decimal::ONE,
+                Side::Sell => Decimal::NEGATIVE_ONE,
+            };
+            let filled = record.filled_size.unwrap_or(Decimal::ZERO);
+            let unfilled = if record.oco_cancelled_by.is_some() {
+                Decimal::ZERO
+            } else {
+                (record.base_size - filled).max(Decimal::ZERO)
+            };
+            match fills_by_order.get(record.order_id) {
+                Some(order_fills) => {
+                    for fill in order_fills {
+                        let fee_rate = if fill.maker.unwrap_or(record.post_only) { maker_fee } else { taker_fee };
+                        fees += fill.commission.unwrap_or(fill.price * fill.size * fee_rate);
+                        trading_cost += sign * (fill.price - submission) * fill.size;
+                    }
+                }
+                None => {
+                    let fee_rate = if record.post_only { maker_fee } else { taker_fee };
+                    if let Some(price) = record.avg_fill_price {
+                        fees += price * filled * fee_rate;
+                        trading_cost += sign * (price - submission) * filled;
+                    }
+                }
+            }
+            delay_cost += sign * (submission - decision) * filled;
+            opportunity_cost += end_price.map_or(Decimal::ZERO, |price| sign * (price - decision) * unfilled);
+            paper_notional += decision * (filled + unfilled);
+            base_size += filled + unfilled;
+            filled_size += filled;
+            unfilled_size += unfilled;
+        }
+        trading_cost += fees;
+        let total = delay_cost + trading_cost + opportunity_cost;
+
+        let row = PyDict::new_bound(py);
+        row.set_item("parent_order_id", *parent)?;
+        row.set_item("order_ids", children.iter().map(|record| record.order_id).collect::<Vec<_>>())?;
+        row.set_item("side", side.as_str())?;
+        row.set_item("base_size", output.value(py, base_size))?;
+        row.set_item("filled_size", output.value(py, filled_size))?;
+        row.set_item("unfilled_size", output.value(py, unfilled_size))?;
+        row.set_item("decision_price", output.value(py, decision))?;
+        row.set_item("paper_notional", output.value(py, paper_notional))?;
+        row.set_item("delay_cost", output.value(py, delay_cost))?;
+        row.set_item("trading_cost", output.value(py, trading_cost))?;
+        row.set_item("fees", output.value(py, fees))?;
+        row.set_item("opportunity_cost", output.value(py, opportunity_cost))?;
+        row.set_item("total_shortfall", output.value(py, total))?;
+        row.set_item(
+            "shortfall_bps",
+            (!paper_notional.is_zero()).then(|| output.value(py, slippage_bps(total, paper_notional))),
+        )?;
+        rows.append(row)?;
+    }
+    Ok(rows.into())
+}
+
+/// Realized PnL per order from the lot match ledger, joined back through the
+/// fills. `realized_profit` is what the order realized when it closed lots;
+/// `opened_lot_profit` is what lots it opened later realized, so an entry
+/// and its exit can each be credited. Orders without fills are omitted.
+fn order_pnl_to_py<'py>(
+    py: Python<'py>,
+    records: &[ProcessedExecutedRecord<'_>],
+    trades: &[Trade],
+    fees: &FeeSchedule,
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    let options = ReplayOptions {
+        match_ledger: true,
+        ..ReplayOptions::default()
+    };
+    let replay = build_entries(trades, fees, EntryScales::default(), options)?;
+
+    #[derive(Default)]
+    struct OrderPnl {
+        fill_count: u64,
+        filled_size: Decimal,
+        realized_profit: Decimal,
+        opened_lot_profit: Decimal,
+        fees: Decimal,
+    }
+    let mut by_record: BTreeMap<usize, OrderPnl> = BTreeMap::new();
+    for (trade, entry) in trades.iter().zip(&replay.entries) {
+        let pnl = by_record.entry(trade.input_index).or_default();
+        pnl.fill_count += 1;
+        pnl.filled_size += trade.size;
+        pnl.fees += entry.fee;
+    }
+    for matched in &replay.matches {
+        if let Some(pnl) = by_record.get_mut(&trades[matched.close_trade].input_index) {
+            pnl.realized_profit += matched.realized;
+        }
+        if let Some(pnl) = by_record.get_mut(&trades[matched.open_trade].input_index) {
+            pnl.opened_lot_profit += matched.realized;
+        }
+    }
+
+    let rows = PyList::empty_bound(py);
+    for (index, pnl) in by_record {
+        let record = &records[index];
+        let row = PyDict::new_bound(py);
+        row.set_item("order_id", record.order_id)?;
+        row.set_item("client_order_id", record.client_order_id)?;
+        row.set_item("side", record.side.as_str())?;
+        row.set_item("fill_count", pnl.fill_count)?;
+        row.set_item("filled_size", output.value(py, pnl.filled_size))?;
+        row.set_item("realized_profit", output.value(py, pnl.realized_profit))?;
+        row.set_item("opened_lot_profit", output.value(py, pnl.opened_lot_profit))?;
+        row.set_item("fees", output.value(py, pnl.fees))?;
+        row.set_item("net_profit", output.value(py, pnl.realized_profit - pnl.fees))?;
+        rows.append(row)?;
+    }
+    Ok(rows)
+}
+
+/// Realized PnL of each bracket entry together with the legs attached to it
+/// (via `originating_order_id`), replayed on a book of its own so the exits
+/// close the entry they protect. `trades` must include fills from before
+/// `cutoff` so an earlier entry is on the book; only fills at or after the
+/// cutoff count towards realized profit and fees. Entries without executed
+/// legs since the cutoff are omitted.
+fn bracket_groups_to_py<'py>(
+    py: Python<'py>,
+    records: &[ProcessedExecutedRecord<'_>],
+    trades: &[Trade],
+    cutoff: DateTime<Utc>,
+    fee_schedule: &FeeSchedule,
+    output: OutputOptions,
+) -> PyResult<Bound<'py, PyList>> {
+    let parents: FxHashMap<&str, usize> =
+        records.iter().enumerate().map(|(index, record)| (record.order_id, index)).collect();
+    let mut legs: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    let mut group_of: FxHashMap<usize, usize> = FxHashMap::default();
+    for (index, record) in records.iter().enumerate() {
+        if let Some(&parent) = record.parent_order_id.and_then(|parent| parents.get(parent)) {
+            legs.entry(parent).or_default().push(index);
+            group_of.insert(index, parent);
+            group_of.insert(parent, parent);
+        }
+    }
+    let mut members: BTreeMap<usize, Vec<&Trade>> = BTreeMap::new();
+    for trade in trades {
+        if let Some(&parent) = group_of.get(&trade.input_index) {
+            members.entry(parent).or_default().push(trade);
+        }
+    }
+
+    let rows = PyList::empty_bound(py);
+    for (parent, members) in members {
+        let mut book: Book<Decimal> = Book::default();
+        let (mut realized, mut fees, mut net_size) = (Decimal::ZERO, Decimal::ZERO, Decimal::ZERO);
+        let (mut entry_size, mut exit_size, mut reported_exits) = (Decimal::ZERO, Decimal::ZERO, false);
+        for trade in members {
+            let fee_rate = fee_schedule.rate(trade.timestamp, trade.post_only);
+            let (booked_side, booked_price) = book.booked(trade)?;
+            let fee = fee_schedule.charge(booked_price * trade.size, fee_rate)?;
+            let trade_realized = book.execute(booked_side, booked_price, trade.size, fee)?;
+            if trade.timestamp >= cutoff {
+                realized += trade_realized;
+                fees += fee;
+            }
+            net_size += match trade.side {
+                Side::Buy => trade.size,
+                Side::Sell => -trade.size,
+            };
+            if trade.input_index == parent {
+                entry_size += trade.size;
+            } else {
+                exit_size += trade.size;
+                reported_exits |= trade.timestamp >= cutoff;
+            }
+        }
+        if !reported_exits {
+            continue;
+        }
+        let legs = &legs[&parent];
+        let row = PyDict::new_bound(py);
+        row.set_item("parent_order_id", records[parent].order_id)?;
+        row.set_item("entry_side", records[parent].side.as_str())?;
+        row.set_item("leg_order_ids", legs.iter().map(|&leg| records[leg].order_id).collect::<Vec<_>>())?;
+        row.set_item("entry_size", output.value(py, entry_size))?;
+        row.set_item("exit_size", output.value(py, exit_size))?;
+        row.set_item("open_size", output.value(py, net_size.abs()))?;
+        row.set_item("realized_profit", output.value(py, realized))?;
+        row.set_item("fees", output.value(py, fees))?;
+        row.set_item("net_profit", output.value(py, realized - fees))?;
+        rows.append(row)?;
+    }
+    Ok(rows)
+}
+
+/// Runs order/fill processing and the interval summary in one call: fills
+/// become trades on their order's side, maker by their liquidity indicator
+/// or else the order's post_only flag.
+#[pyfunction]
+#[pyo3(signature = (
+    orders,
+    fills,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    maker_fee_rate,
+    taker_fee_rate,
+    product_id,
+    *,
+    float_output = false,
+    include_bracket_groups = false,
+    arrival_prices = None,
+    arrival_price_series = None,
+    include_order_pnl = false,
+    mixed_products = "warn",
+    inverse_products = None,
+    oversell = "allow_short",
+    fee_schedule = None,
+    min_fee = None,
+    max_fee = None,
+    quote_currency = None,
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn summarise_from_orders_and_fills(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
+    product_id: &str,
+    float_output: bool,
+    include_bracket_groups: bool,
+    arrival_prices: Option<BTreeMap<String, String>>,
+    arrival_price_series: Option<Vec<PricePointSpec>>,
+    include_order_pnl: bool,
+    mixed_products: &str,
+    inverse_products: Option<Vec<String>>,
+    oversell: &str,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<&str>,
+    max_fee: Option<&str>,
+    quote_currency: Option<String>,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let arrival = PriceSeries::parse(arrival_prices, arrival_price_series)?;
+    let fees = FeeSchedule::parse(maker_fee_rate, taker_fee_rate, fee_schedule, min_fee, max_fee)?;
+    let now = timestamp_us_to_datetime(now_timestamp_us)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+
+    let options = ProcessOptions {
+        now,
+        watch: Some(watch.clone()),
+        ..ProcessOptions::default()
+    };
+    let (_, mut executed_records) = run_watched(py, &watch, "orders", orders.len(), || {
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)
+    })?;
+    if let Some(arrival) = &arrival {
+        arrival.assign(&mut executed_records);
+    }
+    let fills_by_order = collect_fills(&fills);
+    let mut trades = trades_from_records(&executed_records, &fills_by_order, cutoff);
+    mark_inverse(&mut trades, inverse_products.clone());
+    let output = OutputOptions {
+        floats: float_output,
+        ..OutputOptions::default()
+    };
+    let result = summary_to_py(
+        py,
+        &trades,
+        &intervals,
+        now,
+        cutoff,
+        &fees,
+        output,
+        MixedProductPolicy::try_from(mixed_products)?,
+        OversellPolicy::try_from(oversell)?,
+        quote_currency,
+        &watch,
+        &FxHashSet::default(),
+    )?;
+    if include_bracket_groups {
+        let mut all_trades = trades_from_records(&executed_records, &fills_by_order, DateTime::<Utc>::MIN_UTC);
+        mark_inverse(&mut all_trades, inverse_products);
+        let groups = bracket_groups_to_py(py, &executed_records, &all_trades, cutoff, &fees, output)?;
+        result.set_item("bracket_groups", groups)?;
+    }
+    if include_order_pnl {
+        let order_pnl = order_pnl_to_py(py, &executed_records, &trades, &fees, output)?;
+        result.set_item("order_pnl", order_pnl)?;
+    }
+    let execution = execution_quality_to_py(py, &executed_records, &intervals, now, cutoff, output)?;
+    result.set_item("execution_quality", execution)?;
+    result.set_item("order_flow", order_flow_to_py(py, &executed_records, &intervals, &options, cutoff)?)?;
+    Ok(result.into())
+}
+
+/// Interval report, totals and open position for already-built trades.
+#[allow(clippy::too_many_arguments)]
+fn summary_to_py<'py>(
+    py: Python<'py>,
+    trades: &[Trade],
+    intervals: &[IntervalSpec],
+    now: DateTime<Utc>,
+    cutoff: DateTime<Utc>,
+    fees: &FeeSchedule,
+    output: OutputOptions,
+    mixed_products: MixedProductPolicy,
+    oversell: OversellPolicy,
+    quote_currency: Option<String>,
+    watch: &Watch,
+    reported_before: &FxHashSet<usize>,
+) -> PyResult<Bound<'py, PyDict>> {
+    check_single_product(py, trades, mixed_products)?;
+    let options = ReplayOptions {
+        oversell,
+        watch: Tracker(Some(watch)),
+        ..ReplayOptions::default()
+    };
+    let mut replay = run_watched(py, watch, "replay", trades.len(), || {
+        build_entries(trades, fees, EntryScales::default(), options)
+    })?;
+    if !reported_before.is_empty() {
+        // Entries line up with trades; drop those an earlier call reported.
+        let mut trades = trades.iter();
+        replay.entries.retain(|_| {
+            trades
+                .next()
+                .is_some_and(|trade| !reported_before.contains(&trade.input_index))
+        });
+    }
+    let intervals = anchor_since_last_flat(intervals, &replay, now);
+    let report = build_interval_report(replay.ledger(), &intervals, now, cutoff, &CarryFlows::default())?;
+
+    let result = PyDict::new_bound(py);
+    report.write_py(py, &result, &intervals, output, pnl_currency(trades, quote_currency).as_deref())?;
+    result.set_item("open_position", replay.open_position.to_py(py)?)?;
+    result.set_item("trade_count", trades.len())?;
+    result.set_item("oversold_count", replay.oversold_count)?;
+    Ok(result)
+}
+
+/// Decides maker vs taker for fills the payload leaves ambiguous: the fill
+/// is a maker (or taker, per `maker`) when `field` equals `value`. Fields are
+/// `order_type`, `liquidity_indicator`, `product_id` and `side`.
+struct MakerRule {
+    field: String,
+    value: String,
+    maker: bool,
+}
+
+impl<'py> FromPyObject<'py> for MakerRule {
+    fn extract_bound(obj: &Bound<'py, PyAny>) -> PyResult<Self> {
+        let field: String = obj.get_item("field")?.extract()?;
+        if !matches!(field.as_str(), "order_type" | "liquidity_indicator" | "product_id" | "side") {
+            return Err(PyValueError::new_err(format!("unknown maker rule field: {}", field)));
+        }
+        Ok(MakerRule {
+            field,
+            value: obj.get_item("value")?.extract()?,
+            maker: obj.get_item("maker")?.extract()?,
+        })
+    }
+}
+
+impl MakerRule {
+    fn decide(
+        &self,
+        fill: &RawFill,
+        record: Option<&ProcessedExecutedRecord<'_>>,
+        product_id: &str,
+        side: Side,
+    ) -> Option<bool> {
+        let actual = match self.field.as_str() {
+            "order_type" => record.map(|record| record.order_type.as_str()),
+            "liquidity_indicator" => fill.liquidity_indicator.as_deref(),
+            "product_id" => Some(product_id),
+            _ => Some(side.as_str()),
+        };
+        actual
+            .is_some_and(|actual| actual.eq_ignore_ascii_case(&self.value))
+            .then_some(self.maker)
+    }
+}
+
+/// Maker status from a fill's `liquidity_indicator`, when it says.
+fn fill_is_maker(fill: &RawFill) -> Option<bool> {
+    match fill.liquidity_indicator.as_deref().map(str::to_ascii_uppercase).as_deref() {
+        Some("MAKER") => Some(true),
+        Some("TAKER") => Some(false),
+        _ => None,
+    }
+}
+
+/// Computes PnL straight from fills. Side comes from the fill or, failing
+/// that, its order in `orders`; maker status from `liquidity_indicator`, then
+/// the order's post_only flag, then `maker_rules`, then `classify_maker`,
+/// and is taker otherwise. Fills with no usable side, size or price, and
+/// fills before the cutoff, are skipped and counted in `skipped_fills`.
+#[pyfunction]
+#[pyo3(signature = (
+    fills,
+    intervals,
+    now_timestamp_us,
+    cutoff_timestamp_us,
+    maker_fee_rate,
+    taker_fee_rate,
+    product_id,
+    *,
+    orders = None,
+    float_output = false,
+    maker_rules = None,
+    classify_maker = None,
+    seen_ids = None,
+    mixed_products = "warn",
+    inverse_products = None,
+    oversell = "allow_short",
+    fee_schedule = None,
+    min_fee = None,
+    max_fee = None,
+    quote_currency = None,
+    cancel_token = None,
+    progress = None,
+    progress_every = 10_000,
+))]
+#[allow(clippy::too_many_arguments)]
+fn summarise_fills(
     py: Python<'_>,
-    orders: &PyAny,
-    fills: &PyAny,
+    fills: &Bound<'_, PyAny>,
+    intervals: Vec<IntervalSpec>,
+    now_timestamp_us: i64,
+    cutoff_timestamp_us: i64,
+    maker_fee_rate: &str,
+    taker_fee_rate: &str,
     product_id: &str,
+    orders: Option<&Bound<'_, PyAny>>,
+    float_output: bool,
+    maker_rules: Option<Vec<MakerRule>>,
+    classify_maker: Option<&Bound<'_, PyAny>>,
+    seen_ids: Option<&Bound<'_, SeenIdStore>>,
+    mixed_products: &str,
+    inverse_products: Option<Vec<String>>,
+    oversell: &str,
+    fee_schedule: Option<Vec<FeeRateSpec>>,
+    min_fee: Option<&str>,
+    max_fee: Option<&str>,
+    quote_currency: Option<String>,
+    cancel_token: Option<PyRef<'_, CancelToken>>,
+    progress: Option<PyObject>,
+    progress_every: usize,
+) -> PyResult<PyObject> {
+    let watch = Arc::new(Watch::new(cancel_token, progress, progress_every)?);
+    let maker_rules = maker_rules.unwrap_or_default();
+    let fees = FeeSchedule::parse(maker_fee_rate, taker_fee_rate, fee_schedule, min_fee, max_fee)?;
+    let now = timestamp_us_to_datetime(now_timestamp_us)?;
+    let cutoff = timestamp_us_to_datetime(cutoff_timestamp_us)?;
+    let no_orders = PyList::empty_bound(py);
+    let (orders, fills) = load_payloads(py, orders.unwrap_or(no_orders.as_any()), fills)?;
+
+    let options = ProcessOptions {
+        now,
+        watch: Some(watch.clone()),
+        ..ProcessOptions::default()
+    };
+    let (_, executed_records) = run_watched(py, &watch, "orders", orders.len(), || {
+        process_orders_internal(&orders, &fills, product_id, &options).map_err(PyValueError::new_err)
+    })?;
+    let records_by_order: FxHashMap<&str, &ProcessedExecutedRecord<'_>> =
+        executed_records.iter().map(|record| (record.order_id, record)).collect();
+
+    let mut labels = Interner::default();
+    let mut trades = Vec::with_capacity(fills.len());
+    let mut skipped_fills = 0;
+    let mut already_seen = 0;
+    let mut new_fill_ids = Vec::new();
+    let mut batch_fill_ids: FxHashSet<&str> = FxHashSet::default();
+    let mut reported_before: FxHashSet<usize> = FxHashSet::default();
+    for (input_index, fill) in fills.iter().enumerate() {
+        let record = fill.order_id.as_deref().and_then(|id| records_by_order.get(id));
+        let side = fill
+            .side
+            .as_deref()
+            .and_then(|text| Side::try_from(text).ok())
+            .or(record.map(|record| record.side));
+        let timestamp = fill
+            .trade_time
+            .as_deref()
+            .and_then(parse_datetime_text)
+            .or(record.and_then(|record| record.ts_filled));
+        let (Some(side), Some((size, price)), Some(timestamp)) = (side, fill_size_and_price(fill), timestamp) else {
+            skipped_fills += 1;
+            continue;
+        };
+        if timestamp < cutoff {
+            skipped_fills += 1;
+            continue;
+        }
+        // A repeat within the batch is the same fill twice and is dropped; a
+        // fill reported by an earlier call still opens and closes lots, but
+        // its own PnL is left out of this report.
+        if let (Some(store), Some(trade_id)) = (seen_ids, fill.trade_id.as_deref().filter(|s| !s.is_empty())) {
+            if !batch_fill_ids.insert(trade_id) {
+                already_seen += 1;
+                continue;
+            }
+            if store.borrow().fills.contains(trade_id) {
+                already_seen += 1;
+                reported_before.insert(input_index);
+            } else {
+                new_fill_ids.push(trade_id.to_string());
+            }
+        }
+        let fill_product = fill
+            .product_id
+            .as_deref()
+            .filter(|s| !s.is_empty())
+            .or(record.map(|record| record.product_id))
+            .unwrap_or(product_id);
+        // A post-only order is conclusive; a plain limit order may still have
+        // rested, so only `true` short-circuits the rules and callback.
+        let mut post_only = fill_is_maker(fill).or(record.and_then(|record| record.post_only.then_some(true)));
+        if post_only.is_none() {
+            post_only = maker_rules
+                .iter()
+                .find_map(|rule| rule.decide(fill, record.copied(), fill_product, side));
+        }
+        if let (None, Some(callback)) = (post_only, classify_maker) {
+            let context = PyDict::new_bound(py);
+            context.set_item("order_id", fill.order_id.as_deref())?;
+            context.set_item("trade_id", fill.trade_id.as_deref())?;
+            context.set_item("product_id", fill_product)?;
+            context.set_item("side", side.as_str())?;
+            context.set_item("price", price.to_string())?;
+            context.set_item("size", size.to_string())?;
+            context.set_item("liquidity_indicator", fill.liquidity_indicator.as_deref())?;
+            context.set_item("order_type", record.map(|record| record.order_type.as_str()))?;
+            context.set_item("limit_price", record.map(|record| record.limit_price.to_string()))?;
+            post_only = callback.call1((context,))?.extract()?;
+        }
+        trades.push(Trade {
+            timestamp,
+            side,
+            price,
+            size,
+            post_only: post_only.unwrap_or(false),
+            product_id: Some(labels.intern(fill_product)),
+            leg_group: None,
+            trade_id: fill.trade_id.as_deref().map(Arc::from),
+            input_index,
+            commission: None,
+            commission_currency: None,
+            inverse: false,
+        });
+    }
+    trades.sort_by_key(|trade| trade.timestamp);
+    mark_inverse(&mut trades, inverse_products);
+
+    let output = OutputOptions {
+        floats: float_output,
+        ..OutputOptions::default()
+    };
+    let result = summary_to_py(
+        py,
+        &trades,
+        &intervals,
+        now,
+        cutoff,
+        &fees,
+        output,
+        MixedProductPolicy::try_from(mixed_products)?,
+        OversellPolicy::try_from(oversell)?,
+        quote_currency,
+        &watch,
+        &reported_before,
+    )?;
+    result.set_item("skipped_fills", skipped_fills)?;
+    if let Some(store) = seen_ids {
+        // Recorded only once the summary succeeded, so a failed call can be retried.
+        let mut store = store.borrow_mut();
+        let added = new_fill_ids.len();
+        store.fills.extend(new_fill_ids);
+        store.record_added(py, added)?;
+        result.set_item("already_seen", already_seen)?;
+    }
+    Ok(result.into())
+}
+
+/// Returns the lifecycle of every order as flat rows, ordered per order by
+/// `sequence`.
+#[pyfunction]
+#[pyo3(signature = (orders, fills, *, working_statuses = None, timestamp_precision = "millis"))]
+fn order_timeline(
+    py: Python<'_>,
+    orders: &Bound<'_, PyAny>,
+    fills: &Bound<'_, PyAny>,
+    working_statuses: Option<Vec<String>>,
+    timestamp_precision: &str,
 ) -> PyResult<PyObject> {
-    let json = py.import("json")?;
-    let orders_json: String = json.call_method1("dumps", (orders,))?.extract()?;
-    let fills_json: String = json.call_method1("dumps", (fills,))?.extract()?;
-
-    let orders: Vec<RawOrder> = serde_json::from_str(&orders_json)
-        .map_err(|err| PyValueError::new_err(format!("Failed to parse orders payload: {err}")))?;
-    let fills: Vec<RawFill> = serde_json::from_str(&fills_json)
-        .map_err(|err| PyValueError::new_err(format!("Failed to parse fills payload: {err}")))?;
-
-    match process_orders_internal(&orders, &fills, product_id) {
-        Ok((open_records, executed_records)) => {
-            let open_list = PyList::empty(py);
-            for record in open_records {
-                let dict = PyDict::new(py);
-                dict.set_item("order_id", record.order_id)?;
-                dict.set_item("side", record.side)?;
-                dict.set_item("limit_price", record.limit_price.to_string())?;
-                dict.set_item("base_size", record.base_size.to_string())?;
-                dict.set_item("status", record.status)?;
-                dict.set_item("client_order_id", record.client_order_id)?;
-                dict.set_item("end_time", record.end_time.map(format_datetime))?;
-                dict.set_item("product_id", record.product_id)?;
-                dict.set_item(
-                    "stop_price",
-                    record.stop_price.map(|d| d.to_string()),
-                )?;
-                open_list.append(dict)?;
-            }
-
-            let executed_list = PyList::empty(py);
-            for record in executed_records {
-                let dict = PyDict::new(py);
-                dict.set_item("order_id", record.order_id)?;
-                dict.set_item("ts_submitted", format_datetime(record.ts_submitted))?;
-                dict.set_item("ts_submitted_inferred", record.ts_submitted_inferred)?;
-                dict.set_item("ts_filled", record.ts_filled.map(format_datetime))?;
-                dict.set_item("side", record.side)?;
-                dict.set_item("limit_price", record.limit_price.to_string())?;
-                dict.set_item("base_size", record.base_size.to_string())?;
-                dict.set_item("status", record.status)?;
-                dict.set_item(
-                    "filled_size",
-                    record.filled_size.map(|d| d.to_string()),
-                )?;
-                dict.set_item("client_order_id", record.client_order_id)?;
-                dict.set_item("end_time", record.end_time.map(format_datetime))?;
-                dict.set_item("product_id", record.product_id)?;
-                dict.set_item(
-                    "stop_price",
-                    record.stop_price.map(|d| d.to_string()),
-                )?;
-                dict.set_item("post_only", record.post_only)?;
-                executed_list.append(dict)?;
-            }
-
-            let result = PyDict::new(py);
-            result.set_item("open_records", open_list)?;
-            result.set_item("executed_records", executed_list)?;
-            Ok(result.into())
-        }
-        Err(message) => Err(PyValueError::new_err(message)),
+    let timestamps = TimestampPrecision::try_from(timestamp_precision)?;
+    let mut options = ProcessOptions::default();
+    if let Some(statuses) = working_statuses {
+        options.working_statuses = statuses.iter().map(|s| s.to_ascii_uppercase()).collect();
+    }
+    let (orders, fills) = load_payloads(py, orders, fills)?;
+    let events = build_timelines(&orders, &fills, &options);
+
+    let rows = PyList::empty_bound(py);
+    let mut sequence = 0usize;
+    let mut current: Option<&str> = None;
+    for event in events {
+        if current != Some(event.order_id) {
+            current = Some(event.order_id);
+            sequence = 0;
+        }
+        let dict = PyDict::new_bound(py);
+        dict.set_item("order_id", event.order_id)?;
+        dict.set_item("sequence", sequence)?;
+        dict.set_item("event", event.event)?;
+        dict.set_item("timestamp", event.timestamp.map(|dt| timestamps.format(dt)))?;
+        dict.set_item("price", event.price.map(|d| d.to_string()))?;
+        dict.set_item("size", event.size.map(|d| d.to_string()))?;
+        dict.set_item("trade_id", event.trade_id)?;
+        rows.append(dict)?;
+        sequence += 1;
+    }
+    Ok(rows.into())
+}
+
+/// Returns copies of `trades` with `post_only` taken from the executed record
+/// sharing their `order_id`. Trades without a matching record keep their own
+/// flag, defaulting to taker.
+#[pyfunction]
+fn join_post_only<'py>(
+    py: Python<'py>,
+    trades: Vec<Bound<'py, PyDict>>,
+    executed_records: Vec<Bound<'py, PyDict>>,
+) -> PyResult<Bound<'py, PyList>> {
+    let mut post_only_by_order: FxHashMap<String, bool> = FxHashMap::default();
+    for record in &executed_records {
+        let Some(order_id) = record.get_item("order_id")?.filter(|id| !id.is_none()) else {
+            continue;
+        };
+        let post_only = match record.get_item("post_only")? {
+            Some(flag) if !flag.is_none() => flag.is_truthy()?,
+            _ => false,
+        };
+        post_only_by_order.insert(order_id.extract()?, post_only);
+    }
+
+    let joined = PyList::empty_bound(py);
+    for trade in trades {
+        let trade = trade.copy()?;
+        let matched = match trade.get_item("order_id")?.filter(|id| !id.is_none()) {
+            Some(order_id) => post_only_by_order.get(&order_id.extract::<String>()?).copied(),
+            None => None,
+        };
+        match matched {
+            Some(post_only) => trade.set_item("post_only", post_only)?,
+            None if !trade.contains("post_only")? => trade.set_item("post_only", false)?,
+            None => {}
+        }
+        joined.append(trade)?;
+    }
+    Ok(joined)
+}
+
+/// Derives net position, average entry and realized PnL per product from
+/// fills only, for when the orders endpoint can't be trusted.
+#[pyfunction]
+fn compute_positions(py: Python<'_>, fills: &Bound<'_, PyAny>, product_id: &str) -> PyResult<PyObject> {
+    let json = py.import_bound("json")?;
+    let fills: Vec<RawFill> = parse_records(&json, fills, &FILL_ALIASES, "fills")?;
+
+    let positions = compute_fill_positions(&fills, product_id)?;
+    let rows = PyList::empty_bound(py);
+    for (product_id, summary) in positions {
+        let position = &summary.position;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("product_id", &product_id)?;
+        dict.set_item("product_type", ProductType::detect(&product_id, None).as_str())?;
+        dict.set_item("net_position", position.signed_size().to_string())?;
+        dict.set_item("direction", position.direction())?;
+        dict.set_item(
+            "average_entry_price",
+            position.average_entry_price().map(|d| d.to_string()),
+        )?;
+        dict.set_item("realized_profit", summary.realized_profit.to_string())?;
+        dict.set_item("commission_total", summary.commission_total.to_string())?;
+        dict.set_item("fill_count", summary.fill_count)?;
+        dict.set_item("skipped_fills", summary.skipped_fills)?;
+        rows.append(dict)?;
+    }
+    Ok(rows.into())
+}
+
+/// Fee rates may be written as strings or bare numbers; strings are
+/// preferred as TOML and YAML floats are binary.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FeeConfig {
+    maker_fee_rate: Option<Value>,
+    taker_fee_rate: Option<Value>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProductConfig {
+    maker_fee_rate: Option<Value>,
+    taker_fee_rate: Option<Value>,
+    #[serde(default)]
+    inverse: bool,
+}
+
+/// Declarative configuration shared by the bot and the CLI: interval specs,
+/// the default fee schedule and per-product settings.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    #[serde(default)]
+    intervals: Vec<IntervalSpec>,
+    #[serde(default)]
+    fees: FeeConfig,
+    #[serde(default)]
+    products: BTreeMap<String, ProductConfig>,
+}
+
+/// Validates an optional fee rate and returns its canonical decimal text.
+fn config_fee_rate(value: Option<&Value>, label: &str) -> PyResult<Option<String>> {
+    value
+        .map(|value| {
+            let text = value_to_string(value)
+                .ok_or_else(|| PyValueError::new_err(format!("invalid decimal for {}: {}", label, value)))?;
+            Ok(parse_decimal(text.trim(), label)?.normalize().to_string())
+        })
+        .transpose()
+}
+
+/// Parses config `text` as "toml", "yaml" or "json". YAML goes through
+/// PyYAML's `safe_load` and back out as JSON.
+fn parse_config(py: Python<'_>, text: &str, format: &str) -> PyResult<ConfigFile> {
+    let invalid = |err: &dyn std::fmt::Display| PyValueError::new_err(format!("invalid {} config: {}", format, err));
+    match format {
+        "toml" => toml::from_str(text).map_err(|err| invalid(&err)),
+        "yaml" => {
+            let yaml = py
+                .import_bound("yaml")
+                .map_err(|_| PyValueError::new_err("yaml configs need PyYAML installed"))?;
+            let loaded = yaml.call_method1("safe_load", (text,)).map_err(|err| invalid(&err))?;
+            let json: String = py
+                .import_bound("json")?
+                .call_method1("dumps", (loaded,))
+                .and_then(|json| json.extract())
+                .map_err(|err| invalid(&err))?;
+            serde_json::from_str(&json).map_err(|err| invalid(&err))
+        }
+        "json" => serde_json::from_str(text).map_err(|err| invalid(&err)),
+        other => Err(PyValueError::new_err(format!("unknown config format: {}", other))),
+    }
+}
+
+/// Loads a TOML, YAML (needs PyYAML) or JSON config file (format from `format` or the file
+/// extension) and returns ready-to-use specs: `intervals` for the summarise
+/// calls, the default `maker_fee_rate` / `taker_fee_rate`, `products` with
+/// each product's fees resolved against the defaults, and `inverse_products`.
+#[pyfunction]
+#[pyo3(signature = (path, *, format = None))]
+fn load_config(py: Python<'_>, path: &str, format: Option<&str>) -> PyResult<PyObject> {
+    let format = match format {
+        Some(format) => format.to_ascii_lowercase(),
+        None => match std::path::Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => "toml".to_string(),
+            Some("yaml" | "yml") => "yaml".to_string(),
+            Some("json") => "json".to_string(),
+            _ => return Err(PyValueError::new_err(format!("cannot infer config format of {}", path))),
+        },
+    };
+    let text = std::fs::read_to_string(path).map_err(|err| PyOSError::new_err(format!("failed to read {}: {}", path, err)))?;
+    let config = parse_config(py, &text, &format)?;
+
+    let intervals = PyList::empty_bound(py);
+    for interval in config.intervals {
+        if interval.delta_seconds.is_some_and(|delta| delta <= 0) {
+            return Err(PyValueError::new_err(format!("interval {} delta_seconds must be positive", interval.key)));
+        }
+        if interval.period_seconds.is_some_and(|period| period <= 0) {
+            return Err(PyValueError::new_err(format!("interval {} period_seconds must be positive", interval.key)));
+        }
+        if interval.delta_seconds.is_none() && interval.anchor_timestamp_us.is_none() && !interval.since_last_flat {
+            return Err(PyValueError::new_err(format!(
+                "interval {} needs delta_seconds or anchor_timestamp_us",
+                interval.key
+            )));
+        }
+        let dict = PyDict::new_bound(py);
+        dict.set_item("label", interval.label())?;
+        dict.set_item("key", &interval.key)?;
+        dict.set_item("delta_seconds", interval.delta_seconds)?;
+        dict.set_item("anchor_timestamp_us", interval.anchor_timestamp_us)?;
+        dict.set_item("period_seconds", interval.period_seconds)?;
+        dict.set_item("kind", interval.since_last_flat.then_some("since_last_flat"))?;
+        intervals.append(dict)?;
+    }
+
+    let maker_fee_rate = config_fee_rate(config.fees.maker_fee_rate.as_ref(), "maker_fee_rate")?;
+    let taker_fee_rate = config_fee_rate(config.fees.taker_fee_rate.as_ref(), "taker_fee_rate")?;
+    let products = PyDict::new_bound(py);
+    let mut inverse_products = Vec::new();
+    for (product_id, product) in &config.products {
+        let maker = config_fee_rate(product.maker_fee_rate.as_ref(), "maker_fee_rate")?;
+        let taker = config_fee_rate(product.taker_fee_rate.as_ref(), "taker_fee_rate")?;
+        let dict = PyDict::new_bound(py);
+        dict.set_item("maker_fee_rate", maker.or_else(|| maker_fee_rate.clone()))?;
+        dict.set_item("taker_fee_rate", taker.or_else(|| taker_fee_rate.clone()))?;
+        dict.set_item("inverse", product.inverse)?;
+        products.set_item(product_id, dict)?;
+        if product.inverse {
+            inverse_products.push(product_id.as_str());
+        }
     }
+
+    let result = PyDict::new_bound(py);
+    result.set_item("intervals", intervals)?;
+    result.set_item("maker_fee_rate", maker_fee_rate)?;
+    result.set_item("taker_fee_rate", taker_fee_rate)?;
+    result.set_item("products", products)?;
+    result.set_item("inverse_products", inverse_products)?;
+    Ok(result.into())
+}
+
+/// System allocator that, while `set_memory_debug` is on, counts the bytes
+/// the native code holds. Counters are process-wide, so concurrent calls
+/// show up in each other's figures; Python objects are not included. Only
+/// installed in builds with the `memory-debug` feature.
+#[cfg(feature = "memory-debug")]
+struct TrackingAllocator;
+
+static MEMORY_DEBUG: AtomicBool = AtomicBool::new(false);
+/// Signed because memory allocated before tracking began may be freed later.
+static MEMORY_CURRENT: AtomicIsize = AtomicIsize::new(0);
+static MEMORY_PEAK: AtomicIsize = AtomicIsize::new(0);
+static MEMORY_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "memory-debug")]
+impl TrackingAllocator {
+    fn record(delta: isize) {
+        if !MEMORY_DEBUG.load(Ordering::Relaxed) {
+            return;
+        }
+        let current = MEMORY_CURRENT.fetch_add(delta, Ordering::Relaxed) + delta;
+        MEMORY_PEAK.fetch_max(current, Ordering::Relaxed);
+        if delta > 0 {
+            MEMORY_ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(feature = "memory-debug")]
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            TrackingAllocator::record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            TrackingAllocator::record(layout.size() as isize);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        TrackingAllocator::record(-(layout.size() as isize));
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            TrackingAllocator::record(new_size as isize - layout.size() as isize);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(feature = "memory-debug")]
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+/// Turns native heap tracking on or off; counters restart from zero.
+/// Builds without the `memory-debug` feature cannot turn it on.
+#[pyfunction]
+fn set_memory_debug(enabled: bool) -> PyResult<()> {
+    if enabled && !cfg!(feature = "memory-debug") {
+        return Err(PyValueError::new_err("memory debug needs a build with the memory-debug feature"));
+    }
+    MEMORY_CURRENT.store(0, Ordering::Relaxed);
+    MEMORY_PEAK.store(0, Ordering::Relaxed);
+    MEMORY_ALLOCATIONS.store(0, Ordering::Relaxed);
+    MEMORY_DEBUG.store(enabled, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Native heap counters since tracking began: bytes currently held, the
+/// peak (reset to the current figure with `reset_peak`) and allocation count.
+/// `available` says whether the build has the `memory-debug` feature.
+#[pyfunction]
+#[pyo3(signature = (*, reset_peak = false))]
+fn memory_usage(py: Python<'_>, reset_peak: bool) -> PyResult<PyObject> {
+    let current = MEMORY_CURRENT.load(Ordering::Relaxed);
+    let result = PyDict::new_bound(py);
+    result.set_item("available", cfg!(feature = "memory-debug"))?;
+    result.set_item("enabled", MEMORY_DEBUG.load(Ordering::Relaxed))?;
+    result.set_item("current_bytes", current)?;
+    result.set_item("peak_bytes", MEMORY_PEAK.load(Ordering::Relaxed))?;
+    result.set_item("allocations", MEMORY_ALLOCATIONS.load(Ordering::Relaxed))?;
+    if reset_peak {
+        MEMORY_PEAK.store(current, Ordering::Relaxed);
+    }
+    Ok(result.into())
 }
 
 #[pymodule]
-fn _pnl_rs(py: Python<'_>, m: &PyModule) -> PyResult<()> {
+fn _pnl_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(summarise_trades, m)?)?;
     m.add_function(wrap_pyfunction!(process_orders_and_fills, m)?)?;
+    m.add_function(wrap_pyfunction!(mark_to_market, m)?)?;
+    m.add_function(wrap_pyfunction!(order_timeline, m)?)?;
+    m.add_function(wrap_pyfunction!(compute_positions, m)?)?;
+    m.add_function(wrap_pyfunction!(aggregate_portfolio, m)?)?;
+    m.add_function(wrap_pyfunction!(summarise_accounts, m)?)?;
+    m.add_function(wrap_pyfunction!(join_post_only, m)?)?;
+    m.add_function(wrap_pyfunction!(summarise_from_orders_and_fills, m)?)?;
+    m.add_function(wrap_pyfunction!(summarise_fills, m)?)?;
+    m.add_function(wrap_pyfunction!(register_field_aliases, m)?)?;
+    m.add_function(wrap_pyfunction!(register_quote_aliases, m)?)?;
+    m.add_function(wrap_pyfunction!(validate_payload, m)?)?;
+    m.add_function(wrap_pyfunction!(iter_orders_and_fills, m)?)?;
+    m.add_function(wrap_pyfunction!(simulate_stops, m)?)?;
+    m.add_function(wrap_pyfunction!(implementation_shortfall, m)?)?;
+    m.add_function(wrap_pyfunction!(quote_uptime, m)?)?;
+    m.add_function(wrap_pyfunction!(inventory_skew, m)?)?;
+    m.add_function(wrap_pyfunction!(load_config, m)?)?;
+    m.add_function(wrap_pyfunction!(set_input_limits, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate_payload, m)?)?;
+    m.add_function(wrap_pyfunction!(set_memory_debug, m)?)?;
+    m.add_function(wrap_pyfunction!(memory_usage, m)?)?;
+    m.add_function(wrap_pyfunction!(bootstrap_forward_pnl, m)?)?;
+    m.add_class::<SeenIdStore>()?;
+    m.add_class::<RecordStream>()?;
+    m.add_class::<SpreadAdvisor>()?;
+    m.add_class::<CancelToken>()?;
+    m.add("Cancelled", m.py().get_type_bound::<Cancelled>())?;
     // Ensure module has a __doc__ to aid debugging when import succeeds
     m.add("__doc__", "Rust-accelerated PnL helpers")?;
     Ok(())
@@ -0,0 +1,1044 @@
+from __future__ import annotations
+
+import asyncio
+from decimal import Decimal
+from pathlib import Path
+from typing import Any
+
+import pytest
+
+from app import pnl_native
+
+pytestmark = pytest.mark.skipif(not pnl_native.native_available(), reason="native extension not built")
+
+HOUR_US = 3_600_000_000
+DAY_US = 24 * HOUR_US
+BASE_US = 1_760_000_000_000_000
+ALL_TIME = [{"key": "all", "label": "All", "delta_seconds": None}]
+
+
+def _trade(ts: int, side: str, price: str, size: str, post_only: bool = False, **extra: Any) -> dict[str, Any]:
+    return {"timestamp_us": ts, "side": side, "price": price, "size": size, "post_only": post_only, **extra}
+
+
+def _eth(ts: int, side: str, price: str, size: str, post_only: bool = False) -> dict[str, Any]:
+    return _trade(ts, side, price, size, post_only, product_id="ETH-USD")
+
+
+def _summarise(trades: list[dict[str, Any]], **kwargs: Any) -> dict[str, Any]:
+    kwargs.setdefault("now_timestamp_us", BASE_US + 10 * DAY_US)
+    kwargs.setdefault("cutoff_timestamp_us", 0)
+    kwargs.setdefault("maker_fee_rate", "0.001")
+    kwargs.setdefault("taker_fee_rate", "0.002")
+    intervals = kwargs.pop("intervals", ALL_TIME)
+    result = pnl_native.summarise_trades(trades, intervals, **kwargs)
+    assert result is not None
+    return result
+
+
+def _order(order_id: str, status: str = "FILLED", side: str = "BUY", **extra: Any) -> dict[str, Any]:
+    config = extra.pop(
+        "order_configuration",
+        {"limit_limit_gtc": {"limit_price": "100", "base_size": "1", "post_only": True}},
+    )
+    return {"order_id": order_id, "status": status, "side": side, "order_configuration": config, **extra}
+
+
+def _fill(order_id: str, size: str = "1", price: str = "100", trade_time: str = "2025-10-01T00:00:00Z", **extra: Any) -> dict[str, Any]:
+    return {"order_id": order_id, "size": size, "price": price, "trade_time": trade_time, **extra}
+
+
+def _summarise_fills(fills: list[dict[str, Any]], **kwargs: Any) -> dict[str, Any]:
+    kwargs.setdefault("product_id", "ETH-USDC")
+    kwargs.setdefault("now_timestamp_us", BASE_US)
+    kwargs.setdefault("cutoff_timestamp_us", 0)
+    kwargs.setdefault("maker_fee_rate", "0")
+    kwargs.setdefault("taker_fee_rate", "0")
+    intervals = kwargs.pop("intervals", ALL_TIME)
+    result = pnl_native.summarise_fills(fills, intervals, **kwargs)
+    assert result is not None
+    return result
+
+
+def _process(orders: list[dict[str, Any]], fills: list[dict[str, Any]], **kwargs: Any) -> dict[str, Any]:
+    kwargs.setdefault("product_id", "ETH-USDC")
+    result = pnl_native.process_orders_and_fills(orders, fills, **kwargs)
+    assert result is not None
+    return result
+
+
+def test_fills_are_grouped_by_order_id() -> None:
+    result = _process(
+        [_order("a"), _order("b", status="OPEN")],
+        [
+            _fill("a", size="0.4", price="100"),
+            _fill("a", size="0.6", price="110", trade_time="2025-10-01T00:01:00Z"),
+            _fill("", size="5", price="1"),
+        ],
+    )
+
+    by_id = {record["order_id"]: record for record in result["executed_records"]}
+    assert by_id["a"]["filled_size"] == "1.0"
+    assert by_id["a"]["avg_fill_price"] == "106"
+    assert by_id["a"]["ts_filled"] == "2025-10-01T00:01:00.000Z"
+    assert [record["order_id"] for record in result["open_records"]] == ["b"]
+
+
+def test_fixed_point_matches_decimal_replay() -> None:
+    trades = [
+        _eth(BASE_US, "buy", "100.25", "1.5"),
+        _eth(BASE_US + HOUR_US, "sell", "110.5", "0.5", post_only=True),
+        _eth(BASE_US + 2 * HOUR_US, "sell", "99", "1"),
+    ]
+
+    decimal = _summarise(trades)["intervals"][0]
+    fixed = _summarise(trades, fixed_point_decimals=(2, 1))["intervals"][0]
+    by_product = _summarise(trades, fixed_point_decimals={"ETH-USD": (2, 1)})["intervals"][0]
+
+    for key in ("profit_before_fees", "profit_after_fees", "fee_total", "maker_volume", "taker_volume", "avg_exposure_notional"):
+        assert Decimal(fixed[key]) == Decimal(decimal[key]), key
+    assert fixed == by_product
+
+
+def test_fixed_point_rejects_unknown_product_and_overflow() -> None:
+    trades = [_eth(BASE_US, "buy", "100", "1")]
+    with pytest.raises(ValueError, match="no fixed_point_decimals for product ETH-USD"):
+        _summarise(trades, fixed_point_decimals={"BTC-USD": (2, 2)})
+
+    huge = [_trade(BASE_US, "buy", "1" + "0" * 20, "1" + "0" * 15), _trade(BASE_US + 1, "sell", "1" + "0" * 20, "1" + "0" * 15)]
+    with pytest.raises(ValueError, match="overflow"):
+        _summarise(huge, fixed_point_decimals=(6, 6))
+
+    held = [_trade(BASE_US - 400 * DAY_US, "buy", "1", "1" + "0" * 20), _trade(BASE_US, "sell", "1", "1" + "0" * 20)]
+    with pytest.raises(ValueError, match="overflow"):
+        _summarise(held)
+
+
+def test_mark_to_market_charges_fees_like_summarise_trades() -> None:
+    trades = [_eth(BASE_US, "BUY", "100", "1"), _trade(BASE_US + HOUR_US, "SELL", "110", "1", product_id="ETH-USD", commission="0.5")]
+    snapshots = [
+        {"timestamp_us": BASE_US + DAY_US, "mark_price": "120"},
+        {"timestamp_us": BASE_US + HOUR_US // 2, "mark_price": "105"},
+    ]
+    closed, opened = pnl_native.mark_to_market(trades, snapshots, maker_fee_rate="0.001", taker_fee_rate="0.002", min_fee="1")
+    assert (opened["position_size"], opened["unrealized_profit"], opened["fee_total"]) == ("1", "5", "1")
+    assert (closed["position_size"], closed["realized_profit"], closed["fee_total"]) == ("0", "10", "1.5")
+    assert Decimal(_summarise(trades, min_fee="1")["intervals"][0]["fee_total"]) == Decimal("1.5")
+
+    with pytest.raises(ValueError, match="several products"):
+        pnl_native.mark_to_market(
+            trades + [_trade(BASE_US, "BUY", "10", "1", product_id="SOL-USD")],
+            snapshots,
+            maker_fee_rate="0",
+            taker_fee_rate="0",
+        )
+
+
+def test_out_of_range_interval_starts_raise() -> None:
+    trades = [_trade(BASE_US, "buy", "100", "1")]
+    huge_delta = [{"key": "d", "label": "D", "delta_seconds": 10**17}]
+    with pytest.raises(ValueError, match="interval d starts out of range"):
+        _summarise(trades, intervals=huge_delta)
+
+    huge_period = [{"key": "p", "label": "P", "delta_seconds": None, "anchor_timestamp_us": BASE_US + DAY_US, "period_seconds": 9 * 10**12}]
+    with pytest.raises(ValueError, match="interval p starts out of range"):
+        _summarise(trades, intervals=huge_period, now_timestamp_us=BASE_US)
+
+
+def test_quote_aliases_map_perp_settlement_currency() -> None:
+    trades = [_trade(BASE_US, "buy", "100", "1", product_id="BTC-PERP-INTX")]
+    assert _summarise(trades)["quote_currency"] == "USDC"
+
+    assert pnl_native.register_quote_aliases({"PERP": "USDT"})
+    try:
+        assert _summarise(trades)["quote_currency"] == "USDT"
+    finally:
+        pnl_native.register_quote_aliases({}, replace=True)
+    assert _summarise(trades)["quote_currency"] == "USDC"
+
+
+def test_notional_and_margin_use_filled_size_only() -> None:
+    result = _process(
+        [_order("a", leverage="5"), _order("b", status="CANCELLED", leverage="5")],
+        [_fill("a", size="0.5", price="100")],
+    )
+
+    by_id = {record["order_id"]: record for record in result["executed_records"]}
+    assert (by_id["a"]["notional"], by_id["a"]["margin_used"]) == ("50", "10")
+    assert (by_id["b"]["notional"], by_id["b"]["margin_used"]) == ("0", "0")
+
+
+def test_funding_settles_on_the_booked_position() -> None:
+    trades = [_trade(BASE_US, "BUY", "100", "1"), _trade(BASE_US + HOUR_US, "SELL", "100", "3")]
+    funding = [
+        {"timestamp_us": BASE_US + HOUR_US // 2, "rate": "0.001", "mark_price": "100"},
+        {"timestamp_us": BASE_US + 2 * HOUR_US, "rate": "0.001", "mark_price": "100"},
+    ]
+    clamped = _summarise(trades, funding_rates=funding, oversell="clamp_to_inventory")
+    assert Decimal(clamped["intervals"][0]["funding_pnl"]) == Decimal("-0.1")
+    short = _summarise(trades, funding_rates=funding)
+    assert Decimal(short["intervals"][0]["funding_pnl"]) == Decimal("0.1")
+
+    mixed = [_eth(BASE_US, "BUY", "100", "1"), _trade(BASE_US + 1, "BUY", "100", "1", product_id="BTC-USD")]
+    with pytest.raises(ValueError, match="funding_rates needs trades of a single product"):
+        _summarise(mixed, funding_rates=funding, mixed_products="allow")
+
+
+def test_borrow_rates_accrue_per_rate_segment() -> None:
+    rates = [
+        {"timestamp_us": BASE_US, "annual_rate": "0.365"},
+        {"timestamp_us": BASE_US + 5 * DAY_US, "annual_rate": "0.73"},
+    ]
+    intervals = ALL_TIME + [{"key": "3d", "label": "3d", "delta_seconds": 3 * 86_400}]
+    short = [_trade(BASE_US, "sell", "100", "1")]
+
+    held = _summarise(short, intervals=intervals, borrow_rates=rates)["intervals"]
+    assert [Decimal(interval["borrow_cost"]) for interval in held] == [Decimal("1.5"), Decimal("0.6")]
+
+    covered = _summarise(short + [_trade(BASE_US + 7 * DAY_US, "buy", "100", "1")], intervals=intervals, borrow_rates=rates)["intervals"]
+    assert [Decimal(interval["borrow_cost"]) for interval in covered] == [Decimal("0.9"), Decimal("0")]
+
+    since_epoch = [{"timestamp_us": 0, "annual_rate": "0.365"}]
+    result = _summarise(short, borrow_rates=since_epoch, now_timestamp_us=BASE_US + 1000 * 365 * DAY_US)
+    assert Decimal(result["intervals"][0]["borrow_cost"]) == Decimal("0.1") * 365_000
+
+    oversold = [_trade(BASE_US - DAY_US, "buy", "100", "1"), _trade(BASE_US, "sell", "100", "2")]
+    clamped = _summarise(oversold, borrow_rates=rates, oversell="clamp_to_inventory")
+    assert Decimal(clamped["intervals"][0]["borrow_cost"]) == 0
+
+
+def test_aggregate_portfolio_reports_interval_metrics() -> None:
+    def product(product_id: str, sell_price: str) -> dict[str, Any]:
+        return {
+            "product_id": product_id,
+            "maker_fee_rate": "0",
+            "taker_fee_rate": "0",
+            "trades": [_trade(BASE_US, "buy", "100", "1"), _trade(BASE_US + HOUR_US, "sell", sell_price, "1")],
+        }
+
+    kwargs: dict[str, Any] = {
+        "now_timestamp_us": BASE_US + DAY_US,
+        "cutoff_timestamp_us": 0,
+        "conversion_prices": {"EUR": "2"},
+    }
+    products = [product("ETH-USD", "110"), product("ETH-EUR", "105")]
+    result = pnl_native.aggregate_portfolio(products, ALL_TIME, **kwargs)
+    assert result is not None
+    interval = result["intervals"][0]
+    assert Decimal(interval["profit_before_fees"]) == Decimal(20)
+    assert result["total_profit_before_fees"] == interval["profit_before_fees"]
+    assert Decimal(interval["avg_exposure_notional"]) > 0
+    assert "avg_buy_price" not in interval and "max_drawdown" not in interval
+    usd, eur = result["products"]
+    assert (usd["intervals"][0]["avg_sell_price"], eur["intervals"][0]["avg_sell_price"]) == ("110", "105")
+    assert eur["intervals"][0]["profit_before_fees"] == "5"
+
+    floats = pnl_native.aggregate_portfolio(products, ALL_TIME, float_output=True, **kwargs)
+    assert floats is not None
+    assert floats["intervals"][0]["profit_before_fees"] == 20.0
+
+
+def test_comparison_offset_out_of_range_raises() -> None:
+    trades = [_trade(BASE_US, "buy", "100", "1")]
+    comparisons = [{"key": "far", "interval_key": "all", "offset_seconds": 10**17}]
+    with pytest.raises(ValueError, match="comparison far offset out of range"):
+        _summarise(trades, comparisons=comparisons)
+
+
+def test_splits_rename_products_and_compose_ratios() -> None:
+    splits = [
+        {"timestamp_us": BASE_US + HOUR_US, "ratio": "3/2", "product_id": "OLD-USD", "new_product_id": "NEW-USD"},
+        {"timestamp_us": BASE_US + 2 * HOUR_US, "ratio": "2", "product_id": "NEW-USD"},
+    ]
+    trades = [
+        _trade(BASE_US, "buy", "100", "1", product_id="OLD-USD"),
+        _trade(BASE_US + 3 * HOUR_US, "sell", "40", "3", product_id="NEW-USD"),
+    ]
+
+    result = _summarise(trades, splits=splits)
+    assert result["quote_currency"] == "USD"
+    assert Decimal(result["intervals"][0]["profit_before_fees"]).quantize(Decimal("1e-12")) == Decimal(20)
+
+    with pytest.raises(ValueError, match="not supported with fixed_point_decimals"):
+        _summarise(trades, splits=splits, fixed_point_decimals=(2, 2))
+    with pytest.raises(ValueError, match="needs a product_id"):
+        _summarise(trades, splits=[{"timestamp_us": BASE_US + HOUR_US, "ratio": "2", "new_product_id": "NEW-USD"}])
+
+
+def test_commission_currency_without_product_is_netted() -> None:
+    def trades(**extra: Any) -> list[dict[str, Any]]:
+        return [
+            _trade(BASE_US, "buy", "100", "1", commission="1", commission_currency="USD", **extra),
+            _trade(BASE_US + HOUR_US, "sell", "110", "1", commission="1", commission_currency="USD", **extra),
+        ]
+
+    unknown = _summarise(trades())["intervals"][0]
+    assert Decimal(unknown["profit_after_fees"]) == Decimal(8)
+
+    foreign = _summarise(trades(product_id="ETH-EUR"))["intervals"][0]
+    assert Decimal(foreign["profit_after_fees"]) == Decimal(10)
+    assert Decimal(foreign["fees_by_currency"]["USD"]) == Decimal(2)
+
+
+def test_duplicate_orders_count_their_fills_once() -> None:
+    result = pnl_native.summarise_from_orders_and_fills(
+        [_order("a"), _order("a"), _order("b", side="SELL")],
+        [_fill("a", price="100"), _fill("b", price="110", trade_time="2025-10-01T01:00:00Z")],
+        ALL_TIME,
+        product_id="ETH-USDC",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0",
+        taker_fee_rate="0",
+    )
+    assert result is not None
+    interval = result["intervals"][0]
+    assert (interval["buy_count"], interval["sell_count"]) == (1, 1)
+    assert Decimal(interval["profit_before_fees"]) == Decimal(10)
+
+
+def test_order_pipeline_takes_maker_status_from_each_fill() -> None:
+    fills = [_fill("a", liquidity_indicator="TAKER"), _fill("a", trade_time="2025-10-01T00:01:00Z")]
+    result = pnl_native.summarise_from_orders_and_fills(
+        [_order("a")],
+        fills,
+        ALL_TIME,
+        product_id="ETH-USDC",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0.001",
+        taker_fee_rate="0.002",
+    )
+    assert result is not None
+    interval = result["intervals"][0]
+    assert (Decimal(interval["maker_volume"]), Decimal(interval["taker_volume"])) == (Decimal(100), Decimal(100))
+    assert Decimal(interval["fee_total"]) == Decimal("0.3")
+
+
+def test_fills_before_cutoff_are_counted_as_skipped() -> None:
+    fills = [
+        _fill("a", side="BUY", trade_time="2025-09-01T00:00:00Z"),
+        _fill("b", side="BUY", trade_time="2025-10-01T00:00:00Z"),
+        _fill("c", trade_time="2025-10-01T00:00:00Z"),
+    ]
+    cutoff_us = 1_759_000_000_000_000  # 2025-09-27
+    result = _summarise_fills(fills, cutoff_timestamp_us=cutoff_us)
+    assert result["skipped_fills"] == 2
+    assert result["intervals"][0]["buy_count"] == 1
+
+
+def test_validate_payload_follows_parser_rules() -> None:
+    order = _order("a", client_order_id="N/A", average_filled_price="null")
+    del order["side"]
+    fill = {"size": "1", "price": "100", "commission": "None", "trade_time": "N/A"}
+    report = pnl_native.validate_payload([order], [fill])
+    assert report is not None
+    assert report["valid"], report
+
+    broken = pnl_native.validate_payload([{"order_id": "b"}], [{"order_id": "b", "size": "x", "price": "100"}])
+    assert broken is not None
+    assert [issue["field"] for issue in broken["orders"]["issues"]] == ["order_configuration"]
+    assert [(issue["kind"], issue["field"]) for issue in broken["fills"]["issues"]] == [("type_mismatch", "size")]
+
+
+def test_payload_shapes_unwrap_one_level() -> None:
+    fills = [_fill("a")]
+    for orders in (
+        {"orders": [{"order": _order("a")}]},
+        [{"orders": [_order("a")]}],
+        iter([[{"order": _order("a")}]]),
+    ):
+        result = _process(orders, fills)
+        assert [record["order_id"] for record in result["executed_records"]] == ["a"]
+
+    with pytest.raises(ValueError, match="not further lists"):
+        _process([[[_order("a")]]], fills)
+
+
+def test_estimate_payload_counts_the_shapes_processing_accepts() -> None:
+    for orders in (
+        {"orders": [{"order": _order("a")}, _order("b")]},
+        [{"orders": [_order("a")]}, [_order("b")]],
+        iter([_order("a"), {"order": _order("b")}]),
+    ):
+        estimate = pnl_native.estimate_payload(orders=orders)
+        assert estimate is not None
+        assert estimate["orders"] == 2
+
+    with pytest.raises(TypeError, match="pass a list, tuple or dict"):
+        pnl_native._estimate_payload(orders=iter([_order("a")]))
+
+
+def test_field_aliases_do_not_modify_caller_records() -> None:
+    order = _order("a")
+    order["id"] = order.pop("order_id")
+    try:
+        assert pnl_native.register_field_aliases("order", {"id": "order_id"})
+        result = _process([order], [_fill("a")])
+    finally:
+        pnl_native.register_field_aliases("order", {}, replace=True)
+    assert [record["order_id"] for record in result["executed_records"]] == ["a"]
+    assert "order_id" not in order
+
+
+def test_limit_price_fallback_only_for_stop_and_bracket_orders() -> None:
+    stop = {"stop_limit_stop_limit_gtc": {"base_size": "1", "stop_price": "95"}}
+    plain = {"limit_limit_gtc": {"base_size": "1"}}
+    result = _process(
+        [_order("s", order_configuration=stop), _order("l", order_configuration=plain)],
+        [_fill("s", price="94"), _fill("l", price="101")],
+    )
+
+    by_id = {record["order_id"]: record for record in result["executed_records"]}
+    assert by_id["s"]["limit_price"] == "94"
+    assert by_id["l"]["limit_price"] == "0"
+
+
+def test_seen_id_store_round_trip(tmp_path: Path) -> None:
+    path = tmp_path / "seen.json"
+    store = pnl_native.SeenIdStore(str(path), flush_every=10)
+    result = _process([_order("a"), _order("b")], [_fill("a"), _fill("b")], seen_ids=store)
+    assert result["already_seen"] == 0
+    assert not path.exists()
+
+    store.save()
+    assert not (tmp_path / "seen.json.tmp").exists()
+    reloaded = pnl_native.SeenIdStore(str(path))
+    assert reloaded.has_order("a") and reloaded.has_order("b") and len(reloaded) == 2
+    assert pnl_native.SeenIdStore.loads(store.dumps()).has_order("a")
+
+    again = _process([_order("a"), _order("c")], [_fill("a"), _fill("c")], seen_ids=reloaded)
+    assert [record["order_id"] for record in again["executed_records"]] == ["c"]
+    assert again["already_seen"] == 1
+
+
+def test_seen_fills_stay_in_lot_matching() -> None:
+    store = pnl_native.SeenIdStore()
+    opening = _fill("a", side="BUY", price="100", trade_id="t1")
+    first = _summarise_fills([opening, dict(opening)], seen_ids=store)
+    assert first["already_seen"] == 1
+    assert first["intervals"][0]["buy_count"] == 1
+
+    closing = _fill("b", side="SELL", price="110", trade_time="2025-10-01T01:00:00Z", trade_id="t2")
+    second = _summarise_fills([opening, closing], seen_ids=store)
+    interval = second["intervals"][0]
+    assert second["already_seen"] == 1
+    assert (interval["buy_count"], interval["sell_count"]) == (0, 1)
+    assert Decimal(interval["profit_before_fees"]) == Decimal(10)
+
+
+def test_record_stream_dedupes_and_tracks_seen_ids() -> None:
+    orders = [_order("a", status="OPEN"), _order("b"), _order("a", status="FILLED"), _order("c")]
+    fills = [_fill("a"), _fill("b"), _fill("c")]
+    stream = pnl_native.iter_orders_and_fills(orders, fills, product_id="ETH-USDC", chunk_size=2, duplicate_orders="keep_latest")
+    assert stream is not None
+    chunks = list(stream)
+    assert stream.merged_orders == 1
+    assert [[record["order_id"] for record in chunk["executed_records"]] for chunk in chunks] == [["a", "b"], ["c"]]
+    assert all(not chunk["open_records"] for chunk in chunks)
+    with pytest.raises(ValueError, match="duplicate order_id"):
+        pnl_native.iter_orders_and_fills(orders, fills, product_id="ETH-USDC", duplicate_orders="error")
+
+    store = pnl_native.SeenIdStore()
+    _process([_order("b")], [_fill("b")], seen_ids=store)
+    stream = pnl_native.iter_orders_and_fills(orders[1:], fills, product_id="ETH-USDC", chunk_size=2, seen_ids=store)
+    assert stream is not None
+    chunks = list(stream)
+    assert [chunk["already_seen"] for chunk in chunks] == [1, 0]
+    assert [record["order_id"] for chunk in chunks for record in chunk["executed_records"]] == ["a", "c"]
+    assert store.has_order("c")
+
+
+def test_skipped_trades_are_not_validated() -> None:
+    trades = [
+        _eth(BASE_US, "BUY", "100", "1"),
+        _trade(BASE_US + 1, "HOLD", "n/a", "1", product_id="BTC-USD"),
+        _eth(BASE_US + 2, "HOLD", "n/a", "0"),
+    ]
+    result = _summarise(trades, include_products=["ETH-USD"])
+    assert result["intervals"][0]["buy_count"] == 1
+
+    with pytest.raises(ValueError, match="side"):
+        _summarise([_eth(BASE_US, "HOLD", "100", "1")])
+
+
+def test_oco_winner_prefers_legs_with_a_fill_time() -> None:
+    orders = [
+        _order("a", oco_group_id="g"),
+        _order("b", oco_group_id="g", completed_time="2025-10-01T00:05:00Z"),
+        _order("c", status="OPEN", oco_group_id="g"),
+    ]
+    fills = [_fill("a", trade_time=None), _fill("b")]
+    result = _process(orders, fills, completed_time_sources=["completed_time"])
+    cancelled = {record["order_id"]: record["oco_cancelled_by"] for record in result["executed_records"]}
+    assert cancelled["c"] == "b"
+    assert not result["open_records"]
+
+
+def test_bracket_exits_close_entries_from_before_the_cutoff() -> None:
+    orders = [
+        _order("entry"),
+        _order("take_profit", side="SELL", originating_order_id="entry"),
+        _order("stop_loss", status="CANCELLED", side="SELL", originating_order_id="entry"),
+    ]
+    fills = [_fill("entry", price="100"), _fill("take_profit", price="110", trade_time="2025-10-02T00:00:00Z")]
+    result = pnl_native.summarise_from_orders_and_fills(
+        orders,
+        fills,
+        ALL_TIME,
+        product_id="ETH-USDC",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=1_759_320_000_000_000,
+        maker_fee_rate="0",
+        taker_fee_rate="0",
+        include_bracket_groups=True,
+    )
+    assert result is not None
+    [group] = result["bracket_groups"]
+    assert group["parent_order_id"] == "entry"
+    assert Decimal(group["entry_size"]) == Decimal(1) and Decimal(group["open_size"]) == 0
+    assert Decimal(group["realized_profit"]) == Decimal(10)
+
+
+def test_implementation_shortfall_rolls_legs_up_to_their_parent() -> None:
+    orders = [
+        _order("entry"),
+        _order("take_profit", side="SELL", originating_order_id="entry"),
+        _order("stop_loss", status="OPEN", side="SELL", originating_order_id="entry"),
+        _order("solo"),
+    ]
+    fills = [
+        _fill("entry", price="100", liquidity_indicator="TAKER"),
+        _fill("take_profit", price="110", trade_time="2025-10-02T00:00:00Z", commission="0.05"),
+        _fill("solo", price="101"),
+    ]
+    rows = pnl_native.implementation_shortfall(
+        orders,
+        fills,
+        product_id="ETH-USDC",
+        maker_fee_rate="0.001",
+        taker_fee_rate="0.002",
+        arrival_prices={"entry": "99", "solo": "100"},
+    )
+    assert rows is not None
+    bracket, solo = rows
+    assert (bracket["parent_order_id"], bracket["order_ids"]) == ("entry", ["entry", "take_profit", "stop_loss"])
+    assert (Decimal(bracket["fees"]), Decimal(bracket["trading_cost"])) == (Decimal("0.25"), Decimal("-9.75"))
+    assert Decimal(bracket["unfilled_size"]) == 0 and Decimal(bracket["opportunity_cost"]) == 0
+    assert (solo["order_ids"], Decimal(solo["fees"])) == (["solo"], Decimal("0.101"))
+
+
+def test_price_improvement_follows_fill_liquidity() -> None:
+    orders = [
+        _order("p"),
+        _order("m", order_configuration={"market_market_ioc": {"base_size": "1"}}),
+        _order("k"),
+    ]
+    fills = [
+        _fill("p", price="99", liquidity_indicator="TAKER"),
+        _fill("m"),
+        _fill("k", liquidity_indicator="MAKER"),
+    ]
+    result = pnl_native.summarise_from_orders_and_fills(
+        orders,
+        fills,
+        ALL_TIME,
+        product_id="ETH-USDC",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0",
+        taker_fee_rate="0",
+    )
+    assert result is not None
+    [improvement] = result["execution_quality"][0]["price_improvement"]
+    assert (improvement["taker_orders"], improvement["improved_orders"]) == (1, 1)
+    assert improvement["missing_arrival_orders"] == 1
+    assert Decimal(improvement["improvement"]) == Decimal(1)
+
+
+def test_quote_uptime_tracks_edits_and_mid_changes() -> None:
+    def quote(price: str) -> dict[str, Any]:
+        return {"limit_limit_gtc": {"limit_price": price, "base_size": "1", "post_only": True}}
+
+    orders = [
+        _order("bid", status="OPEN", order_configuration=quote("99.9"), created_time="2025-10-09T06:53:20Z"),
+        _order(
+            "ask",
+            status="OPEN",
+            side="SELL",
+            order_configuration=quote("100.1"),
+            created_time="2025-10-09T07:53:20Z",
+            edit_history=[{"price": "105", "replace_accept_timestamp": "2025-10-09T08:23:20Z"}],
+        ),
+    ]
+    mids = [{"timestamp_us": BASE_US - 2 * HOUR_US, "price": "100"}, {"timestamp_us": BASE_US - HOUR_US // 4, "price": "104.9"}]
+    rows = pnl_native.quote_uptime(
+        orders,
+        [],
+        mids,
+        [{"key": "2h", "label": "2h", "delta_seconds": 7200}, {"key": "1h", "label": "1h", "delta_seconds": 3600}],
+        product_id="ETH-USDC",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=0,
+    )
+    assert rows is not None
+    assert [row["two_sided_seconds"] for row in rows] == [1800, 1800]
+    assert [row["uptime_pct"] for row in rows] == ["25", "50"]
+    assert rows[0]["bid_uptime_pct"] == "87.5"
+
+    with pytest.raises(ValueError, match="max_distance_bps must not be negative"):
+        pnl_native.quote_uptime(
+            orders,
+            [],
+            mids,
+            ALL_TIME,
+            product_id="ETH-USDC",
+            now_timestamp_us=BASE_US,
+            cutoff_timestamp_us=0,
+            max_distance_bps="-100",
+        )
+
+
+def test_inventory_skew_replays_one_product_under_the_oversell_policy() -> None:
+    trades = [_eth(BASE_US, "BUY", "100", "2"), _eth(BASE_US + 1, "SELL", "100", "3")]
+    trades.append(_trade(BASE_US + 2, "BUY", "50000", "1", product_id="BTC-USD"))
+    with pytest.raises(ValueError, match="several products"):
+        pnl_native.inventory_skew(trades, max_position="4")
+
+    short = pnl_native.inventory_skew(trades, max_position="4", product_id="ETH-USD")
+    assert (short["position"], short["inventory_ratio"]) == ("-1", "-0.25")
+    clamped = pnl_native.inventory_skew(trades, max_position="4", product_id="ETH-USD", oversell="clamp_to_inventory")
+    assert (clamped["position"], clamped["direction"]) == ("0", "flat")
+
+    inverse = [_trade(BASE_US, "BUY", "100", "10", product_id="BTC-USD-INV")]
+    long = pnl_native.inventory_skew(inverse, max_position="20", inverse_products=["BTC-USD-INV"])
+    assert (long["direction"], long["inventory_ratio"]) == ("long", "0.5")
+
+
+def test_spread_advisor_reports_decimals_and_rejects_stale_trades() -> None:
+    advisor = pnl_native.SpreadAdvisor("0.001", volatility_multiplier="2")
+    advisor.update([_eth(BASE_US + HOUR_US, "BUY", "100", "1"), _eth(BASE_US, "SELL", "100", "1")])
+    suggestion = advisor.suggest("ETH-USD")
+    assert suggestion["trade_count"] == 2
+    assert (suggestion["fee_bps"], suggestion["volatility_bps"]) == ("10", "0")
+    assert (suggestion["min_spread_bps"], suggestion["min_spread"]) == ("20", "0.2")
+
+    with pytest.raises(ValueError, match="older than the last trade"):
+        advisor.update([_eth(BASE_US + 2 * HOUR_US, "BUY", "101", "1"), _eth(BASE_US, "BUY", "99", "1")])
+    assert advisor.suggest("ETH-USD")["trade_count"] == 2
+
+    floats = pnl_native.SpreadAdvisor("0.001", float_output=True)
+    floats.update([_eth(BASE_US, "BUY", "100", "1")])
+    assert floats.suggest("ETH-USD")["min_spread"] == pytest.approx(0.2)
+
+
+def test_pnl_decomposition_uses_booked_positions() -> None:
+    mids = [{"timestamp_us": BASE_US, "price": "105"}]
+    trades = [_eth(BASE_US, "BUY", "100", "1"), _eth(BASE_US + HOUR_US, "SELL", "110", "2")]
+    clamped = _summarise(trades, mid_prices=mids, oversell="clamp_to_inventory")["pnl_decomposition"][0]
+    assert (Decimal(clamped["spread_capture"]), Decimal(clamped["inventory_pnl"])) == (Decimal(10), Decimal(0))
+    short = _summarise(trades, mid_prices=mids)["pnl_decomposition"][0]
+    assert Decimal(short["spread_capture"]) == Decimal(15)
+
+    inverse = _summarise(
+        [_trade(BASE_US, "BUY", "100", "100", product_id="BTC-USD-INV")],
+        mid_prices=[{"timestamp_us": BASE_US, "price": "100"}, {"timestamp_us": BASE_US + DAY_US, "price": "125"}],
+        inverse_products=["BTC-USD-INV"],
+    )["pnl_decomposition"][0]
+    assert Decimal(inverse["inventory_pnl"]) == Decimal("0.2")
+    assert Decimal(inverse["spread_capture"]) == 0
+
+    with pytest.raises(ValueError, match="single product"):
+        _summarise(trades + [_trade(BASE_US, "BUY", "10", "1", product_id="SOL-USD")], mid_prices=mids, mixed_products="allow")
+
+
+def test_fill_paths_warn_on_mixed_products_by_default() -> None:
+    fills = [_fill("a", side="BUY", product_id="ETH-USDC"), _fill("b", side="SELL", product_id="BTC-USDC")]
+    with pytest.warns(UserWarning, match="several products"):
+        result = _summarise_fills(fills)
+    assert result["intervals"][0]["buy_count"] == 1
+    with pytest.raises(ValueError, match="several products"):
+        _summarise_fills(fills, mixed_products="error")
+
+
+def test_fill_paths_accept_inverse_products() -> None:
+    fills = [
+        _fill("a", size="100", price="100", side="BUY", product_id="BTC-USD-INV"),
+        _fill("b", size="100", price="125", side="SELL", product_id="BTC-USD-INV", trade_time="2025-10-02T00:00:00Z"),
+    ]
+    result = _summarise_fills(fills, inverse_products=["BTC-USD-INV"])
+    assert Decimal(result["intervals"][0]["profit_before_fees"]) == Decimal("0.2")
+
+    orders = [_order("a", product_id="BTC-USD-INV"), _order("b", side="SELL", product_id="BTC-USD-INV")]
+    result = pnl_native.summarise_from_orders_and_fills(
+        orders,
+        fills,
+        ALL_TIME,
+        product_id="BTC-USD-INV",
+        now_timestamp_us=BASE_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0",
+        taker_fee_rate="0",
+        inverse_products=["BTC-USD-INV"],
+    )
+    assert result is not None
+    assert Decimal(result["intervals"][0]["profit_before_fees"]) == Decimal("0.2")
+
+
+def test_oversell_policies_apply_across_entry_points() -> None:
+    trades = [_eth(BASE_US, "BUY", "100", "1"), _eth(BASE_US + HOUR_US, "SELL", "110", "2")]
+    short = _summarise(trades)
+    assert (short["open_position"]["direction"], short["oversold_count"]) == ("short", 1)
+    clamped = _summarise(trades, oversell="clamp_to_inventory")
+    assert (clamped["open_position"]["direction"], clamped["oversold_count"]) == ("flat", 1)
+    assert Decimal(clamped["intervals"][0]["profit_before_fees"]) == Decimal(10)
+    with pytest.raises(ValueError, match="exceeds held inventory"):
+        _summarise(trades, oversell="error")
+
+    snapshots = [{"timestamp_us": BASE_US + DAY_US, "mark_price": "120"}]
+    [row] = pnl_native.mark_to_market(trades, snapshots, maker_fee_rate="0", taker_fee_rate="0", oversell="clamp_to_inventory")
+    assert (row["position_size"], row["oversold_count"]) == ("0", 1)
+    with pytest.raises(ValueError, match="exceeds held inventory"):
+        pnl_native.mark_to_market(trades, snapshots, maker_fee_rate="0", taker_fee_rate="0", oversell="error")
+
+    accounts = pnl_native.summarise_accounts(
+        {"spot": trades},
+        ALL_TIME,
+        now_timestamp_us=BASE_US + DAY_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0",
+        taker_fee_rate="0",
+        oversell="clamp_to_inventory",
+    )
+    assert accounts is not None
+    assert (accounts["spot"]["open_position"]["direction"], accounts["spot"]["oversold_count"]) == ("flat", 1)
+
+    legs = [_trade(BASE_US, "SELL", "100", "1", product_id="ETH-USD", leg_group="cycle")]
+    [group] = _summarise(legs, oversell="clamp_to_inventory")["leg_groups"]
+    assert group["oversold_count"] == 1
+    assert Decimal(group["net_flows"]["ETH"]) == 0 and Decimal(group["net_flows"]["USD"]) == 0
+
+
+def test_raw_timestamps_include_end_time() -> None:
+    gtd = {"limit_limit_gtd": {"limit_price": "100", "base_size": "1", "end_time": "2030-01-01T09:00:00+09:00"}}
+    orders = [
+        _order("a", status="OPEN", order_configuration=gtd),
+        _order("b", created_time="2025-10-01T02:00:00+02:00"),
+    ]
+    result = _process(orders, [_fill("b")], include_raw_timestamps=True)
+    [open_record] = result["open_records"]
+    assert open_record["end_time_raw"] == "2030-01-01T09:00:00+09:00"
+    assert open_record["end_time"].startswith("2030-01-01T00:00:00")
+    executed = {record["order_id"]: record for record in result["executed_records"]}
+    assert executed["a"]["end_time_raw"] == "2030-01-01T09:00:00+09:00"
+    assert executed["b"]["end_time_raw"] == "2025-10-01T02:00:00+02:00"
+    assert "end_time_raw" not in _process(orders, [_fill("b")])["open_records"][0]
+
+
+def test_epochs_are_read_only_from_numeric_fields_within_range() -> None:
+    orders = [
+        _order("numeric", created_time=1_759_300_000),
+        _order("text", created_time="1759300000"),
+        _order("zero", created_time=0),
+        _order("far", created_time=9_999_999_999),
+    ]
+    fills = [_fill(order["order_id"], trade_time="2025-10-02T00:00:00Z") for order in orders]
+    result = _process(orders, fills)
+    records = {record["order_id"]: record for record in result["executed_records"]}
+    assert records["numeric"]["ts_submitted"] == "2025-10-01T06:26:40.000Z"
+    assert not records["numeric"]["ts_submitted_inferred"]
+    for order_id in ("text", "zero", "far"):
+        assert records[order_id]["ts_submitted"] == "2025-10-02T00:00:00.000Z", order_id
+
+    report = pnl_native.validate_payload([orders[0], orders[1]], fills[:2])
+    assert report is not None
+    assert [(issue["record_id"], issue["field"]) for issue in report["orders"]["issues"]] == [("text", "created_time")]
+
+
+def test_columnar_output_keeps_columns_without_rows() -> None:
+    empty = _process([], [], columnar=True, fields=["order_id", "status", "ts_filled_raw"])
+    assert empty["open_records"] == {"order_id": [], "status": []}
+    assert empty["executed_records"] == {"order_id": [], "status": []}
+
+    result = _process([_order("a", status="OPEN")], [], columnar=True, include_raw_timestamps=True)
+    assert result["open_records"]["order_id"] == ["a"]
+    assert set(result["executed_records"]) >= {"ts_submitted_raw", "end_time_raw", "arrival_slippage_bps"}
+    assert all(len(values) == 1 for values in result["executed_records"].values())
+    assert set(_process([], [], columnar=True)["executed_records"]) == set(result["executed_records"]) - {
+        "ts_submitted_raw",
+        "ts_filled_raw",
+        "end_time_raw",
+    }
+
+
+def test_load_config_reads_toml_and_yaml_into_the_same_interval_specs(tmp_path: Path) -> None:
+    (tmp_path / "pnl.toml").write_text(
+        '[[intervals]]\nkey = "day"\ndelta_seconds = 86400\n\n'
+        '[[intervals]]\nkey = "open"\nlabel = "Open position"\nkind = "since_last_flat"\n\n'
+        '[fees]\nmaker_fee_rate = "0.001"\n'
+    )
+    (tmp_path / "pnl.yaml").write_text(
+        "intervals:\n  - key: day\n    delta_seconds: 86400\n"
+        "  - key: open\n    label: Open position\n    kind: since_last_flat\n"
+        "fees:\n  maker_fee_rate: '0.001'\n"
+    )
+    toml_config = pnl_native.load_config(tmp_path / "pnl.toml")
+    yaml_config = pnl_native.load_config(tmp_path / "pnl.yaml")
+    assert toml_config == yaml_config
+    assert toml_config["intervals"][0]["label"] == "day"
+    assert toml_config["intervals"][1]["kind"] == "since_last_flat"
+
+    summary = _summarise([_trade(BASE_US, "BUY", "100", "1")], intervals=toml_config["intervals"])
+    assert summary is not None
+
+    (tmp_path / "bad.json").write_text('{"intervals": [{"key": "forever"}]}')
+    with pytest.raises(ValueError, match="needs delta_seconds or anchor_timestamp_us"):
+        pnl_native.load_config(tmp_path / "bad.json")
+    (tmp_path / "kind.json").write_text('{"intervals": [{"key": "x", "delta_seconds": 1, "kind": "weekly"}]}')
+    with pytest.raises(ValueError, match="unknown interval kind: weekly"):
+        pnl_native.load_config(tmp_path / "kind.json")
+
+
+def test_trade_limit_is_checked_before_trades_are_extracted() -> None:
+    pnl_native.set_input_limits(max_trades=2)
+    try:
+        with pytest.raises(ValueError, match="trades payload has 3 records, above the limit of 2"):
+            _summarise([None, None, None])
+        assert _summarise([_trade(BASE_US, "BUY", "100", "1")]) is not None
+    finally:
+        pnl_native.set_input_limits()
+
+
+def test_memory_debug_is_unavailable_without_the_cargo_feature() -> None:
+    assert pnl_native.set_memory_debug(True) is False
+    assert "native_memory" not in _summarise([_trade(BASE_US, "BUY", "100", "1")])
+
+
+def test_async_wrappers_match_the_sync_calls() -> None:
+    trades = [_trade(BASE_US, "BUY", "100", "1"), _trade(BASE_US + HOUR_US, "SELL", "110", "1")]
+    call = pnl_native.summarise_trades_async(
+        trades,
+        ALL_TIME,
+        now_timestamp_us=BASE_US + 10 * DAY_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0.001",
+        taker_fee_rate="0.002",
+    )
+    result = asyncio.run(call)
+    assert result == _summarise(trades)
+
+
+def test_cancel_token_stops_the_replay_and_plain_calls_run_inline() -> None:
+    trades = [_trade(BASE_US + index, "BUY" if index % 2 else "SELL", "100", "1") for index in range(3000)]
+    token = pnl_native.CancelToken()
+    token.cancel()
+    with pytest.raises(pnl_native.Cancelled):
+        _summarise(trades, cancel_token=token, oversell="allow_short")
+
+    token.reset()
+    seen: list[tuple[str, int, int]] = []
+    watched = _summarise(trades, cancel_token=token, oversell="allow_short", progress=lambda *args: seen.append(args))
+    assert watched == _summarise(trades, oversell="allow_short")
+    assert seen and seen[-1][1] == seen[-1][2]
+
+
+def test_progress_stays_within_the_stage_total_across_accounts() -> None:
+    accounts = {
+        name: [_trade(BASE_US + index, "BUY" if index % 2 else "SELL", "100", "1") for index in range(count)]
+        for name, count in (("a", 2500), ("b", 1030), ("c", 7))
+    }
+    seen: list[tuple[str, int, int]] = []
+    result = pnl_native.summarise_accounts(
+        accounts,
+        ALL_TIME,
+        now_timestamp_us=BASE_US + 10 * DAY_US,
+        cutoff_timestamp_us=0,
+        maker_fee_rate="0.001",
+        taker_fee_rate="0.002",
+        progress=lambda *args: seen.append(args),
+        progress_every=10,
+    )
+    assert result is not None
+    assert seen[-1] == ("replay", 3537, 3537)
+    counts = [processed for _, processed, _ in seen]
+    assert counts == sorted(counts) and all(processed <= 3537 for processed in counts)
+
+
+def test_fee_bps_split_by_liquidity_side_and_withheld_for_mixed_units() -> None:
+    trades = [
+        _trade(BASE_US, "BUY", "100", "1", post_only=True, product_id="BTC-USD"),
+        _trade(BASE_US + HOUR_US, "SELL", "100", "1", product_id="BTC-USD", commission="0"),
+    ]
+    interval = _summarise(trades)["intervals"][0]
+    assert (interval["maker_fee_bps"], interval["taker_fee_bps"], interval["effective_fee_bps"]) == ("10", "0", "5")
+
+    mixed = trades + [_trade(BASE_US + 2 * HOUR_US, "BUY", "50000", "100", product_id="BTC-USD-INV")]
+    interval = _summarise(mixed, inverse_products=["BTC-USD-INV"], mixed_products="allow")["intervals"][0]
+    assert interval["effective_fee_bps"] is None
+    assert interval["maker_fee_bps"] is None and interval["taker_fee_bps"] is None
+
+
+def test_since_last_flat_starts_at_the_trade_that_opened_the_position() -> None:
+    trades = [
+        _trade(BASE_US, "BUY", "100", "1"),
+        _trade(BASE_US + HOUR_US, "SELL", "110", "1"),
+        _trade(BASE_US + 2 * HOUR_US, "BUY", "100", "2"),
+        _trade(BASE_US + 3 * HOUR_US, "SELL", "120", "1"),
+    ]
+    intervals = [
+        {"key": "open", "label": "Open", "kind": "since_last_flat", "period_seconds": 86_400, "anchor_timestamp_us": 0},
+        {"key": "all", "label": "All", "delta_seconds": None},
+    ]
+    result = _summarise(trades, intervals=intervals)
+    since_flat, everything = result["intervals"]
+    assert since_flat["profit_before_fees"] == "20"
+    assert everything["profit_before_fees"] == "30"
+    assert since_flat["buy_count"] == 1 and since_flat["sell_count"] == 1
+
+    flat = _summarise(trades[:2], intervals=intervals)["intervals"][0]
+    assert flat["buy_count"] == 0 and flat["profit_before_fees"] == "0"
+
+
+def test_turnover_is_withheld_when_inverse_and_linear_units_meet() -> None:
+    linear = [
+        _trade(BASE_US, "BUY", "100", "1", product_id="BTC-USD"),
+        _trade(BASE_US + HOUR_US, "SELL", "100", "0.5", product_id="BTC-USD"),
+    ]
+    assert _summarise(linear)["intervals"][0]["turnover"] is not None
+
+    inverse = [_trade(BASE_US + DAY_US, "BUY", "50000", "100", product_id="BTC-USD-INV")]
+    intervals = ALL_TIME + [{"key": "hour", "label": "Hour", "delta_seconds": 3600}]
+    result = _summarise(
+        linear + inverse,
+        intervals=intervals,
+        now_timestamp_us=BASE_US + DAY_US + HOUR_US // 2,
+        inverse_products=["BTC-USD-INV"],
+        mixed_products="allow",
+    )
+    all_time, last_hour = result["intervals"]
+    assert all_time["turnover"] is None
+    assert last_hour["turnover"] is None
+
+
+def test_fifo_and_lifo_close_different_lots() -> None:
+    trades = [
+        _trade(BASE_US, "BUY", "100", "1"),
+        _trade(BASE_US + HOUR_US, "BUY", "110", "3"),
+        _trade(BASE_US + 3 * HOUR_US, "SELL", "120", "2"),
+    ]
+    fifo = _summarise(trades, lot_method="fifo")
+    lifo = _summarise(trades, lot_method="lifo")
+    assert fifo["intervals"][0]["profit_before_fees"] == "30"
+    assert lifo["intervals"][0]["profit_before_fees"] == "20"
+    assert fifo["intervals"][0]["avg_holding_us"] == fifo["intervals"][0]["median_holding_us"] == 5 * HOUR_US // 2
+    assert lifo["intervals"][0]["avg_holding_us"] == lifo["intervals"][0]["median_holding_us"] == 2 * HOUR_US
+    assert fifo["open_position"]["average_entry_price"] == "110"
+    assert lifo["open_position"]["average_entry_price"] == "105"
+
+
+def test_holding_times_are_weighted_by_the_size_closed() -> None:
+    trades = [
+        _trade(BASE_US, "BUY", "100", "1"),
+        _trade(BASE_US + HOUR_US, "BUY", "100", "3"),
+        _trade(BASE_US + 4 * HOUR_US, "SELL", "100", "4"),
+    ]
+    interval = _summarise(trades)["intervals"][0]
+    assert interval["avg_holding_us"] == 13 * HOUR_US // 4
+    assert interval["median_holding_us"] == 3 * HOUR_US
+
+
+def test_quote_currency_is_emitted_once_and_inverse_records_use_the_base() -> None:
+    trades = [_trade(BASE_US, "BUY", "50000", "100", product_id="BTC-USD-INV")]
+    summary = _summarise(trades, inverse_products=["BTC-USD-INV"])
+    assert summary["quote_currency"] == "BTC"
+    assert "quote_currency" not in summary["intervals"][0]
+
+    orders = [_order("a", product_id="BTC-USD-INV"), _order("b", product_id="ETH-USDC")]
+    fills = [_fill("a"), _fill("b")]
+    records = _process(orders, fills, inverse_products=["BTC-USD-INV"])["executed_records"]
+    assert {record["order_id"]: record["quote_currency"] for record in records} == {"a": "BTC", "b": "USDC"}
+
+
+def test_fee_schedule_changes_apply_from_their_timestamp_inclusive() -> None:
+    schedule = [{"effective_from_timestamp_us": BASE_US + HOUR_US, "maker_fee_rate": "0", "taker_fee_rate": "0.01"}]
+    trades = [
+        _trade(BASE_US + HOUR_US - 1, "BUY", "100", "1"),
+        _trade(BASE_US + HOUR_US, "BUY", "100", "1"),
+        _trade(BASE_US + HOUR_US + 1, "BUY", "100", "1", post_only=True),
+    ]
+    assert _summarise(trades[:1], fee_schedule=schedule)["intervals"][0]["fee_total"] == "0.200"
+    assert _summarise(trades[1:2], fee_schedule=schedule)["intervals"][0]["fee_total"] == "1.00"
+    assert Decimal(_summarise(trades, fee_schedule=schedule)["intervals"][0]["fee_total"]) == Decimal("1.2")
+
+
+def test_min_and_max_fee_bound_each_fill_and_carry_into_the_open_position() -> None:
+    small = [_trade(BASE_US, "BUY", "100", "2"), _trade(BASE_US + HOUR_US, "SELL", "100", "1")]
+    result = _summarise(small, min_fee="0.5")
+    assert Decimal(result["intervals"][0]["fee_total"]) == Decimal("1")
+    assert Decimal(result["open_position"]["accrued_fees"]) == Decimal("0.25")
+
+    large = [_trade(BASE_US, "BUY", "100", "1000")]
+    result = _summarise(large, max_fee="2")
+    assert Decimal(result["intervals"][0]["fee_total"]) == Decimal("2")
+    assert Decimal(result["open_position"]["accrued_fees"]) == Decimal("2")
+
+    with pytest.raises(ValueError, match="min_fee 3 exceeds max_fee 2"):
+        _summarise(large, min_fee="3", max_fee="2")
+
+
+def test_fee_scenarios_price_trades_with_commissions_at_the_scenario_rate() -> None:
+    trades = [
+        _trade(BASE_US, "BUY", "100", "10", commission="2"),
+        _trade(BASE_US + HOUR_US, "BUY", "90", "10", commission="1.8"),
+        _trade(BASE_US + 2 * HOUR_US, "SELL", "110", "10", commission="2.2"),
+    ]
+    scenarios = [
+        {"key": "vip", "maker_fee_rate": "0", "taker_fee_rate": "0.0005"},
+        {"key": "lifo", "lot_method": "lifo"},
+    ]
+    result = _summarise(trades, scenarios=scenarios)
+    assert Decimal(result["intervals"][0]["fee_total"]) == Decimal("6")
+    vip, lifo = result["scenarios"]
+    assert Decimal(vip["intervals"][0]["fee_total"]) == Decimal("1.5")
+    assert Decimal(vip["intervals"][0]["fee_savings"]) == Decimal("4.5")
+    assert Decimal(vip["intervals"][0]["profit_after_fees_delta"]) == Decimal("4.5")
+    assert Decimal(vip["total_fee_savings"]) == Decimal("4.5")
+
+    assert Decimal(lifo["intervals"][0]["fee_total"]) == Decimal("6")
+    assert Decimal(lifo["intervals"][0]["fee_savings"]) == 0
+    assert Decimal(lifo["total_profit_after_fees_delta"]) == Decimal("100")
+    assert lifo["open_position"]["average_entry_price"] == "100"
+
+
+def _bootstrap(trades: list[dict[str, Any]], **kwargs: Any) -> dict[str, Any]:
+    kwargs.setdefault("maker_fee_rate", "0.001")
+    kwargs.setdefault("taker_fee_rate", "0.002")
+    kwargs.setdefault("horizon_days", 5)
+    kwargs.setdefault("paths", 500)
+    return pnl_native.bootstrap_forward_pnl(trades, **kwargs)
+
+
+def test_bootstrap_repeats_per_seed_and_validates_its_inputs() -> None:
+    trades = [
+        _trade(BASE_US + index * HOUR_US, "BUY" if index % 2 else "SELL", str(100 + index % 7), "1")
+        for index in range(24)
+    ]
+    first = _bootstrap(trades, seed=7, ruin_loss="20")
+    assert _bootstrap(trades, seed=7, ruin_loss="20") == first
+    assert _bootstrap(trades, seed=8, ruin_loss="20")["bands"] != first["bands"]
+    assert first["bands"][-1]["trade_count"] == 120
+
+    with pytest.raises(ValueError, match="ruin_loss must be positive"):
+        _bootstrap(trades, ruin_loss="0")
+    with pytest.raises(ValueError, match="paths x horizon_days"):
+        _bootstrap(trades, paths=5_000_000, horizon_days=3)
+    dense = [_trade(BASE_US + index, "BUY" if index % 2 else "SELL", "100", "1") for index in range(2000)]
+    with pytest.raises(ValueError, match="paths x trades per day x horizon_days"):
+        _bootstrap(dense, paths=1_000_000)
+    with pytest.raises(ValueError, match="several products"):
+        _bootstrap([_eth(BASE_US, "BUY", "100", "1"), _trade(BASE_US + 1, "SELL", "100", "1", product_id="BTC-USD")])
+
+    token = pnl_native.CancelToken()
+    token.cancel()
+    with pytest.raises(pnl_native.Cancelled):
+        _bootstrap(trades, cancel_token=token)